@@ -0,0 +1,162 @@
+//! Benchmarks for the received messages store, run with `cargo bench`
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mavlink::ardupilotmega::{MavMessage, ATTITUDE_DATA};
+use mavlink::{MavHeader, Message};
+
+#[allow(dead_code)]
+#[path = "../src/data.rs"]
+mod data;
+
+const READERS: usize = 4;
+
+const HEADER: MavHeader = MavHeader {
+    system_id: 1,
+    component_id: 1,
+    sequence: 0,
+};
+
+// Mimics the previous store, that cloned and converted everything to look for a single field
+#[derive(Default)]
+struct LegacyData(Mutex<HashMap<String, MavMessage>>);
+
+impl LegacyData {
+    fn update(&self, message: &MavMessage) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(message.message_name().to_string(), message.clone());
+    }
+
+    fn pointer(&self, path: &str) -> String {
+        let messages = self.0.lock().unwrap().clone();
+        let value = serde_json::to_value(messages).unwrap();
+        match value.pointer(path) {
+            Some(content) => serde_json::to_string_pretty(content).unwrap(),
+            None => "None".into(),
+        }
+    }
+}
+
+fn attitude() -> MavMessage {
+    MavMessage::ATTITUDE(ATTITUDE_DATA {
+        roll: 0.1,
+        pitch: 0.2,
+        yaw: 0.3,
+        ..Default::default()
+    })
+}
+
+// Fill the stores with one of each message available in the dialect
+fn populate(legacy: &LegacyData) {
+    for id in 0..60000 {
+        if let Some(message) = MavMessage::default_message_from_id(id) {
+            legacy.update(&message);
+            data::update((HEADER, message), data::Reception::new("bench"));
+        }
+    }
+}
+
+fn with_readers<R: Fn() + Send + Sync + 'static>(reader: R, bench: impl FnOnce()) {
+    let running = Arc::new(AtomicBool::new(true));
+    let reader = Arc::new(reader);
+    let threads: Vec<_> = (0..READERS)
+        .map(|_| {
+            let running = running.clone();
+            let reader = reader.clone();
+            std::thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    reader();
+                }
+            })
+        })
+        .collect();
+
+    bench();
+
+    running.store(false, Ordering::Relaxed);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+fn pointer(c: &mut Criterion) {
+    let legacy = LegacyData::default();
+    populate(&legacy);
+
+    let mut group = c.benchmark_group("pointer");
+    group.bench_function("field", |b| {
+        b.iter(|| {
+            data::pointer(
+                black_box("vehicles/1/components/1/messages/ATTITUDE/message/roll"),
+                None,
+            )
+        })
+    });
+    group.bench_function("legacy_field", |b| {
+        b.iter(|| legacy.pointer(black_box("/ATTITUDE/roll")))
+    });
+    group.bench_function("message", |b| {
+        b.iter(|| data::pointer(black_box("vehicles/1/components/1/messages/ATTITUDE"), None))
+    });
+    group.bench_function("legacy_message", |b| {
+        b.iter(|| legacy.pointer(black_box("/ATTITUDE")))
+    });
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let legacy = Arc::new(LegacyData::default());
+    populate(&legacy);
+    let message = attitude();
+
+    let mut group = c.benchmark_group("update");
+    group.bench_function("idle", |b| {
+        b.iter(|| {
+            data::update(
+                black_box((HEADER, message.clone())),
+                data::Reception::new("bench"),
+            )
+        })
+    });
+    group.bench_function("legacy_idle", |b| {
+        b.iter(|| legacy.update(black_box(&message)))
+    });
+
+    // Receive loop performance while HTTP clients are polling the whole tree
+    with_readers(
+        || {
+            data::pointer("", None);
+        },
+        || {
+            group.bench_function("with_readers", |b| {
+                b.iter(|| {
+                    data::update(
+                        black_box((HEADER, message.clone())),
+                        data::Reception::new("bench"),
+                    )
+                })
+            });
+        },
+    );
+
+    let reader_legacy = legacy.clone();
+    with_readers(
+        move || {
+            reader_legacy.pointer("");
+        },
+        || {
+            group.bench_function("legacy_with_readers", |b| {
+                b.iter(|| legacy.update(black_box(&message)))
+            });
+        },
+    );
+    group.finish();
+}
+
+criterion_group!(benches, pointer, update);
+criterion_main!(benches);
@@ -1,4 +1,9 @@
-use std::{fs::File, io, path::Path};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use vergen::{vergen, Config};
 
@@ -16,13 +21,74 @@ fn main() {
     ] {
         download_file(remote_file, &artifacts_dir);
     }
+
+    // MAVLink definitions are used to provide descriptions and units of the messages,
+    // the ones shipped with the mavlink crate match its generated messages and enums
+    let definitions_dir = Path::new(&std::env::var("OUT_DIR").unwrap()).join("definitions");
+    std::fs::create_dir_all(&definitions_dir).expect("failed to create a dir");
+
+    // Builds without the mavlink crate sources still work, without the decoded names and units
+    println!("cargo:rerun-if-env-changed=MAVLINK_DEFINITIONS_DIR");
+    let source_dir = mavlink_definitions_dir();
+    if source_dir.is_none() {
+        println!("cargo:warning=MAVLink definitions not found, set MAVLINK_DEFINITIONS_DIR to the message_definitions/v1.0 directory of the mavlink crate");
+    }
+    for definition in [
+        "minimal.xml",
+        "standard.xml",
+        "common.xml",
+        "uAvionix.xml",
+        "icarous.xml",
+        "cubepilot.xml",
+        "csAirLink.xml",
+        "ardupilotmega.xml",
+    ] {
+        let destination = definitions_dir.join(definition);
+        match &source_dir {
+            Some(source_dir) => {
+                let source = source_dir.join(definition);
+                println!("cargo:rerun-if-changed={}", source.display());
+                std::fs::copy(&source, &destination)
+                    .unwrap_or_else(|_| panic!("Failed to copy definition file: {:?}", source));
+            }
+            None => std::fs::write(&destination, "")
+                .unwrap_or_else(|_| panic!("Failed to create definition file: {:?}", destination)),
+        }
+    }
+}
+
+fn mavlink_definitions_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("MAVLINK_DEFINITIONS_DIR") {
+        let dir = PathBuf::from(dir);
+        return dir.is_dir().then_some(dir);
+    }
+
+    // Cargo knows where the sources of the mavlink crate are, registry or vendored
+    let output = Command::new(std::env::var("CARGO").ok()?)
+        .args(["metadata", "--format-version", "1", "--offline"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .ok()?;
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+
+    let manifest_path = metadata["packages"]
+        .as_array()?
+        .iter()
+        .find(|package| package["name"] == "mavlink")?["manifest_path"]
+        .as_str()?;
+
+    let dir = Path::new(manifest_path)
+        .parent()?
+        .join("mavlink/message_definitions/v1.0");
+    dir.is_dir().then_some(dir)
 }
 
 fn download_file(remote_file: &str, dir: &Path) {
     let mut resp = reqwest::blocking::get(remote_file)
-        .unwrap_or_else(|_| panic!("Failed to download vue file: {}", remote_file));
+        .and_then(|resp| resp.error_for_status())
+        .unwrap_or_else(|error| panic!("Failed to download file {}: {}", remote_file, error));
 
-    let filename = remote_file.split('/').last().unwrap();
+    let filename = remote_file.split('/').next_back().unwrap();
     let file_path = dir.join(filename);
     let mut output_file = File::create(&file_path)
         .unwrap_or_else(|_| panic!("Failed to create artifact file: {:?}", file_path));
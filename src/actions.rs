@@ -0,0 +1,385 @@
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavCmd, MavFrame, MavMessage};
+
+use crate::command::{self, Command, CommandError, CommandResult};
+use crate::data;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::modes::{self, Firmware, SetModeError};
+
+// Time to wait for the final answer of each command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+// Time to wait for the mode change that some actions need
+const MODE_TIMEOUT: Duration = Duration::from_secs(5);
+// Magic value of MAV_CMD_COMPONENT_ARM_DISARM param2 that skips the safety checks
+const FORCE: f32 = 21196.0;
+
+#[derive(Debug)]
+pub enum ActionError {
+    /// No heartbeat from an autopilot with known firmware
+    UnknownVehicle,
+    /// Action does not exist for the firmware, contains the firmware
+    Unsupported(Firmware),
+    /// Position of the vehicle is needed and unknown
+    UnknownPosition,
+    /// Latitude and longitude out of range
+    InvalidPosition(f64, f64),
+    Mode(SetModeError),
+    Command(CommandError),
+}
+
+impl std::fmt::Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownVehicle => write!(f, "Vehicle not found or its firmware is unknown."),
+            Self::Unsupported(firmware) => write!(f, "Action is not available for {firmware:?}."),
+            Self::UnknownPosition => write!(f, "Position of the vehicle is unknown."),
+            Self::InvalidPosition(latitude, longitude) => write!(
+                f,
+                "Invalid position, latitude {latitude} should be in -90..90 and longitude {longitude} in -180..180."
+            ),
+            Self::Mode(error) => write!(f, "{error}"),
+            Self::Command(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl ActionError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Self::Mode(SetModeError::Timeout) | Self::Command(CommandError::Timeout(_))
+        )
+    }
+}
+
+fn autopilot(system_id: u8) -> Result<(u8, Firmware), ActionError> {
+    let autopilot = data::autopilot_heartbeat(system_id).ok_or(ActionError::UnknownVehicle)?;
+    let firmware = match &autopilot.message {
+        MavMessage::HEARTBEAT(heartbeat) => Firmware::from_heartbeat(heartbeat),
+        _ => None,
+    }
+    .ok_or(ActionError::UnknownVehicle)?;
+    Ok((autopilot.header.component_id, firmware))
+}
+
+// Altitude of home above mean sea level, PX4 uses it instead of relative altitudes
+fn home_altitude(system_id: u8, component_id: u8) -> Result<f32, ActionError> {
+    data::messages_with_time("GLOBAL_POSITION_INT")
+        .into_iter()
+        .find_map(|(message, _, _)| match message.message {
+            MavMessage::GLOBAL_POSITION_INT(position)
+                if message.header.system_id == system_id
+                    && message.header.component_id == component_id =>
+            {
+                Some((position.alt - position.relative_alt) as f32 / 1000.0)
+            }
+            _ => None,
+        })
+        .ok_or(ActionError::UnknownPosition)
+}
+
+fn send(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    command: &Command,
+) -> Result<CommandResult, ActionError> {
+    command::send(vehicle, system_id, component_id, command, COMMAND_TIMEOUT)
+        .map_err(ActionError::Command)
+}
+
+/// Arms or disarms the vehicle, force skips the safety checks
+pub fn arm(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    arm: bool,
+    force: bool,
+) -> Result<CommandResult, ActionError> {
+    let (component_id, _) = autopilot(system_id)?;
+    send(vehicle, system_id, component_id, &arm_command(arm, force))
+}
+
+fn arm_command(arm: bool, force: bool) -> Command {
+    Command::long(
+        MavCmd::MAV_CMD_COMPONENT_ARM_DISARM,
+        [
+            arm as u8 as f32,
+            if force { FORCE } else { 0.0 },
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+        ],
+    )
+}
+
+/// Takes off to an altitude relative to home in meters, the vehicle should be armed.
+/// ArduCopter is changed to GUIDED first.
+pub fn takeoff(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    altitude: f32,
+) -> Result<CommandResult, ActionError> {
+    let (component_id, firmware) = autopilot(system_id)?;
+    let command = takeoff_command(firmware, altitude, || {
+        home_altitude(system_id, component_id)
+    })?;
+    if firmware == Firmware::ArduCopter {
+        modes::set_mode(vehicle, system_id, "GUIDED", MODE_TIMEOUT).map_err(ActionError::Mode)?;
+    }
+    send(vehicle, system_id, component_id, &command)
+}
+
+fn takeoff_command(
+    firmware: Firmware,
+    altitude: f32,
+    home_altitude: impl FnOnce() -> Result<f32, ActionError>,
+) -> Result<Command, ActionError> {
+    let altitude = match firmware {
+        Firmware::ArduCopter | Firmware::ArduPlane => altitude,
+        Firmware::PX4 => home_altitude()? + altitude,
+        Firmware::ArduRover | Firmware::ArduSub => return Err(ActionError::Unsupported(firmware)),
+    };
+
+    // NaN keeps the current position and yaw, also for landings
+    Ok(Command::long(
+        MavCmd::MAV_CMD_NAV_TAKEOFF,
+        [0.0, 0.0, 0.0, f32::NAN, f32::NAN, f32::NAN, altitude],
+    ))
+}
+
+/// Lands at the current position, ArduPlane starts the landing sequence of its mission
+pub fn land(vehicle: &MAVLinkVehicleArcMutex, system_id: u8) -> Result<CommandResult, ActionError> {
+    let (component_id, firmware) = autopilot(system_id)?;
+    send(vehicle, system_id, component_id, &land_command(firmware)?)
+}
+
+fn land_command(firmware: Firmware) -> Result<Command, ActionError> {
+    match firmware {
+        Firmware::ArduCopter | Firmware::PX4 => Ok(Command::long(
+            MavCmd::MAV_CMD_NAV_LAND,
+            [0.0, 0.0, 0.0, f32::NAN, f32::NAN, f32::NAN, f32::NAN],
+        )),
+        Firmware::ArduPlane => Ok(Command::long(MavCmd::MAV_CMD_DO_LAND_START, [0.0; 7])),
+        Firmware::ArduRover | Firmware::ArduSub => Err(ActionError::Unsupported(firmware)),
+    }
+}
+
+/// Returns to the launch position
+pub fn return_to_launch(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+) -> Result<CommandResult, ActionError> {
+    let (component_id, firmware) = autopilot(system_id)?;
+    if firmware == Firmware::ArduSub {
+        return Err(ActionError::Unsupported(firmware));
+    }
+    let command = Command::long(MavCmd::MAV_CMD_NAV_RETURN_TO_LAUNCH, [0.0; 7]);
+    send(vehicle, system_id, component_id, &command)
+}
+
+/// Goes to a position, the altitude is relative to home in meters.
+/// The vehicle changes to its guided mode, the speed in m/s is optional.
+pub fn goto(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    latitude: f64,
+    longitude: f64,
+    altitude: f32,
+    speed: Option<f32>,
+) -> Result<CommandResult, ActionError> {
+    check_position(latitude, longitude)?;
+    let (component_id, firmware) = autopilot(system_id)?;
+    let command = goto_command(firmware, latitude, longitude, altitude, speed, || {
+        home_altitude(system_id, component_id)
+    })?;
+    send(vehicle, system_id, component_id, &command)
+}
+
+fn check_position(latitude: f64, longitude: f64) -> Result<(), ActionError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(ActionError::InvalidPosition(latitude, longitude));
+    }
+    Ok(())
+}
+
+fn goto_command(
+    firmware: Firmware,
+    latitude: f64,
+    longitude: f64,
+    altitude: f32,
+    speed: Option<f32>,
+    home_altitude: impl FnOnce() -> Result<f32, ActionError>,
+) -> Result<Command, ActionError> {
+    let (frame, altitude) = match firmware {
+        Firmware::PX4 => (MavFrame::MAV_FRAME_GLOBAL, home_altitude()? + altitude),
+        Firmware::ArduCopter | Firmware::ArduPlane | Firmware::ArduRover => {
+            (MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT, altitude)
+        }
+        Firmware::ArduSub => return Err(ActionError::Unsupported(firmware)),
+    };
+
+    Ok(Command::global(
+        MavCmd::MAV_CMD_DO_REPOSITION,
+        frame,
+        [
+            speed.unwrap_or(-1.0),
+            1.0, // MAV_DO_REPOSITION_FLAGS_CHANGE_MODE
+            0.0,
+            f32::NAN,
+        ],
+        latitude,
+        longitude,
+        altitude,
+    ))
+}
+
+/// Changes the speed in m/s, the airspeed by default for planes and the ground speed for others
+pub fn change_speed(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    speed: f32,
+    airspeed: Option<bool>,
+) -> Result<CommandResult, ActionError> {
+    let (component_id, firmware) = autopilot(system_id)?;
+    if firmware == Firmware::ArduSub {
+        return Err(ActionError::Unsupported(firmware));
+    }
+    let airspeed = airspeed.unwrap_or(firmware == Firmware::ArduPlane);
+
+    // Speed type 0 is airspeed and 1 ground speed, -1 keeps the throttle
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_CHANGE_SPEED,
+        [
+            if airspeed { 0.0 } else { 1.0 },
+            speed,
+            -1.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+        ],
+    );
+    send(vehicle, system_id, component_id, &command)
+}
+
+/// Reboots the autopilot
+pub fn reboot(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+) -> Result<CommandResult, ActionError> {
+    let (component_id, _) = autopilot(system_id)?;
+    let command = Command::long(
+        MavCmd::MAV_CMD_PREFLIGHT_REBOOT_SHUTDOWN,
+        [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    );
+    send(vehicle, system_id, component_id, &command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Params;
+    use crate::data::Reception;
+    use mavlink::ardupilotmega::GLOBAL_POSITION_INT_DATA;
+
+    fn long_params(command: &Command) -> [f32; 7] {
+        match command.params {
+            Params::Long(params) => params,
+            Params::Int { .. } => panic!("Expected a COMMAND_LONG: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn arm_commands() {
+        let command = arm_command(true, false);
+        assert_eq!(command.command, MavCmd::MAV_CMD_COMPONENT_ARM_DISARM);
+        assert_eq!(long_params(&command)[..2], [1.0, 0.0]);
+        assert_eq!(long_params(&arm_command(false, true))[..2], [0.0, FORCE]);
+    }
+
+    #[test]
+    fn takeoff_altitudes() {
+        let no_home = || -> Result<f32, ActionError> { panic!("Home altitude is not needed") };
+        for firmware in [Firmware::ArduCopter, Firmware::ArduPlane] {
+            let command = takeoff_command(firmware, 10.0, no_home).unwrap();
+            assert_eq!(command.command, MavCmd::MAV_CMD_NAV_TAKEOFF);
+            assert_eq!(long_params(&command)[6], 10.0);
+        }
+
+        // PX4 takes the altitude above mean sea level
+        let command = takeoff_command(Firmware::PX4, 10.0, || Ok(584.5)).unwrap();
+        assert_eq!(long_params(&command)[6], 594.5);
+        assert!(matches!(
+            takeoff_command(Firmware::PX4, 10.0, || Err(ActionError::UnknownPosition)),
+            Err(ActionError::UnknownPosition)
+        ));
+        assert!(matches!(
+            takeoff_command(Firmware::ArduSub, 10.0, no_home),
+            Err(ActionError::Unsupported(Firmware::ArduSub))
+        ));
+    }
+
+    #[test]
+    fn firmware_commands() {
+        let land = |firmware| land_command(firmware).map(|command| command.command);
+        assert_eq!(
+            land(Firmware::ArduCopter).unwrap(),
+            MavCmd::MAV_CMD_NAV_LAND
+        );
+        assert_eq!(land(Firmware::PX4).unwrap(), MavCmd::MAV_CMD_NAV_LAND);
+        assert_eq!(
+            land(Firmware::ArduPlane).unwrap(),
+            MavCmd::MAV_CMD_DO_LAND_START
+        );
+        assert!(land(Firmware::ArduRover).is_err());
+
+        let goto = |firmware| goto_command(firmware, -27.5, -48.5, 20.0, None, || Ok(100.0));
+        match goto(Firmware::ArduRover).unwrap().params {
+            Params::Int { frame, x, y, z, .. } => {
+                assert_eq!(frame, MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT);
+                assert_eq!((x, y, z), (-275_000_000, -485_000_000, 20.0));
+            }
+            params => panic!("Expected a COMMAND_INT: {:?}", params),
+        }
+        match goto(Firmware::PX4).unwrap().params {
+            Params::Int { frame, z, .. } => {
+                assert_eq!(frame, MavFrame::MAV_FRAME_GLOBAL);
+                assert_eq!(z, 120.0);
+            }
+            params => panic!("Expected a COMMAND_INT: {:?}", params),
+        }
+        assert!(goto(Firmware::ArduSub).is_err());
+    }
+
+    #[test]
+    fn home_altitude_above_sea_level() {
+        let header = mavlink::MavHeader {
+            system_id: 202,
+            component_id: 1,
+            sequence: 0,
+        };
+        let position = MavMessage::GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+            alt: 600_000,
+            relative_alt: 15_500,
+            ..Default::default()
+        });
+        assert!(home_altitude(202, 1).is_err());
+        data::update((header, position), Reception::new("test"));
+        assert_eq!(home_altitude(202, 1).unwrap(), 584.5);
+    }
+
+    #[test]
+    fn position_range() {
+        assert!(check_position(-27.5, -48.5).is_ok());
+        assert!(check_position(90.0, 180.0).is_ok());
+        for (latitude, longitude) in [(91.0, 0.0), (0.0, -180.5), (f64::NAN, 0.0)] {
+            assert!(matches!(
+                check_position(latitude, longitude),
+                Err(ActionError::InvalidPosition(..))
+            ));
+        }
+    }
+}
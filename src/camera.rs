@@ -0,0 +1,453 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::{MavCmd, MavMessage, COMMAND_LONG_DATA};
+use mavlink::Message;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::command::{self, Command, CommandError, CommandResult};
+use crate::data::{self, MAVLinkMessage, Reception};
+use crate::inventory;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{self, Matcher, TransactionError};
+use crate::websocket_manager;
+
+// Maximum number of captured images kept per camera
+const IMAGES_SIZE: usize = 1000;
+// Time to wait for the answer of each request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Messages of the camera protocol, requested with MAV_CMD_REQUEST_MESSAGE
+const CAMERA_MESSAGES: [&str; 4] = [
+    "CAMERA_INFORMATION",
+    "CAMERA_SETTINGS",
+    "STORAGE_INFORMATION",
+    "CAMERA_CAPTURE_STATUS",
+];
+
+// Fields with char arrays that are shown as strings
+const TEXT_FIELDS: [&str; 5] = [
+    "vendor_name",
+    "model_name",
+    "cam_definition_uri",
+    "name",
+    "file_url",
+];
+
+/// MAVLink camera component with its latest information
+#[derive(Clone, Debug, Serialize)]
+pub struct Camera {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub online: bool,
+    /// Latest CAMERA_INFORMATION, E.g: vendor, model and capabilities
+    pub information: Option<Value>,
+    /// Latest CAMERA_SETTINGS, E.g: mode, zoom and focus
+    pub settings: Option<Value>,
+    /// Latest STORAGE_INFORMATION
+    pub storage: Option<Value>,
+    /// Latest CAMERA_CAPTURE_STATUS, E.g: recording time and image count
+    pub capture_status: Option<Value>,
+}
+
+/// Image reported by CAMERA_IMAGE_CAPTURED
+#[derive(Clone, Debug, Serialize)]
+pub struct Image {
+    /// UTC time when the message was received
+    pub time: DateTime<Utc>,
+    /// UTC time of the capture reported by the camera, if it has a valid clock
+    pub time_utc: Option<DateTime<Utc>>,
+    pub system_id: u8,
+    pub component_id: u8,
+    pub image_index: i32,
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Longitude in degrees
+    pub longitude: f64,
+    /// Altitude above mean sea level in meters
+    pub altitude: f64,
+    /// Altitude above home in meters
+    pub relative_altitude: f64,
+    /// Camera orientation as roll, pitch and yaw in degrees
+    pub attitude: [f64; 3],
+    pub success: bool,
+    pub file_url: String,
+}
+
+#[derive(Debug)]
+pub enum CameraError {
+    UnknownMode(String),
+    Command(CommandError),
+    Transaction(TransactionError),
+}
+
+impl std::fmt::Display for CameraError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownMode(mode) => {
+                write!(f, "Unknown mode {mode}, use IMAGE, VIDEO or IMAGE_SURVEY")
+            }
+            Self::Command(error) => write!(f, "{error}"),
+            Self::Transaction(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl CameraError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Self::Command(CommandError::Timeout(_)) | Self::Transaction(TransactionError::Timeout)
+        )
+    }
+}
+
+lazy_static! {
+    static ref IMAGES: Mutex<HashMap<(u8, u8), VecDeque<Image>>> = Mutex::new(HashMap::new());
+    // Sequence number of the last single photo request of each camera
+    static ref SEQUENCES: Mutex<HashMap<(u8, u8), u32>> = Mutex::new(HashMap::new());
+}
+
+fn text(bytes: &[u8]) -> String {
+    let length = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..length]).to_string()
+}
+
+// Message content with char arrays as strings
+fn readable(message: &MavMessage) -> Value {
+    let mut content = serde_json::to_value(message).unwrap_or(Value::Null);
+    for field in TEXT_FIELDS.iter() {
+        if let Some(value) = content.get_mut(*field) {
+            if let Some(values) = value.as_array() {
+                let bytes: Vec<u8> = values
+                    .iter()
+                    .filter_map(|value| value.as_u64().map(|byte| byte as u8))
+                    .collect();
+                *value = Value::String(text(&bytes));
+            }
+        }
+    }
+    content
+}
+
+fn cached(system_id: u8, component_id: u8, name: &str) -> Option<Value> {
+    data::messages_with_time(name)
+        .into_iter()
+        .find(|(message, _, _)| {
+            message.header.system_id == system_id && message.header.component_id == component_id
+        })
+        .map(|(message, _, _)| readable(&message.message))
+}
+
+/// Roll, pitch and yaw in degrees of a quaternion with w, x, y, z order
+pub fn euler_degrees(q: [f32; 4]) -> [f64; 3] {
+    let [w, x, y, z] = q.map(|value| value as f64);
+    let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+    [roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()]
+}
+
+/// Keeps the images reported by the cameras, new images are sent as events
+pub fn update(message: &MAVLinkMessage<MavMessage>, reception: &Reception) {
+    let captured = match &message.message {
+        MavMessage::CAMERA_IMAGE_CAPTURED(captured) => captured,
+        _ => return,
+    };
+
+    let key = (message.header.system_id, message.header.component_id);
+    let mut images = IMAGES.lock().unwrap();
+    let images = images.entry(key).or_default();
+
+    let time_utc = match captured.time_utc {
+        0 => None,
+        time => Utc.timestamp_opt((time / 1_000_000) as i64, 0).single(),
+    };
+    // Cameras may send the same image again when asked for it,
+    // the index starts again after a reboot but the capture time changes
+    if images
+        .iter()
+        .any(|image| image.image_index == captured.image_index && image.time_utc == time_utc)
+    {
+        return;
+    }
+
+    let image = Image {
+        time: reception.utc,
+        time_utc,
+        system_id: key.0,
+        component_id: key.1,
+        image_index: captured.image_index,
+        latitude: captured.lat as f64 / 1e7,
+        longitude: captured.lon as f64 / 1e7,
+        altitude: captured.alt as f64 / 1e3,
+        relative_altitude: captured.relative_alt as f64 / 1e3,
+        attitude: euler_degrees(captured.q),
+        success: captured.capture_result == 1,
+        file_url: text(&captured.file_url[..]),
+    };
+    websocket_manager::send_event("camera_image", &image);
+    images.push_back(image);
+    if images.len() > IMAGES_SIZE {
+        images.pop_front();
+    }
+}
+
+/// Images captured by a camera, sorted by reception
+pub fn images(system_id: u8, component_id: u8) -> Vec<Image> {
+    IMAGES
+        .lock()
+        .unwrap()
+        .get(&(system_id, component_id))
+        .map(|images| images.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn camera(system_id: u8, component_id: u8, online: bool) -> Camera {
+    Camera {
+        system_id,
+        component_id,
+        online,
+        information: cached(system_id, component_id, "CAMERA_INFORMATION"),
+        settings: cached(system_id, component_id, "CAMERA_SETTINGS"),
+        storage: cached(system_id, component_id, "STORAGE_INFORMATION"),
+        capture_status: cached(system_id, component_id, "CAMERA_CAPTURE_STATUS"),
+    }
+}
+
+/// Components with MAV_TYPE_CAMERA or that sent CAMERA_INFORMATION
+pub fn cameras() -> Vec<Camera> {
+    inventory::inventory()
+        .into_iter()
+        .filter(|component| {
+            component.mavtype == "MAV_TYPE_CAMERA"
+                || data::message(
+                    component.system_id,
+                    component.component_id,
+                    "CAMERA_INFORMATION",
+                )
+                .is_some()
+        })
+        .map(|component| {
+            camera(
+                component.system_id,
+                component.component_id,
+                component.online,
+            )
+        })
+        .collect()
+}
+
+/// Requests the camera protocol messages and answers with the updated camera
+pub fn refresh(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<Camera, CameraError> {
+    let mut camera = camera(system_id, component_id, true);
+    for name in CAMERA_MESSAGES.iter() {
+        let request = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+            param1: MavMessage::message_id_from_name(name).unwrap_or_default() as f32,
+            command: MavCmd::MAV_CMD_REQUEST_MESSAGE,
+            target_system: system_id,
+            target_component: component_id,
+            ..Default::default()
+        });
+        let matcher = Matcher {
+            name: name.to_string(),
+            system_id: Some(system_id),
+            component_id: Some(component_id),
+            fields: None,
+        };
+
+        // Cameras may not support storage or capture status
+        let content = match transaction::exchange(vehicle, &request, &matcher, 3, REQUEST_TIMEOUT) {
+            Ok(answer) => Some(readable(&answer.message)),
+            Err(TransactionError::Timeout) if *name != "CAMERA_INFORMATION" => None,
+            Err(error) => return Err(CameraError::Transaction(error)),
+        };
+        match *name {
+            "CAMERA_INFORMATION" => camera.information = content,
+            "CAMERA_SETTINGS" => camera.settings = content,
+            "STORAGE_INFORMATION" => camera.storage = content,
+            _ => camera.capture_status = content,
+        }
+    }
+    Ok(camera)
+}
+
+fn send(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    command: MavCmd,
+    params: [f32; 7],
+) -> Result<CommandResult, CameraError> {
+    command::send(
+        vehicle,
+        system_id,
+        component_id,
+        &Command::long(command, params),
+        REQUEST_TIMEOUT,
+    )
+    .map_err(CameraError::Command)
+}
+
+/// Takes a single photo, or photos every interval in seconds until the count or a stop
+pub fn start_photos(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    interval: f32,
+    count: u32,
+) -> Result<CommandResult, CameraError> {
+    // Single photos have a sequence number so retransmissions do not take more photos
+    let sequence = match count {
+        1 => {
+            let mut sequences = SEQUENCES.lock().unwrap();
+            let sequence = sequences.entry((system_id, component_id)).or_insert(0);
+            *sequence += 1;
+            *sequence as f32
+        }
+        _ => 0.0,
+    };
+    send(
+        vehicle,
+        system_id,
+        component_id,
+        MavCmd::MAV_CMD_IMAGE_START_CAPTURE,
+        [0.0, interval, count as f32, sequence, 0.0, 0.0, 0.0],
+    )
+}
+
+pub fn stop_photos(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<CommandResult, CameraError> {
+    send(
+        vehicle,
+        system_id,
+        component_id,
+        MavCmd::MAV_CMD_IMAGE_STOP_CAPTURE,
+        [0.0; 7],
+    )
+}
+
+pub fn start_video(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<CommandResult, CameraError> {
+    send(
+        vehicle,
+        system_id,
+        component_id,
+        MavCmd::MAV_CMD_VIDEO_START_CAPTURE,
+        [0.0; 7],
+    )
+}
+
+pub fn stop_video(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<CommandResult, CameraError> {
+    send(
+        vehicle,
+        system_id,
+        component_id,
+        MavCmd::MAV_CMD_VIDEO_STOP_CAPTURE,
+        [0.0; 7],
+    )
+}
+
+/// Changes the camera mode: IMAGE, VIDEO or IMAGE_SURVEY
+pub fn set_mode(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    mode: &str,
+) -> Result<CommandResult, CameraError> {
+    let mode_id = match mode.to_uppercase().as_str() {
+        "IMAGE" => 0.0,
+        "VIDEO" => 1.0,
+        "IMAGE_SURVEY" => 2.0,
+        _ => return Err(CameraError::UnknownMode(mode.to_string())),
+    };
+    send(
+        vehicle,
+        system_id,
+        component_id,
+        MavCmd::MAV_CMD_SET_CAMERA_MODE,
+        [0.0, mode_id, 0.0, 0.0, 0.0, 0.0, 0.0],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mavlink::ardupilotmega::CAMERA_IMAGE_CAPTURED_DATA;
+
+    #[test]
+    fn quaternion_angles() {
+        let close = |actual: [f64; 3], expected: [f64; 3]| {
+            actual
+                .iter()
+                .zip(expected.iter())
+                .all(|(actual, expected)| (actual - expected).abs() < 1e-3)
+        };
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(close(euler_degrees([1.0, 0.0, 0.0, 0.0]), [0.0, 0.0, 0.0]));
+        assert!(close(
+            euler_degrees([half, half, 0.0, 0.0]),
+            [90.0, 0.0, 0.0]
+        ));
+        let (sin, cos) = 15f32.to_radians().sin_cos();
+        assert!(close(
+            euler_degrees([cos, 0.0, -sin, 0.0]),
+            [0.0, -30.0, 0.0]
+        ));
+        assert!(close(
+            euler_degrees([half, 0.0, 0.0, half]),
+            [0.0, 0.0, 90.0]
+        ));
+    }
+
+    #[test]
+    fn images_after_reboot() {
+        let capture = |image_index, time_utc| {
+            let message = MAVLinkMessage {
+                header: mavlink::MavHeader {
+                    system_id: 204,
+                    component_id: 100,
+                    sequence: 0,
+                },
+                message: MavMessage::CAMERA_IMAGE_CAPTURED(CAMERA_IMAGE_CAPTURED_DATA {
+                    image_index,
+                    time_utc,
+                    ..Default::default()
+                }),
+            };
+            update(&message, &Reception::new("test"));
+        };
+        capture(0, 1_700_000_000_000_000);
+        capture(1, 1_700_000_001_000_000);
+        capture(1, 1_700_000_001_000_000);
+        // Indexes start again after a reboot
+        capture(0, 1_700_000_100_000_000);
+
+        let indexes: Vec<i32> = images(204, 100)
+            .iter()
+            .map(|image| image.image_index)
+            .collect();
+        assert_eq!(indexes, [0, 1, 0]);
+    }
+}
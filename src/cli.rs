@@ -23,35 +23,35 @@ pub fn init() {
 }
 
 pub fn is_verbose() -> bool {
-    return MANAGER.as_ref().clap_matches.is_present("verbose");
+    MANAGER.as_ref().clap_matches.is_present("verbose")
 }
 
 pub fn mavlink_connection_string() -> &'static str {
-    return MANAGER.as_ref().clap_matches.value_of("connect").unwrap();
+    MANAGER.as_ref().clap_matches.value_of("connect").unwrap()
 }
 
 pub fn server_address() -> &'static str {
-    return MANAGER.as_ref().clap_matches.value_of("server").unwrap();
+    MANAGER.as_ref().clap_matches.value_of("server").unwrap()
 }
 
 pub fn default_api_version() -> u8 {
-    return MANAGER
+    MANAGER
         .as_ref()
         .clap_matches
         .value_of("default-api-version")
         .unwrap()
         .parse::<u8>()
-        .unwrap();
+        .unwrap()
 }
 
 pub fn mavlink_version() -> u8 {
-    return MANAGER
+    MANAGER
         .as_ref()
         .clap_matches
         .value_of("mavlink")
         .unwrap()
         .parse::<u8>()
-        .unwrap();
+        .unwrap()
 }
 
 pub fn mavlink_system_and_component_id() -> (u8, u8) {
@@ -75,10 +75,113 @@ pub fn mavlink_system_and_component_id() -> (u8, u8) {
 }
 
 pub fn mavlink_send_initial_heartbeats() -> bool {
-    return MANAGER
+    MANAGER
         .as_ref()
         .clap_matches
-        .is_present("send-initial-heartbeats");
+        .is_present("send-initial-heartbeats")
+}
+
+pub fn snapshot_file() -> Option<&'static str> {
+    MANAGER.as_ref().clap_matches.value_of("snapshot-file")
+}
+
+pub fn snapshot_interval() -> std::time::Duration {
+    let seconds = MANAGER
+        .as_ref()
+        .clap_matches
+        .value_of("snapshot-interval")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    std::time::Duration::from_secs(seconds)
+}
+
+// Periodic tasks can't run every 0 seconds
+fn validate_interval(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(()),
+        _ => Err("Should be a positive number of seconds.".to_string()),
+    }
+}
+
+// Repeated values keep their first position
+fn unique<'a>(values: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut known = std::collections::HashSet::new();
+    values.filter(|value| known.insert(*value)).collect()
+}
+
+// Values read while receiving messages must not fail after startup
+fn validate_regex(value: String) -> Result<(), String> {
+    regex::Regex::new(&value)
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+pub fn metrics_fields() -> Vec<&'static str> {
+    MANAGER
+        .as_ref()
+        .clap_matches
+        .values_of("metrics-field")
+        .map(unique)
+        .unwrap_or_default()
+}
+
+pub fn timeseries_filter() -> Option<&'static str> {
+    MANAGER.as_ref().clap_matches.value_of("timeseries-filter")
+}
+
+pub fn timeseries_retention() -> std::time::Duration {
+    let seconds = MANAGER
+        .as_ref()
+        .clap_matches
+        .value_of("timeseries-retention")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    std::time::Duration::from_secs(seconds)
+}
+
+pub fn offline_timeout() -> std::time::Duration {
+    let seconds = MANAGER
+        .as_ref()
+        .clap_matches
+        .value_of("offline-timeout")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    std::time::Duration::from_secs(seconds)
+}
+
+pub fn influxdb_url() -> Option<&'static str> {
+    MANAGER.as_ref().clap_matches.value_of("influxdb-url")
+}
+
+pub fn influxdb_token() -> Option<&'static str> {
+    MANAGER.as_ref().clap_matches.value_of("influxdb-token")
+}
+
+pub fn message_rates() -> std::collections::BTreeMap<String, f32> {
+    MANAGER
+        .as_ref()
+        .clap_matches
+        .values_of("message-rate")
+        .map(|values| {
+            values
+                .map(|value| {
+                    let (name, rate) = value
+                        .split_once('=')
+                        .expect("Message rate should be in the format MESSAGE=HZ.");
+                    let rate = rate
+                        .parse::<f32>()
+                        .expect("Message rate should be a positive number of Hz.");
+                    (name.to_string(), rate)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 //TODO: Move to the top
@@ -157,9 +260,85 @@ fn get_clap_matches<'a>() -> clap::ArgMatches<'a> {
                 .long("send-initial-heartbeats")
                 .help("Send a burst of initial heartbeats to the autopilot spaced by 0.1 seconds to wake up MAVLink connection (useful for PX4-like autopilots).")
                 .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("snapshot-file")
+                .long("snapshot-file")
+                .value_name("PATH")
+                .help("Periodically saves all received messages to this file and restores them at startup, restored messages are marked as stale until received again.")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("snapshot-interval")
+                .long("snapshot-interval")
+                .value_name("SECONDS")
+                .help("Sets the interval between snapshots saved to the snapshot file.")
+                .takes_value(true)
+                .validator(validate_interval)
+                .default_value("60"),
+        )
+        .arg(
+            clap::Arg::with_name("metrics-field")
+                .long("metrics-field")
+                .value_name("MESSAGE.FIELD")
+                .help("Exports a numeric message field as a gauge in the /metrics endpoint, E.g: SYS_STATUS.voltage_battery. Can be used multiple times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("timeseries-filter")
+                .long("timeseries-filter")
+                .value_name("REGEX")
+                .help("Records the numeric fields of the received messages that match this regex, available as CSV and pushed to the InfluxDB URL.")
+                .takes_value(true)
+                .validator(validate_regex),
+        )
+        .arg(
+            clap::Arg::with_name("timeseries-retention")
+                .long("timeseries-retention")
+                .value_name("SECONDS")
+                .help("Sets how long the recorded messages are kept in memory.")
+                .takes_value(true)
+                .validator(validate_interval)
+                .default_value("3600"),
+        )
+        .arg(
+            clap::Arg::with_name("influxdb-url")
+                .long("influxdb-url")
+                .value_name("URL")
+                .help("Pushes the recorded messages in InfluxDB line protocol to this HTTP write endpoint, E.g: http://127.0.0.1:8086/api/v2/write?org=blue&bucket=vehicle")
+                .takes_value(true)
+                .requires("timeseries-filter"),
+        )
+        .arg(
+            clap::Arg::with_name("influxdb-token")
+                .long("influxdb-token")
+                .value_name("TOKEN")
+                .help("Sets the token used to authenticate with the InfluxDB write endpoint.")
+                .takes_value(true)
+                .requires("influxdb-url"),
+        )
+        .arg(
+            clap::Arg::with_name("message-rate")
+                .long("message-rate")
+                .value_name("MESSAGE=HZ")
+                .help("Sets the rate of a message in every autopilot when it appears or comes back online, E.g: ATTITUDE=10. Can be used multiple times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("offline-timeout")
+                .long("offline-timeout")
+                .value_name("SECONDS")
+                .help("Sets the time without heartbeats after which a component is offline.")
+                .takes_value(true)
+                .validator(validate_interval)
+                .default_value("5"),
         );
 
-    return matches.get_matches();
+    matches.get_matches()
 }
 
 #[cfg(test)]
@@ -173,5 +352,31 @@ mod tests {
         assert_eq!(server_address(), "0.0.0.0:8088");
         assert_eq!(mavlink_version(), 2);
         assert_eq!(default_api_version(), 1);
+        assert_eq!(snapshot_file(), None);
+        assert_eq!(snapshot_interval(), std::time::Duration::from_secs(60));
+        assert!(metrics_fields().is_empty());
+        assert_eq!(timeseries_filter(), None);
+        assert_eq!(timeseries_retention(), std::time::Duration::from_secs(3600));
+        assert_eq!(influxdb_url(), None);
+        assert!(message_rates().is_empty());
+        assert_eq!(offline_timeout(), std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn unique_values() {
+        let values = ["SYS_STATUS.load", "VFR_HUD.alt", "SYS_STATUS.load"];
+        assert_eq!(
+            unique(values.iter().copied()),
+            vec!["SYS_STATUS.load", "VFR_HUD.alt"]
+        );
+    }
+
+    #[test]
+    fn interval_validation() {
+        assert!(validate_interval("60".to_string()).is_ok());
+        assert!(validate_interval("0".to_string()).is_err());
+        assert!(validate_interval("-1".to_string()).is_err());
+        assert!(validate_regex("ATTITUDE|VFR_HUD".to_string()).is_ok());
+        assert!(validate_regex("(".to_string()).is_err());
     }
 }
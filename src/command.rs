@@ -0,0 +1,272 @@
+use std::time::{Duration, Instant};
+
+use mavlink::ardupilotmega::{
+    MavCmd, MavFrame, MavMessage, MavResult, COMMAND_ACK_DATA, COMMAND_INT_DATA, COMMAND_LONG_DATA,
+};
+use serde::Serialize;
+
+use crate::cli;
+use crate::data;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{RETRIES, RETRY_TIMEOUT};
+use crate::websocket_manager;
+
+/// Parameters of a command, which also select the message used to send it
+#[derive(Clone, Debug)]
+pub enum Params {
+    /// Sent as COMMAND_LONG
+    Long([f32; 7]),
+    /// Sent as COMMAND_INT, x and y replace param5 and param6 and keep their integer
+    /// precision, E.g: latitude and longitude in degE7
+    Int {
+        frame: MavFrame,
+        params: [f32; 4],
+        x: i32,
+        y: i32,
+        z: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Command {
+    pub command: MavCmd,
+    pub params: Params,
+}
+
+impl Command {
+    pub fn long(command: MavCmd, params: [f32; 7]) -> Self {
+        Self {
+            command,
+            params: Params::Long(params),
+        }
+    }
+
+    pub fn int(command: MavCmd, frame: MavFrame, params: [f32; 4], x: i32, y: i32, z: f32) -> Self {
+        Self {
+            command,
+            params: Params::Int {
+                frame,
+                params,
+                x,
+                y,
+                z,
+            },
+        }
+    }
+
+    /// COMMAND_INT with a position in degrees and the altitude of the frame in meters
+    pub fn global(
+        command: MavCmd,
+        frame: MavFrame,
+        params: [f32; 4],
+        latitude: f64,
+        longitude: f64,
+        altitude: f32,
+    ) -> Self {
+        Self::int(
+            command,
+            frame,
+            params,
+            degrees_e7(latitude),
+            degrees_e7(longitude),
+            altitude,
+        )
+    }
+
+    fn message(&self, system_id: u8, component_id: u8, confirmation: u8) -> MavMessage {
+        match self.params {
+            Params::Int {
+                frame,
+                params: [param1, param2, param3, param4],
+                x,
+                y,
+                z,
+            } => MavMessage::COMMAND_INT(COMMAND_INT_DATA {
+                param1,
+                param2,
+                param3,
+                param4,
+                x,
+                y,
+                z,
+                command: self.command,
+                target_system: system_id,
+                target_component: component_id,
+                frame,
+                current: 0,
+                autocontinue: 0,
+            }),
+            Params::Long([param1, param2, param3, param4, param5, param6, param7]) => {
+                MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+                    param1,
+                    param2,
+                    param3,
+                    param4,
+                    param5,
+                    param6,
+                    param7,
+                    command: self.command,
+                    target_system: system_id,
+                    target_component: component_id,
+                    confirmation,
+                })
+            }
+        }
+    }
+}
+
+/// Degrees as degE7, the integer unit of latitudes and longitudes
+pub fn degrees_e7(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
+
+/// Final answer of the vehicle to a command
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandResult {
+    /// MAV_RESULT name, E.g: MAV_RESULT_ACCEPTED
+    pub result: String,
+    pub accepted: bool,
+    /// Last progress percentage reported with MAV_RESULT_IN_PROGRESS
+    pub progress: Option<u8>,
+    /// Command specific information, E.g: the reason of a denied command
+    pub result_param2: i32,
+    /// Number of times that the command was sent
+    pub attempts: u8,
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Send(std::io::Error),
+    /// No final answer, contains the last progress reported by the vehicle
+    Timeout(Option<u8>),
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Send(error) => write!(f, "Failed to send command: {error}"),
+            Self::Timeout(None) => write!(f, "Timeout while waiting for COMMAND_ACK."),
+            Self::Timeout(Some(progress)) => write!(
+                f,
+                "Timeout while waiting for COMMAND_ACK, last progress was {progress}%."
+            ),
+        }
+    }
+}
+
+// Answers to other GCSs are ignored, older autopilots do not fill the targets
+fn is_answer(
+    message: &data::MAVLinkMessage<MavMessage>,
+    system_id: u8,
+    component_id: u8,
+    command: MavCmd,
+) -> Option<COMMAND_ACK_DATA> {
+    if message.header.system_id != system_id || message.header.component_id != component_id {
+        return None;
+    }
+    let ack = match &message.message {
+        MavMessage::COMMAND_ACK(ack) if ack.command == command => ack,
+        _ => return None,
+    };
+    let (own_system_id, own_component_id) = cli::mavlink_system_and_component_id();
+    if (ack.target_system != 0 && ack.target_system != own_system_id)
+        || (ack.target_component != 0 && ack.target_component != own_component_id)
+    {
+        return None;
+    }
+    Some(ack.clone())
+}
+
+/// Sends the command until it's acknowledged, the confirmation is incremented on every retry.
+/// Commands in progress are not sent again, their progress is sent as events.
+pub fn send(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    command: &Command,
+    timeout: Duration,
+) -> Result<CommandResult, CommandError> {
+    let receiver = data::subscribe();
+    let deadline = Instant::now() + timeout;
+    let mut progress = None;
+    let mut attempts = 0;
+
+    while (attempts as usize) < RETRIES || progress.is_some() {
+        if progress.is_none() {
+            vehicle
+                .lock()
+                .unwrap()
+                .send_default(&command.message(system_id, component_id, attempts))
+                .map_err(CommandError::Send)?;
+            attempts += 1;
+        }
+
+        // After the first IN_PROGRESS, the vehicle has until the timeout to answer
+        let retry = match progress {
+            Some(_) => deadline,
+            None => (Instant::now() + RETRY_TIMEOUT).min(deadline),
+        };
+        while let Some(remaining) = retry.checked_duration_since(Instant::now()) {
+            let message = match receiver.recv_timeout(remaining) {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            let ack = match is_answer(&message, system_id, component_id, command.command) {
+                Some(ack) => ack,
+                None => continue,
+            };
+
+            if ack.result == MavResult::MAV_RESULT_IN_PROGRESS {
+                progress = Some(ack.progress);
+                websocket_manager::send_event(
+                    "command_progress",
+                    &serde_json::json!({
+                        "system_id": system_id,
+                        "component_id": component_id,
+                        "command": format!("{:?}", command.command),
+                        "progress": ack.progress,
+                    }),
+                );
+                continue;
+            }
+
+            return Ok(CommandResult {
+                result: format!("{:?}", ack.result),
+                accepted: ack.result == MavResult::MAV_RESULT_ACCEPTED,
+                progress,
+                result_param2: ack.result_param2,
+                attempts,
+            });
+        }
+
+        if Instant::now() >= deadline {
+            break;
+        }
+    }
+
+    Err(CommandError::Timeout(progress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_int_position() {
+        let command = Command::global(
+            MavCmd::MAV_CMD_DO_REPOSITION,
+            MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT,
+            [-1.0, 1.0, 0.0, 0.0],
+            -27.5999999,
+            -48.5123457,
+            20.0,
+        );
+        match command.message(1, 1, 0) {
+            MavMessage::COMMAND_INT(data) => {
+                assert_eq!((data.x, data.y, data.z), (-275999999, -485123457, 20.0));
+                assert_eq!(data.frame, MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT);
+            }
+            message => panic!("Unexpected message: {:?}", message),
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
 use lazy_static::lazy_static;
 use mavlink::{self, Message};
 use serde::{Deserialize, Serialize};
@@ -34,18 +37,56 @@ impl Temporal {
     }
 }
 
+pub fn is_false(value: &bool) -> bool {
+    !value
+}
+
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
 struct Status {
     time: Temporal,
+    /// Restored from a snapshot and not received since then
+    #[serde(default, skip_serializing_if = "is_false")]
+    stale: bool,
 }
 
 impl Status {
     fn update(&mut self) -> &mut Self {
-        self.time.update();
+        // Statistics from the snapshot are not valid anymore
+        if self.stale {
+            self.time = Temporal::default();
+            self.stale = false;
+        } else {
+            self.time.update();
+        }
         self
     }
 }
 
+/// Information about when and where a message was received
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Reception {
+    /// UTC time of reception
+    pub utc: chrono::DateTime<chrono::Utc>,
+    /// Nanoseconds since the service started, not affected by system clock changes
+    pub monotonic_ns: u64,
+    /// Link that the message came from, the connection string or the API used to send it
+    pub link: String,
+    /// UTC time of the vehicle timestamp of the message, E.g: time_boot_ms, estimated with TIMESYNC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle_time_utc: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Reception {
+    pub fn new(link: &str) -> Self {
+        Self {
+            utc: chrono::Utc::now(),
+            monotonic_ns: monotonic_ns(),
+            link: link.to_string(),
+            vehicle_time_utc: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MAVLinkMessage<T> {
     pub header: mavlink::MavHeader,
@@ -55,32 +96,191 @@ pub struct MAVLinkMessage<T> {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct MAVLinkMessageStatus {
     message: mavlink::ardupilotmega::MavMessage,
+    header: mavlink::MavHeader,
+    reception: Reception,
     status: Status,
 }
 
 impl MAVLinkMessageStatus {
-    fn update(&mut self, message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>) {
+    fn update(
+        &mut self,
+        message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) {
         self.message = message.message.clone();
+        self.header = message.header;
+        self.reception = reception.clone();
         self.status.update();
     }
 }
 
+#[derive(Debug)]
+struct MAVLinkMessageCellContent {
+    status: MAVLinkMessageStatus,
+    // Serialized status, it's created on demand and dropped on every update
+    cache: Option<Arc<serde_json::Value>>,
+}
+
+/// Shared cell that holds the latest state of a single message, updates only lock this cell
+#[derive(Clone, Debug)]
+struct MAVLinkMessageCell(Arc<Mutex<MAVLinkMessageCellContent>>);
+
+impl MAVLinkMessageCell {
+    fn new(status: MAVLinkMessageStatus) -> Self {
+        Self(Arc::new(Mutex::new(MAVLinkMessageCellContent {
+            status,
+            cache: None,
+        })))
+    }
+
+    fn update(
+        &self,
+        message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) {
+        let mut content = self.0.lock().unwrap();
+        content.status.update(message, reception);
+        content.cache = None;
+    }
+
+    fn mark_stale(&self) {
+        let mut content = self.0.lock().unwrap();
+        content.status.status.stale = true;
+        content.cache = None;
+    }
+
+    fn value(&self) -> Arc<serde_json::Value> {
+        let mut content = self.0.lock().unwrap();
+        if let Some(value) = &content.cache {
+            return value.clone();
+        }
+
+        let value = Arc::new(serde_json::to_value(&content.status).unwrap());
+        content.cache = Some(value.clone());
+        value
+    }
+
+    fn pointer(&self, path: &[&str], view: View) -> Option<String> {
+        let value = self.value();
+        let value = match view {
+            Some(view) => {
+                let mut value = value.as_ref().clone();
+                view(&mut value);
+                Arc::new(value)
+            }
+            None => value,
+        };
+
+        if path.is_empty() {
+            return Some(serde_json::to_string_pretty(value.as_ref()).unwrap());
+        }
+
+        value
+            .pointer(&format!("/{}", path.join("/")))
+            .map(|content| serde_json::to_string_pretty(content).unwrap())
+    }
+}
+
+impl Serialize for MAVLinkMessageCell {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MAVLinkMessageCell {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        MAVLinkMessageStatus::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Transformation applied over the JSON of each message status before it's returned
+pub type View<'a> = Option<&'a dyn Fn(&mut serde_json::Value)>;
+
+// Where the message status objects are inside of each level, "*" matches any key
+const VEHICLES_MESSAGES: &[&str] = &["vehicles", "*", "components", "*", "messages", "*"];
+const VEHICLE_MESSAGES: &[&str] = &["components", "*", "messages", "*"];
+const COMPONENT_MESSAGES: &[&str] = &["messages", "*"];
+
+fn apply_view(
+    value: &mut serde_json::Value,
+    pattern: &[&str],
+    view: &dyn Fn(&mut serde_json::Value),
+) {
+    match pattern.split_first() {
+        None => view(value),
+        Some((&"*", rest)) => {
+            if let Some(object) = value.as_object_mut() {
+                for content in object.values_mut() {
+                    apply_view(content, rest, view);
+                }
+            }
+        }
+        Some((key, rest)) => {
+            if let Some(content) = value.get_mut(*key) {
+                apply_view(content, rest, view);
+            }
+        }
+    }
+}
+
+// Serialize only the requested subtree, falling back to a JSON pointer when the path goes
+// beyond the typed structure
+fn subtree_pointer<T: Serialize>(
+    subtree: &T,
+    path: &[&str],
+    pattern: &[&str],
+    view: View,
+) -> Option<String> {
+    if path.is_empty() && view.is_none() {
+        return Some(serde_json::to_string_pretty(subtree).unwrap());
+    }
+
+    let mut value = serde_json::to_value(subtree).unwrap();
+    if let Some(view) = view {
+        apply_view(&mut value, pattern, view);
+    }
+
+    if path.is_empty() {
+        return Some(serde_json::to_string_pretty(&value).unwrap());
+    }
+
+    value
+        .pointer(&format!("/{}", path.join("/")))
+        .map(|content| serde_json::to_string_pretty(content).unwrap())
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct MAVLinkVehicleComponentData {
     id: u8,
-    messages: HashMap<String, MAVLinkMessageStatus>,
+    messages: HashMap<String, MAVLinkMessageCell>,
 }
 
 impl MAVLinkVehicleComponentData {
-    fn update(&mut self, message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>) {
+    fn update(
+        &mut self,
+        message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) {
         let message_name = message.message.message_name().to_string();
         self.messages
             .entry(message_name)
-            .or_insert(MAVLinkMessageStatus {
-                message: message.message.clone(),
-                status: Status::default(),
+            .or_insert_with(|| {
+                MAVLinkMessageCell::new(MAVLinkMessageStatus {
+                    message: message.message.clone(),
+                    header: message.header,
+                    reception: reception.clone(),
+                    status: Status::default(),
+                })
             })
-            .update(message);
+            .update(message, reception);
+    }
+
+    fn pointer(&self, path: &[&str], view: View) -> Option<String> {
+        match path {
+            ["messages"] => subtree_pointer(&self.messages, &[], &COMPONENT_MESSAGES[1..], view),
+            ["messages", name, rest @ ..] => self.messages.get(*name)?.pointer(rest, view),
+            _ => subtree_pointer(self, path, COMPONENT_MESSAGES, view),
+        }
     }
 }
 
@@ -91,7 +291,11 @@ struct MAVLinkVehicleData {
 }
 
 impl MAVLinkVehicleData {
-    fn update(&mut self, message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>) {
+    fn update(
+        &mut self,
+        message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) {
         let component_id = message.header.component_id;
         self.components
             .entry(component_id)
@@ -99,7 +303,18 @@ impl MAVLinkVehicleData {
                 id: component_id,
                 messages: HashMap::new(),
             })
-            .update(message);
+            .update(message, reception);
+    }
+
+    fn pointer(&self, path: &[&str], view: View) -> Option<String> {
+        match path {
+            ["components"] => subtree_pointer(&self.components, &[], &VEHICLE_MESSAGES[1..], view),
+            ["components", id, rest @ ..] => self
+                .components
+                .get(&id.parse::<u8>().ok()?)?
+                .pointer(rest, view),
+            _ => subtree_pointer(self, path, VEHICLE_MESSAGES, view),
+        }
     }
 }
 
@@ -110,7 +325,11 @@ pub struct MAVLinkVehiclesData {
 
 impl MAVLinkVehiclesData {
     //TODO: Move message to reference
-    fn update(&mut self, message: MAVLinkMessage<mavlink::ardupilotmega::MavMessage>) {
+    fn update(
+        &mut self,
+        message: MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) {
         let vehicle_id = message.header.system_id;
         self.vehicles
             .entry(vehicle_id)
@@ -118,49 +337,326 @@ impl MAVLinkVehiclesData {
                 id: vehicle_id,
                 components: HashMap::new(),
             })
-            .update(&message);
+            .update(&message, reception);
     }
 
-    pub fn pointer(&self, path: &str) -> String {
-        if path.is_empty() {
-            return serde_json::to_string_pretty(self).unwrap();
-        }
+    fn mark_stale(&self) {
+        self.vehicles
+            .values()
+            .flat_map(|vehicle| vehicle.components.values())
+            .flat_map(|component| component.messages.values())
+            .for_each(MAVLinkMessageCell::mark_stale);
+    }
 
-        let path = format!("/{path}");
+    fn cells(&self) -> impl Iterator<Item = (u8, u8, &String, &MAVLinkMessageCell)> {
+        self.vehicles.iter().flat_map(|(system_id, vehicle)| {
+            vehicle
+                .components
+                .iter()
+                .flat_map(move |(component_id, component)| {
+                    component
+                        .messages
+                        .iter()
+                        .map(move |(name, cell)| (*system_id, *component_id, name, cell))
+                })
+        })
+    }
 
-        dbg!(&path);
+    fn cell(&self, system_id: u8, component_id: u8, name: &str) -> Option<MAVLinkMessageCell> {
+        self.vehicles
+            .get(&system_id)?
+            .components
+            .get(&component_id)?
+            .messages
+            .get(name)
+            .cloned()
+    }
 
-        if path == "/vehicles" {
-            return serde_json::to_string_pretty(&self.vehicles).unwrap();
+    pub fn pointer(&self, path: &str, view: View) -> String {
+        let path: Vec<&str> = if path.is_empty() {
+            vec![]
+        } else {
+            path.split('/').collect()
         };
 
-        let value = serde_json::to_value(self).unwrap();
-        return match value.pointer(&path) {
-            Some(content) => serde_json::to_string_pretty(content).unwrap(),
-            None => "None".into(),
+        let content = match path.as_slice() {
+            ["vehicles"] => subtree_pointer(&self.vehicles, &[], &VEHICLES_MESSAGES[1..], view),
+            ["vehicles", id, rest @ ..] => id
+                .parse::<u8>()
+                .ok()
+                .and_then(|id| self.vehicles.get(&id))
+                .and_then(|vehicle| vehicle.pointer(rest, view)),
+            _ => subtree_pointer(self, &path, VEHICLES_MESSAGES, view),
         };
+
+        content.unwrap_or_else(|| "None".into())
     }
 }
 
+type MAVLinkMessageSender = mpsc::Sender<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>>;
+type MAVLinkMessageAsyncSender =
+    UnboundedSender<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>>;
+
 #[derive(Debug)]
 struct Data {
-    messages: Arc<Mutex<MAVLinkVehiclesData>>,
+    messages: Arc<RwLock<MAVLinkVehiclesData>>,
+    subscribers: Arc<Mutex<Vec<MAVLinkMessageSender>>>,
+    async_subscribers: Arc<Mutex<Vec<MAVLinkMessageAsyncSender>>>,
 }
 
 lazy_static! {
+    static ref START: Instant = Instant::now();
     static ref DATA: Data = Data {
-        messages: Arc::new(Mutex::new(MAVLinkVehiclesData::default())),
+        messages: Arc::new(RwLock::new(MAVLinkVehiclesData::default())),
+        subscribers: Arc::new(Mutex::new(Vec::new())),
+        async_subscribers: Arc::new(Mutex::new(Vec::new())),
     };
 }
 
-pub fn update((header, message): (mavlink::MavHeader, mavlink::ardupilotmega::MavMessage)) {
-    DATA.messages
+pub fn update(
+    (header, message): (mavlink::MavHeader, mavlink::ardupilotmega::MavMessage),
+    reception: Reception,
+) {
+    let message = MAVLinkMessage { header, message };
+
+    // Subscribers that dropped their receiver are removed here
+    DATA.subscribers
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(message.clone()).is_ok());
+    DATA.async_subscribers
         .lock()
         .unwrap()
-        .update(MAVLinkMessage { header, message });
+        .retain(|subscriber| subscriber.unbounded_send(message.clone()).is_ok());
+
+    // Most of the time the message already exists, so only its cell needs to be locked
+    let cell = DATA.messages.read().unwrap().cell(
+        message.header.system_id,
+        message.header.component_id,
+        message.message.message_name(),
+    );
+    match cell {
+        Some(cell) => cell.update(&message, &reception),
+        None => DATA.messages.write().unwrap().update(message, &reception),
+    }
+}
+
+/// Nanoseconds since the service started, not affected by system clock changes
+pub fn monotonic_ns() -> u64 {
+    START.elapsed().as_nanos() as u64
+}
+
+/// Returns a channel that receives every message that goes through [update]
+pub fn subscribe() -> mpsc::Receiver<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>> {
+    let (sender, receiver) = mpsc::channel();
+    DATA.subscribers.lock().unwrap().push(sender);
+    receiver
+}
+
+/// Same as [subscribe], for async code that should not block a thread while waiting
+pub fn subscribe_async() -> UnboundedReceiver<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>> {
+    let (sender, receiver) = unbounded();
+    DATA.async_subscribers.lock().unwrap().push(sender);
+    receiver
+}
+
+fn is_message(
+    message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+    name: &str,
+    system_id: Option<u8>,
+    component_id: Option<u8>,
+) -> bool {
+    message.message.message_name() == name
+        && system_id.is_none_or(|id| id == message.header.system_id)
+        && component_id.is_none_or(|id| id == message.header.component_id)
+}
+
+/// Waits for a new message with the given name and optional ids, or until the timeout expires
+pub async fn next_message(
+    name: &str,
+    system_id: Option<u8>,
+    component_id: Option<u8>,
+    timeout: Duration,
+) -> Option<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>> {
+    let mut receiver = subscribe_async();
+    let wait = async {
+        while let Some(message) = receiver.next().await {
+            if is_message(&message, name, system_id, component_id) {
+                return Some(message);
+            }
+        }
+        None
+    };
+    actix_web::rt::time::timeout(timeout, wait)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Blocks until a new message with the given name and optional ids arrives, or the timeout expires
+pub fn wait_for_message(
+    name: &str,
+    system_id: Option<u8>,
+    component_id: Option<u8>,
+    timeout: Duration,
+) -> Option<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>> {
+    let receiver = subscribe();
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        let message = receiver.recv_timeout(remaining).ok()?;
+        if is_message(&message, name, system_id, component_id) {
+            return Some(message);
+        }
+    }
+}
+
+/// Reception statistics of a single message
+#[derive(Clone, Debug)]
+pub struct MessageStatistics {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub name: String,
+    pub counter: i64,
+    pub frequency: f32,
+    pub last_update: chrono::DateTime<chrono::Local>,
+}
+
+pub fn statistics() -> Vec<MessageStatistics> {
+    DATA.messages
+        .read()
+        .unwrap()
+        .cells()
+        .map(|(system_id, component_id, name, cell)| {
+            let content = cell.0.lock().unwrap();
+            MessageStatistics {
+                system_id,
+                component_id,
+                name: name.clone(),
+                counter: content.status.status.time.counter,
+                frequency: content.status.status.time.frequency,
+                last_update: content.status.status.time.last_update,
+            }
+        })
+        .collect()
+}
+
+/// Latest status of the messages with this name from all vehicles and components, as JSON
+pub fn messages_by_name(name: &str) -> Vec<(u8, u8, Arc<serde_json::Value>)> {
+    DATA.messages
+        .read()
+        .unwrap()
+        .cells()
+        .filter(|(_, _, message_name, _)| *message_name == name)
+        .map(|(system_id, component_id, _, cell)| (system_id, component_id, cell.value()))
+        .collect()
+}
+
+/// Message with the time of its first and last update
+pub type TimedMessage = (
+    MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+    chrono::DateTime<chrono::Local>,
+    chrono::DateTime<chrono::Local>,
+);
+
+/// Latest messages with this name from all vehicles and components, with their first and last update
+pub fn messages_with_time(name: &str) -> Vec<TimedMessage> {
+    DATA.messages
+        .read()
+        .unwrap()
+        .cells()
+        .filter(|(_, _, message_name, _)| *message_name == name)
+        .map(|(_, _, _, cell)| {
+            let content = cell.0.lock().unwrap();
+            (
+                MAVLinkMessage {
+                    header: content.status.header,
+                    message: content.status.message.clone(),
+                },
+                content.status.status.time.first_update,
+                content.status.status.time.last_update,
+            )
+        })
+        .collect()
+}
+
+/// Latest status of a single message as JSON
+pub fn message(system_id: u8, component_id: u8, name: &str) -> Option<Arc<serde_json::Value>> {
+    let cell = DATA
+        .messages
+        .read()
+        .unwrap()
+        .cell(system_id, component_id, name)?;
+    Some(cell.value())
+}
+
+/// IDs of all vehicles that sent a message
+pub fn vehicles() -> Vec<u8> {
+    DATA.messages
+        .read()
+        .unwrap()
+        .vehicles
+        .keys()
+        .copied()
+        .collect()
+}
+
+/// Latest messages received from all components of a vehicle, with their reception
+pub fn vehicle_messages(
+    system_id: u8,
+) -> Vec<(
+    MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+    Reception,
+)> {
+    DATA.messages
+        .read()
+        .unwrap()
+        .cells()
+        .filter(|(id, _, _, _)| *id == system_id)
+        .map(|(_, _, _, cell)| {
+            let content = cell.0.lock().unwrap();
+            (
+                MAVLinkMessage {
+                    header: content.status.header,
+                    message: content.status.message.clone(),
+                },
+                content.status.reception.clone(),
+            )
+        })
+        .collect()
+}
+
+/// Latest heartbeat of the vehicle autopilot, the component with a valid MAV_AUTOPILOT
+pub fn autopilot_heartbeat(
+    system_id: u8,
+) -> Option<MAVLinkMessage<mavlink::ardupilotmega::MavMessage>> {
+    vehicle_messages(system_id)
+        .into_iter()
+        .map(|(message, _)| message)
+        .filter(|message| match &message.message {
+            mavlink::ardupilotmega::MavMessage::HEARTBEAT(heartbeat) => {
+                heartbeat.autopilot != mavlink::ardupilotmega::MavAutopilot::MAV_AUTOPILOT_INVALID
+            }
+            _ => false,
+        })
+        .min_by_key(|message| message.header.component_id)
+}
+
+/// Serializes all received messages, it can be loaded later with [restore]
+pub fn snapshot() -> serde_json::Value {
+    serde_json::to_value(&*DATA.messages.read().unwrap()).unwrap()
+}
+
+/// Replaces the received messages with a snapshot, its messages are stale until received again
+pub fn restore(snapshot: serde_json::Value) -> serde_json::Result<()> {
+    let messages: MAVLinkVehiclesData = serde_json::from_value(snapshot)?;
+    messages.mark_stale();
+    *DATA.messages.write().unwrap() = messages;
+    Ok(())
 }
 
-pub fn messages() -> MAVLinkVehiclesData {
-    let messages = DATA.messages.lock().unwrap();
-    messages.clone()
+/// Serializes the subtree of the received messages that matches the path
+pub fn pointer(path: &str, view: View) -> String {
+    DATA.messages.read().unwrap().pointer(path, view)
 }
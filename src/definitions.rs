@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+
+macro_rules! definition_file {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!(env!("OUT_DIR"), "/definitions/", $name)),
+        )
+    };
+}
+
+// Copied by build.rs from the mavlink crate, these are the files used by the ardupilotmega dialect,
+// they are empty when the build could not find them
+static DEFINITION_FILES: &[(&str, &str)] = &[
+    definition_file!("minimal.xml"),
+    definition_file!("standard.xml"),
+    definition_file!("common.xml"),
+    definition_file!("uAvionix.xml"),
+    definition_file!("icarous.xml"),
+    definition_file!("cubepilot.xml"),
+    definition_file!("csAirLink.xml"),
+    definition_file!("ardupilotmega.xml"),
+];
+
+#[derive(Deserialize)]
+struct XmlMavlink {
+    #[serde(default)]
+    enums: XmlEnums,
+    #[serde(default)]
+    messages: XmlMessages,
+}
+
+#[derive(Default, Deserialize)]
+struct XmlEnums {
+    #[serde(rename = "enum", default)]
+    list: Vec<XmlEnum>,
+}
+
+#[derive(Deserialize)]
+struct XmlEnum {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "@bitmask", default)]
+    bitmask: Option<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(rename = "entry", default)]
+    entries: Vec<XmlEntry>,
+}
+
+#[derive(Deserialize)]
+struct XmlEntry {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "@value", default)]
+    value: Option<String>,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Default, Deserialize)]
+struct XmlMessages {
+    #[serde(rename = "message", default)]
+    list: Vec<XmlMessage>,
+}
+
+#[derive(Deserialize)]
+struct XmlMessage {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "field", default)]
+    fields: Vec<XmlField>,
+}
+
+#[derive(Deserialize)]
+struct XmlField {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "@units", default)]
+    units: Option<String>,
+    #[serde(rename = "@enum", default)]
+    enumeration: Option<String>,
+    #[serde(rename = "@display", default)]
+    display: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct EnumEntry {
+    pub name: String,
+    pub value: u64,
+    pub description: String,
+}
+
+#[derive(Debug, Default)]
+pub struct EnumDefinition {
+    pub bitmask: bool,
+    pub description: String,
+    pub entries: Vec<EnumEntry>,
+}
+
+impl EnumDefinition {
+    pub fn entry(&self, name: &str) -> Option<&EnumEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn entry_by_value(&self, value: u64) -> Option<&EnumEntry> {
+        self.entries.iter().find(|entry| entry.value == value)
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldDefinition {
+    pub units: Option<String>,
+    pub enumeration: Option<String>,
+    pub bitmask: bool,
+}
+
+#[derive(Debug, Default)]
+struct Definitions {
+    enums: HashMap<String, EnumDefinition>,
+    // Message name to field name, using the same field names of the JSON messages
+    messages: HashMap<String, HashMap<String, FieldDefinition>>,
+}
+
+lazy_static! {
+    static ref DEFINITIONS: Definitions = Definitions::load();
+}
+
+// Descriptions are indented inside the XML, keep only single spaces
+fn clean_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn parse_value(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl Definitions {
+    fn load() -> Self {
+        let mut definitions = Self::default();
+        for (name, content) in DEFINITION_FILES {
+            if content.is_empty() {
+                continue;
+            }
+            match quick_xml::de::from_str::<XmlMavlink>(content) {
+                Ok(mavlink) => definitions.add(mavlink),
+                Err(error) => warn!("Failed to parse MAVLink definition {name}: {error:?}"),
+            }
+        }
+        definitions
+    }
+
+    fn add(&mut self, mavlink: XmlMavlink) {
+        for xml_enum in mavlink.enums.list {
+            // Dialects can extend enums defined in other files
+            let definition = self.enums.entry(xml_enum.name).or_default();
+            definition.bitmask |= xml_enum.bitmask.as_deref() == Some("true");
+            if definition.description.is_empty() {
+                definition.description = clean_text(&xml_enum.description);
+            }
+            for entry in xml_enum.entries {
+                let value = match entry.value.as_deref().and_then(parse_value) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                definition.entries.push(EnumEntry {
+                    name: entry.name,
+                    value,
+                    description: clean_text(&entry.description),
+                });
+            }
+        }
+
+        for message in mavlink.messages.list {
+            let fields = message
+                .fields
+                .into_iter()
+                .map(|field| {
+                    // "type" is a reserved word in rust, it's renamed by the mavlink crate
+                    let name = match field.name.as_str() {
+                        "type" => "mavtype".to_string(),
+                        _ => field.name,
+                    };
+                    let definition = FieldDefinition {
+                        units: field.units,
+                        enumeration: field.enumeration,
+                        bitmask: field.display.as_deref() == Some("bitmask"),
+                    };
+                    (name, definition)
+                })
+                .collect();
+            self.messages.insert(message.name, fields);
+        }
+    }
+}
+
+pub fn enumeration(name: &str) -> Option<&'static EnumDefinition> {
+    DEFINITIONS.enums.get(name)
+}
+
+pub fn field(message_name: &str, field_name: &str) -> Option<&'static FieldDefinition> {
+    DEFINITIONS.messages.get(message_name)?.get(field_name)
+}
@@ -1,19 +1,46 @@
 use std::path::Path;
+use std::time::Duration;
 
 use actix_web::{
     web::{self, Json},
     HttpRequest, HttpResponse,
 };
 use actix_web_actors::ws;
+use futures::StreamExt;
 use include_dir::{include_dir, Dir};
 use paperclip::actix::{api_v2_operation, Apiv2Schema};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use super::actions;
+use super::camera;
+use super::cli;
+use super::command;
 use super::data;
+use super::definitions;
+use super::ftp;
+use super::geofence;
+use super::gimbal;
+use super::inventory;
+use super::logs;
 use super::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use super::metrics;
+use super::mission;
+use super::modes;
+use super::params;
+use super::rates;
+use super::snapshot;
+use super::statustext;
+use super::summary;
+use super::timeseries;
+use super::timesync;
+use super::transaction;
+use super::version;
+use super::views;
 use super::websocket_manager::WebsocketActor;
 
 use log::*;
+use mavlink::ardupilotmega::MavMissionType;
 use mavlink::Message;
 
 static HTML_DIST: Dir<'_> = include_dir!("src/html");
@@ -43,6 +70,24 @@ pub struct Info {
 pub struct WebsocketQuery {
     /// Regex filter to selected the desired MAVLink messages by name
     filter: Option<String>,
+    /// Decode enums and bitflags with their names and descriptions
+    decode: Option<bool>,
+    /// Add SI and display units of the fields next to the message
+    units: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct EventsWebsocketQuery {
+    /// Regex filter to select the desired event topics, E.g: vehicle_summary
+    filter: Option<String>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct MAVLinkQuery {
+    /// Decode enums and bitflags with their names and descriptions
+    decode: Option<bool>,
+    /// Add SI and display units of the fields next to the message
+    units: Option<bool>,
 }
 
 #[derive(Apiv2Schema, Deserialize)]
@@ -51,6 +96,226 @@ pub struct MAVLinkHelperQuery {
     name: String,
 }
 
+#[derive(Apiv2Schema, Deserialize)]
+pub struct MAVLinkWaitQuery {
+    /// MAVLink message name to wait for, E.g: COMMAND_ACK
+    name: String,
+    /// Only accept messages sent by this system ID
+    system_id: Option<u8>,
+    /// Only accept messages sent by this component ID
+    component_id: Option<u8>,
+    /// Maximum time to wait in milliseconds, default is 5000 and maximum is 60000
+    timeout: Option<u64>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct MAVLinkTransactionRequest {
+    /// MAVLink message to send, E.g: {"type": "PING", ...}
+    message: serde_json::Value,
+    /// Message that answers the transaction
+    response: transaction::Matcher,
+    /// Number of times that the message is sent, default is 3
+    attempts: Option<u8>,
+    /// Maximum time to wait in milliseconds, default is 5000 and maximum is 60000
+    timeout: Option<u64>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct VehicleModeRequest {
+    /// Name of the mode, E.g: GUIDED for ArduPilot or AUTO.MISSION for PX4
+    mode: String,
+    /// Maximum time to wait for the vehicle to change its mode in milliseconds, default is 5000
+    timeout: Option<u64>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct CommandRequest {
+    /// MAV_CMD name, E.g: MAV_CMD_COMPONENT_ARM_DISARM
+    command: String,
+    param1: Option<f32>,
+    param2: Option<f32>,
+    param3: Option<f32>,
+    param4: Option<f32>,
+    param5: Option<f32>,
+    param6: Option<f32>,
+    /// Also the z of COMMAND_INT
+    param7: Option<f32>,
+    /// MAV_FRAME name, sends the command as COMMAND_INT with x and y instead of param5 and param6
+    frame: Option<String>,
+    /// Integer x of COMMAND_INT, E.g: latitude in degE7
+    x: Option<i32>,
+    /// Integer y of COMMAND_INT, E.g: longitude in degE7
+    y: Option<i32>,
+    /// Component ID that receives the command, default is the autopilot
+    component_id: Option<u8>,
+    /// Maximum time to wait for the final result in milliseconds, default is 10000
+    timeout: Option<u64>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct FtpQuery {
+    /// Path in the component, E.g: /APM/LOGS or @PARAM/param.pck
+    path: String,
+    /// Component ID of the request, default is the autopilot
+    component_id: Option<u8>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct MessageRateRequest {
+    /// Rate in Hz, 0 disables the message and no value restores the default rate
+    rate: Option<f32>,
+    /// Component ID that sends the message, default is the autopilot
+    component_id: Option<u8>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct ParametersQuery {
+    /// Component ID that owns the parameters, default is the autopilot
+    component_id: Option<u8>,
+    /// Request the parameters from the vehicle instead of using the cache
+    refresh: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct ParameterSetRequest {
+    /// New value of the parameter, integer or float depending on its type
+    value: serde_json::Value,
+    /// Component ID that owns the parameter, default is the autopilot
+    component_id: Option<u8>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct ComponentQuery {
+    /// Component ID of the request, default is the autopilot
+    component_id: Option<u8>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct MissionCurrentRequest {
+    /// Sequence number of the new current item
+    seq: u16,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct CameraQuery {
+    /// Request the camera information, settings and storage again
+    refresh: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct PhotoRequest {
+    /// Seconds between photos, default is 1
+    interval: Option<f32>,
+    /// Number of photos, 0 takes photos until stopped, default is 1
+    count: Option<u32>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct CameraModeRequest {
+    /// Camera mode: IMAGE, VIDEO or IMAGE_SURVEY
+    mode: String,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct GimbalAttitudeRequest {
+    /// Pitch angle in degrees, positive is up
+    pitch: Option<f32>,
+    /// Yaw angle in degrees, positive is to the right
+    yaw: Option<f32>,
+    /// Yaw relative to north instead of the vehicle heading
+    yaw_lock: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct GimbalRateRequest {
+    /// Pitch rate in degrees per second
+    pitch_rate: Option<f32>,
+    /// Yaw rate in degrees per second
+    yaw_rate: Option<f32>,
+    /// Yaw relative to north instead of the vehicle heading
+    yaw_lock: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct GimbalRoiRequest {
+    /// Latitude in degrees
+    latitude: f64,
+    /// Longitude in degrees
+    longitude: f64,
+    /// Altitude relative to home in meters
+    altitude: f32,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct VersionQuery {
+    /// Component ID of the request, default is the autopilot
+    component_id: Option<u8>,
+    /// Request AUTOPILOT_VERSION again
+    refresh: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct ArmQuery {
+    /// Skip the safety checks of the autopilot
+    force: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct TakeoffRequest {
+    /// Altitude relative to home in meters
+    altitude: f32,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct GotoRequest {
+    /// Latitude in degrees
+    latitude: f64,
+    /// Longitude in degrees
+    longitude: f64,
+    /// Altitude relative to home in meters
+    altitude: f32,
+    /// Ground speed in m/s, default is the vehicle speed
+    speed: Option<f32>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct SpeedRequest {
+    /// Speed in m/s
+    speed: f32,
+    /// Change the airspeed instead of the ground speed, default is true only for planes
+    airspeed: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct StatusTextQuery {
+    /// Minimum severity of the texts, name or value, E.g: WARNING, MAV_SEVERITY_ERROR or 3
+    severity: Option<String>,
+    /// Start of the time range in RFC 3339, E.g: 2024-05-01T12:00:00Z
+    start: Option<String>,
+    /// End of the time range in RFC 3339, E.g: 2024-05-01T12:30:00Z
+    end: Option<String>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct TimeseriesStreamQuery {
+    /// Regex filter to select the MAVLink messages by name, E.g: ATTITUDE|GPS_RAW_INT
+    filter: Option<String>,
+}
+
+#[derive(Apiv2Schema, Deserialize)]
+pub struct TimeseriesCsvQuery {
+    /// Regex filter to select the MAVLink messages by name, E.g: ATTITUDE|GPS_RAW_INT
+    filter: Option<String>,
+    /// Start of the time range in RFC 3339, E.g: 2024-05-01T12:00:00Z
+    start: Option<String>,
+    /// End of the time range in RFC 3339, E.g: 2024-05-01T12:30:00Z
+    end: Option<String>,
+    /// Only export messages sent by this system ID
+    system_id: Option<u8>,
+    /// Only export messages sent by this component ID
+    component_id: Option<u8>,
+}
+
 fn load_html_file(filename: &str) -> Option<String> {
     if let Some(file) = HTML_DIST.get_file(filename) {
         return Some(file.contents_utf8().unwrap().to_string());
@@ -75,9 +340,9 @@ pub fn root(req: HttpRequest) -> HttpResponse {
         return HttpResponse::Ok().content_type(mime).body(content);
     };
 
-    return HttpResponse::NotFound()
+    HttpResponse::NotFound()
         .content_type("text/plain")
-        .body("File does not exist");
+        .body("File does not exist")
 }
 
 #[api_v2_operation]
@@ -97,6 +362,15 @@ pub async fn info() -> Json<Info> {
     Json(info)
 }
 
+#[api_v2_operation]
+/// Provides service and telemetry metrics in the Prometheus text format
+pub async fn metrics() -> actix_web::Result<HttpResponse> {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+        .await
+}
+
 #[api_v2_operation]
 /// Provide instructions on how to use this API
 pub async fn llm() -> actix_web::Result<HttpResponse> {
@@ -109,12 +383,40 @@ pub async fn llm() -> actix_web::Result<HttpResponse> {
 
 #[api_v2_operation]
 /// Provides an object containing all MAVLink messages received by the service
-pub async fn mavlink(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+pub async fn mavlink(
+    req: HttpRequest,
+    query: web::Query<MAVLinkQuery>,
+) -> actix_web::Result<HttpResponse> {
     let path = req.match_info().query("path");
-    let message = data::messages().pointer(path);
+    let options = views::Options {
+        decode: query.decode.unwrap_or(false),
+        units: query.units.unwrap_or(false),
+    };
+    let apply = |value: &mut serde_json::Value| options.apply(value);
+    let view: data::View = if options.is_empty() {
+        None
+    } else {
+        Some(&apply)
+    };
+    let message = data::pointer(path, view);
     ok_response(message).await
 }
 
+type TimeRange = (
+    Option<chrono::DateTime<chrono::Utc>>,
+    Option<chrono::DateTime<chrono::Utc>>,
+);
+
+fn parse_time_range(start: Option<String>, end: Option<String>) -> Result<TimeRange, String> {
+    let parse_time = |time: Option<String>| match time {
+        Some(time) => chrono::DateTime::parse_from_rfc3339(&time)
+            .map(|time| Some(time.with_timezone(&chrono::Utc)))
+            .map_err(|error| format!("Invalid time {time}: {error}")),
+        None => Ok(None),
+    };
+    Ok((parse_time(start)?, parse_time(end)?))
+}
+
 pub fn parse_query<T: serde::ser::Serialize>(message: &T) -> String {
     let error_message =
         "Not possible to parse mavlink message, please report this issue!".to_string();
@@ -137,12 +439,10 @@ pub async fn helper_mavlink(
 
     match result {
         Ok(result) => {
-            let msg = match result {
-                msg => parse_query(&data::MAVLinkMessage {
-                    header: mavlink::MavHeader::default(),
-                    message: msg,
-                }),
-            };
+            let msg = parse_query(&data::MAVLinkMessage {
+                header: mavlink::MavHeader::default(),
+                message: result,
+            });
 
             ok_response(msg).await
         }
@@ -151,7 +451,60 @@ pub async fn helper_mavlink(
 }
 
 #[api_v2_operation]
-#[allow(clippy::await_holding_lock)]
+/// Waits for the next MAVLink message matching the given name and IDs
+pub async fn mavlink_wait(query: web::Query<MAVLinkWaitQuery>) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+
+    if mavlink::ardupilotmega::MavMessage::message_id_from_name(&query.name).is_none() {
+        return not_found_response(parse_query(&"Invalid message name.")).await;
+    }
+
+    // Waits without a thread of the blocking pool, clients can wait for a long time
+    let timeout = Duration::from_millis(query.timeout.unwrap_or(5000).min(60000));
+    let result =
+        data::next_message(&query.name, query.system_id, query.component_id, timeout).await;
+
+    match result {
+        Some(message) => ok_response(parse_query(&message)).await,
+        None => timeout_response(parse_query(&"Timeout while waiting for message.")).await,
+    }
+}
+
+#[api_v2_operation]
+/// Send a MAVLink message and wait for the first received message that matches the response
+pub async fn mavlink_transaction(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    request: web::Json<MAVLinkTransactionRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let request = request.into_inner();
+    let message: mavlink::ardupilotmega::MavMessage = match serde_json::from_value(request.message)
+    {
+        Ok(message) => message,
+        Err(error) => {
+            return not_found_response(parse_query(&format!(
+                "Failed to parse message, not a valid MAVLink message: {error}"
+            )))
+            .await
+        }
+    };
+
+    let timeout = Duration::from_millis(request.timeout.unwrap_or(5000).min(60000));
+    let result = transaction::exchange_async(
+        data.get_ref(),
+        &message,
+        &request.response,
+        request.attempts.unwrap_or(3),
+        timeout,
+    )
+    .await
+    .map_err(actix_web::error::BlockingError::Error);
+    blocking_response(result, |error| {
+        matches!(error, transaction::TransactionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
 /// Send a MAVLink message for the desired vehicle
 pub async fn mavlink_post(
     data: web::Data<MAVLinkVehicleArcMutex>,
@@ -170,15 +523,22 @@ pub async fn mavlink_post(
 
     match json5::from_str::<data::MAVLinkMessage<mavlink::ardupilotmega::MavMessage>>(&json_string)
     {
-        Ok(content) => match data.lock().unwrap().send(&content.header, &content.message) {
-            Ok(_result) => {
-                data::update((content.header, content.message));
-                return HttpResponse::Ok().await;
-            }
-            Err(err) => {
-                return not_found_response(format!("Failed to send message: {err:?}")).await
+        Ok(content) => {
+            // The vehicle lock is released before any await
+            let result = data.lock().unwrap().send(&content.header, &content.message);
+            match result {
+                Ok(_result) => {
+                    data::update(
+                        (content.header, content.message),
+                        data::Reception::new("rest"),
+                    );
+                    return HttpResponse::Ok().await;
+                }
+                Err(err) => {
+                    return not_found_response(format!("Failed to send message: {err:?}")).await
+                }
             }
-        },
+        }
         Err(err) => {
             debug!("Failed to parse ardupilotmega message: {err:?}");
             let error_message =
@@ -190,24 +550,1556 @@ pub async fn mavlink_post(
 }
 
 #[api_v2_operation]
-/// Websocket used to receive and send MAVLink messages asynchronously
-pub async fn websocket(
+/// Download a snapshot with all MAVLink messages received by the service, the status texts and time series
+pub async fn snapshot() -> actix_web::Result<HttpResponse> {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"mavlink2rest-snapshot.json\"",
+        )
+        .body(snapshot::content())
+        .await
+}
+
+#[api_v2_operation]
+/// Save a snapshot with all MAVLink messages received by the service to the snapshot file
+pub async fn snapshot_save() -> actix_web::Result<HttpResponse> {
+    let path = match cli::snapshot_file() {
+        Some(path) => path,
+        None => {
+            return not_found_response(parse_query(
+                &"Snapshot file is not configured, check --snapshot-file.",
+            ))
+            .await
+        }
+    };
+
+    match snapshot::save(Path::new(path)) {
+        Ok(_) => HttpResponse::Ok().await,
+        Err(error) => {
+            internal_error_response(parse_query(&format!("Failed to save snapshot: {error}"))).await
+        }
+    }
+}
+
+#[api_v2_operation]
+/// Provides all systems and components with their type, autopilot, version and first/last seen times
+pub async fn inventory() -> actix_web::Result<HttpResponse> {
+    ok_response(parse_query(&inventory::inventory())).await
+}
+
+#[api_v2_operation]
+/// Provides a normalised summary of the vehicle state: armed, mode, position, battery and more
+pub async fn vehicle_summary(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let summary = req
+        .match_info()
+        .query("id")
+        .parse::<u8>()
+        .ok()
+        .and_then(summary::summary);
+
+    match summary {
+        Some(summary) => ok_response(parse_query(&summary)).await,
+        None => not_found_response(parse_query(&"Vehicle not found.")).await,
+    }
+}
+
+#[api_v2_operation]
+/// Change the mode of the vehicle by name, waits until the vehicle reports the new mode
+pub async fn vehicle_mode_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
     req: HttpRequest,
-    query: web::Query<WebsocketQuery>,
-    stream: web::Payload,
-) -> Result<HttpResponse, actix_web::Error> {
-    let filter = match query.into_inner().filter {
-        Some(filter) => filter,
-        _ => ".*".to_owned(),
+    request: web::Json<VehicleModeRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
     };
 
-    debug!("New websocket with filter {:#?}", &filter);
+    let request = request.into_inner();
+    let vehicle = data.get_ref().clone();
+    let timeout = Duration::from_millis(request.timeout.unwrap_or(5000).min(60000));
+    let result =
+        web::block(move || modes::set_mode(&vehicle, system_id, &request.mode, timeout)).await;
 
-    ws::start(WebsocketActor::new(filter), &req, stream)
+    let result = result.map(|mode| serde_json::json!({ "mode": mode }));
+    blocking_response(result, |error| {
+        matches!(error, modes::SetModeError::Timeout)
+    })
+    .await
 }
 
-async fn not_found_response(message: String) -> actix_web::Result<HttpResponse> {
-    HttpResponse::NotFound()
+#[api_v2_operation]
+/// Send a command to the vehicle and wait for its COMMAND_ACK
+pub async fn vehicle_command_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<CommandRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let request = request.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, request.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let mav_cmd = match serde_json::from_value(serde_json::json!({ "type": request.command })) {
+        Ok(mav_cmd) => mav_cmd,
+        Err(_) => return not_found_response(parse_query(&"Unknown command.")).await,
+    };
+    let params = [
+        request.param1.unwrap_or(0.0),
+        request.param2.unwrap_or(0.0),
+        request.param3.unwrap_or(0.0),
+        request.param4.unwrap_or(0.0),
+    ];
+    let command = match request.frame {
+        Some(frame) => {
+            let frame = match serde_json::from_value(serde_json::json!({ "type": frame })) {
+                Ok(frame) => frame,
+                Err(_) => return not_found_response(parse_query(&"Unknown frame.")).await,
+            };
+            // Floats can not hold positions in degE7, E.g: 2.7e8 is rounded to multiples of 32
+            if request.param5.is_some() || request.param6.is_some() {
+                return not_found_response(parse_query(
+                    &"Use x and y instead of param5 and param6 with a frame.",
+                ))
+                .await;
+            }
+            command::Command::int(
+                mav_cmd,
+                frame,
+                params,
+                request.x.unwrap_or(0),
+                request.y.unwrap_or(0),
+                request.param7.unwrap_or(0.0),
+            )
+        }
+        None => {
+            if request.x.is_some() || request.y.is_some() {
+                return not_found_response(parse_query(&"x and y need a frame.")).await;
+            }
+            let [param1, param2, param3, param4] = params;
+            command::Command::long(
+                mav_cmd,
+                [
+                    param1,
+                    param2,
+                    param3,
+                    param4,
+                    request.param5.unwrap_or(0.0),
+                    request.param6.unwrap_or(0.0),
+                    request.param7.unwrap_or(0.0),
+                ],
+            )
+        }
+    };
+
+    let vehicle = data.get_ref().clone();
+    let timeout = Duration::from_millis(request.timeout.unwrap_or(10000).min(60000));
+    let result =
+        web::block(move || command::send(&vehicle, system_id, component_id, &command, timeout))
+            .await;
+    blocking_response(result, |error| {
+        matches!(error, command::CommandError::Timeout(_))
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Arm the vehicle, force skips the safety checks
+pub async fn vehicle_arm(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ArmQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || actions::arm(&vehicle, system_id, true, query.force.unwrap_or(false)))
+            .await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Disarm the vehicle, force disarms it even while flying
+pub async fn vehicle_disarm(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ArmQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || actions::arm(&vehicle, system_id, false, query.force.unwrap_or(false)))
+            .await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Take off to an altitude relative to home, the vehicle should be armed
+pub async fn vehicle_takeoff(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<TakeoffRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || actions::takeoff(&vehicle, system_id, request.altitude)).await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Land at the current position
+pub async fn vehicle_land(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || actions::land(&vehicle, system_id)).await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Return to the launch position
+pub async fn vehicle_rtl(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || actions::return_to_launch(&vehicle, system_id)).await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Go to a position with the altitude relative to home, changing to the guided mode
+pub async fn vehicle_goto(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<GotoRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        actions::goto(
+            &vehicle,
+            system_id,
+            request.latitude,
+            request.longitude,
+            request.altitude,
+            request.speed,
+        )
+    })
+    .await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Change the ground speed or airspeed of the vehicle
+pub async fn vehicle_speed(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<SpeedRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        actions::change_speed(&vehicle, system_id, request.speed, request.airspeed)
+    })
+    .await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Reboot the autopilot
+pub async fn vehicle_reboot(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || actions::reboot(&vehicle, system_id)).await;
+    blocking_response(result, actions::ActionError::is_timeout).await
+}
+
+fn vehicle_and_component(req: &HttpRequest, component_id: Option<u8>) -> Option<(u8, u8)> {
+    let system_id = req.match_info().query("id").parse::<u8>().ok()?;
+    let component_id = component_id.unwrap_or_else(|| {
+        data::autopilot_heartbeat(system_id).map_or(1, |heartbeat| heartbeat.header.component_id)
+    });
+    Some((system_id, component_id))
+}
+
+// Answers with the result of a blocking request to the vehicle, timeouts are answered with 408
+async fn blocking_response<T: Serialize, E: std::fmt::Display + std::fmt::Debug>(
+    result: Result<T, actix_web::error::BlockingError<E>>,
+    is_timeout: impl Fn(&E) -> bool,
+) -> actix_web::Result<HttpResponse> {
+    match result {
+        Ok(content) => ok_response(parse_query(&content)).await,
+        Err(actix_web::error::BlockingError::Error(error)) if is_timeout(&error) => {
+            timeout_response(parse_query(&error.to_string())).await
+        }
+        Err(actix_web::error::BlockingError::Error(error)) => {
+            not_found_response(parse_query(&error.to_string())).await
+        }
+        Err(actix_web::error::BlockingError::Canceled) => {
+            not_found_response(parse_query(&"Request was canceled.")).await
+        }
+    }
+}
+
+#[api_v2_operation]
+/// Provides the parameters of a vehicle component, downloading all of them when refresh is used
+pub async fn vehicle_parameters(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ParametersQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    if !query.refresh.unwrap_or(false) {
+        return match params::table(system_id, component_id) {
+            Some(table) => ok_response(parse_query(&table)).await,
+            None => not_found_response(parse_query(
+                &"No parameters received from this component, use refresh=true to download them.",
+            ))
+            .await,
+        };
+    }
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || params::download(&vehicle, system_id, component_id)).await;
+    blocking_response(result, |error| {
+        matches!(error, params::ParameterError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Provides a parameter of a vehicle component, from the cache unless refresh is used
+pub async fn vehicle_parameter(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ParametersQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let name = req.match_info().query("name").to_string();
+    let refresh = query.refresh.unwrap_or(false);
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || params::get(&vehicle, system_id, component_id, &name, refresh)).await;
+    blocking_response(result, |error| {
+        matches!(error, params::ParameterError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Change a parameter of a vehicle component, waits until the vehicle confirms the new value
+pub async fn vehicle_parameter_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<ParameterSetRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let request = request.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, request.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let name = req.match_info().query("name").to_string();
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || params::set(&vehicle, system_id, component_id, &name, &request.value))
+            .await;
+    blocking_response(result, |error| {
+        matches!(error, params::ParameterError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Download the mission of the vehicle as a list of items
+pub async fn vehicle_mission(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::download(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_MISSION,
+        )
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Upload a mission to the vehicle, replacing the current one
+pub async fn vehicle_mission_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+    items: web::Json<Vec<mission::MissionItem>>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let items = items.into_inner();
+    let result = web::block(move || {
+        mission::upload(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_MISSION,
+            &items,
+        )
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Remove all mission items of the vehicle
+pub async fn vehicle_mission_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::clear(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_MISSION,
+        )
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Change the current mission item of the vehicle
+pub async fn vehicle_mission_current_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+    request: web::Json<MissionCurrentRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let seq = request.seq;
+    let result =
+        web::block(move || mission::set_current(&vehicle, system_id, component_id, seq)).await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Provides the progress of the last mission transfers of the vehicle
+pub async fn vehicle_mission_progress(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => ok_response(parse_query(&mission::progress(system_id))).await,
+        Err(_) => not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    }
+}
+
+#[api_v2_operation]
+/// Download the fences of the vehicle as a GeoJSON FeatureCollection
+pub async fn vehicle_fence(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::download(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_FENCE,
+        )
+        .and_then(|items| geofence::fence_from_items(&items))
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Upload polygon and circle fences, replacing the fences of the vehicle
+pub async fn vehicle_fence_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+    fence: web::Json<geofence::FeatureCollection>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+    let items = match geofence::fence_to_items(&fence) {
+        Ok(items) => items,
+        Err(error) => return not_found_response(parse_query(&error.to_string())).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::upload(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_FENCE,
+            &items,
+        )
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Remove all fences of the vehicle
+pub async fn vehicle_fence_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    mission_type_clear(
+        data,
+        req,
+        query.into_inner(),
+        MavMissionType::MAV_MISSION_TYPE_FENCE,
+    )
+    .await
+}
+
+#[api_v2_operation]
+/// Download the rally points of the vehicle as a GeoJSON FeatureCollection
+pub async fn vehicle_rally(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::download(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_RALLY,
+        )
+        .map(|items| geofence::rally_from_items(&items))
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Upload rally points, replacing the rally points of the vehicle
+pub async fn vehicle_rally_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+    rally: web::Json<geofence::FeatureCollection>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+    let items = match geofence::rally_to_items(&rally) {
+        Ok(items) => items,
+        Err(error) => return not_found_response(parse_query(&error.to_string())).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        mission::upload(
+            &vehicle,
+            system_id,
+            component_id,
+            MavMissionType::MAV_MISSION_TYPE_RALLY,
+            &items,
+        )
+    })
+    .await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Remove all rally points of the vehicle
+pub async fn vehicle_rally_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    mission_type_clear(
+        data,
+        req,
+        query.into_inner(),
+        MavMissionType::MAV_MISSION_TYPE_RALLY,
+    )
+    .await
+}
+
+async fn mission_type_clear(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: ComponentQuery,
+    mission_type: MavMissionType,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || mission::clear(&vehicle, system_id, component_id, mission_type)).await;
+    blocking_response(result, |error| {
+        matches!(error, mission::MissionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Provides the log of status texts of the vehicle, long texts are reassembled from their chunks
+pub async fn vehicle_statustext(
+    req: HttpRequest,
+    query: web::Query<StatusTextQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let system_id = match req.match_info().query("id").parse::<u8>() {
+        Ok(system_id) => system_id,
+        Err(_) => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let query = query.into_inner();
+    let severity = match query.severity {
+        Some(severity) => {
+            let name = severity.to_uppercase();
+            let value = severity.parse::<u8>().ok().or_else(|| {
+                let severities = definitions::enumeration("MAV_SEVERITY")?;
+                severities
+                    .entry(&name)
+                    .or_else(|| severities.entry(&format!("MAV_SEVERITY_{name}")))
+                    .map(|entry| entry.value as u8)
+            });
+            match value {
+                Some(value) => Some(value),
+                None => {
+                    return not_found_response(parse_query(&format!(
+                        "Invalid severity: {severity}"
+                    )))
+                    .await
+                }
+            }
+        }
+        None => None,
+    };
+
+    let (start, end) = match parse_time_range(query.start, query.end) {
+        Ok(range) => range,
+        Err(error) => return not_found_response(parse_query(&error)).await,
+    };
+
+    match statustext::texts(system_id, severity, start, end) {
+        Some(texts) => ok_response(parse_query(&texts)).await,
+        None => not_found_response(parse_query(&"Vehicle not found.")).await,
+    }
+}
+
+#[api_v2_operation]
+/// List a directory of the component with MAVLink FTP
+pub async fn vehicle_ftp_list(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || ftp::list(&vehicle, system_id, component_id, &query.path)).await;
+    blocking_response(result, |error| matches!(error, ftp::FtpError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// Download a file of the component with MAVLink FTP
+pub async fn vehicle_ftp_download(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let path = query.path.clone();
+    let result = web::block(move || ftp::download(&vehicle, system_id, component_id, &path)).await;
+    let (_, receiver) = match result {
+        Ok(download) => download,
+        Err(error) => {
+            return blocking_response(Err::<(), _>(error), |error| {
+                matches!(error, ftp::FtpError::Timeout)
+            })
+            .await
+        }
+    };
+
+    let filename = query
+        .path
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    // Chunked, a stream that fails before the end is not taken as a complete file
+    let stream = receiver.map(|chunk| {
+        chunk
+            .map(web::Bytes::from)
+            .map_err(actix_web::error::ErrorInternalServerError)
+    });
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .streaming(stream)
+        .await
+}
+
+#[api_v2_operation]
+/// Upload the request body as a file of the component with MAVLink FTP
+pub async fn vehicle_ftp_upload(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+    mut payload: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    // The file is written while the body is received, None marks its end
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let vehicle = data.get_ref().clone();
+    let upload =
+        web::block(move || ftp::upload(&vehicle, system_id, component_id, &query.path, receiver));
+    let mut body_error = None;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                body_error = Some(error);
+                break;
+            }
+        };
+        if sender.send(Some(chunk.to_vec())).is_err() {
+            break;
+        }
+    }
+    if body_error.is_none() {
+        let _ = sender.send(None);
+    }
+    drop(sender);
+
+    let result = upload.await.map(|size| serde_json::json!({ "size": size }));
+    if let Some(error) = body_error {
+        return Err(error.into());
+    }
+    blocking_response(result, |error| matches!(error, ftp::FtpError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// Remove a file of the component with MAVLink FTP
+pub async fn vehicle_ftp_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    ftp_remove(data, req, query.into_inner(), false).await
+}
+
+#[api_v2_operation]
+/// Create a directory in the component with MAVLink FTP
+pub async fn vehicle_ftp_directory_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || ftp::create_directory(&vehicle, system_id, component_id, &query.path))
+            .await;
+    blocking_response(result, |error| matches!(error, ftp::FtpError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// Remove an empty directory of the component with MAVLink FTP
+pub async fn vehicle_ftp_directory_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    ftp_remove(data, req, query.into_inner(), true).await
+}
+
+async fn ftp_remove(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: FtpQuery,
+    directory: bool,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || ftp::remove(&vehicle, system_id, component_id, &query.path, directory))
+            .await;
+    blocking_response(result, |error| matches!(error, ftp::FtpError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// CRC32 of a file calculated by the component with MAVLink FTP
+pub async fn vehicle_ftp_crc(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<FtpQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || ftp::crc32(&vehicle, system_id, component_id, &query.path))
+        .await
+        .map(|crc32| serde_json::json!({ "crc32": crc32 }));
+    blocking_response(result, |error| matches!(error, ftp::FtpError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// List the logs stored in the vehicle
+pub async fn vehicle_logs(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || logs::list(&vehicle, system_id, component_id)).await;
+    blocking_response(result, |error| matches!(error, logs::LogError::Timeout)).await
+}
+
+#[api_v2_operation]
+/// Download a log of the vehicle as a .bin file
+pub async fn vehicle_log(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+    let id = match req.match_info().query("log_id").parse::<u16>() {
+        Ok(id) => id,
+        Err(_) => return not_found_response(parse_query(&"Invalid log ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || logs::download(&vehicle, system_id, component_id, id)).await;
+    let (_, receiver) = match result {
+        Ok(download) => download,
+        Err(error) => {
+            return blocking_response(Err::<(), _>(error), |error| {
+                matches!(error, logs::LogError::Timeout)
+            })
+            .await
+        }
+    };
+
+    // Chunked, the log can end before the size reported by the vehicle
+    let stream = receiver.map(|chunk| {
+        chunk
+            .map(web::Bytes::from)
+            .map_err(actix_web::error::ErrorInternalServerError)
+    });
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"log_{id}.bin\""),
+        )
+        .streaming(stream)
+        .await
+}
+
+#[api_v2_operation]
+/// Erase all logs stored in the vehicle
+pub async fn vehicle_logs_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || logs::erase(&vehicle, system_id, component_id)).await;
+    blocking_response(result, |error| matches!(error, logs::LogError::Timeout)).await
+}
+
+fn is_rate_timeout(error: &rates::RateError) -> bool {
+    matches!(
+        error,
+        rates::RateError::Command(command::CommandError::Timeout(_))
+    )
+}
+
+#[api_v2_operation]
+/// Provides the interval of a message configured in the vehicle and its received rate
+pub async fn vehicle_message_rate(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let name = req.match_info().query("message").to_string();
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || rates::get(&vehicle, system_id, component_id, &name)).await;
+    blocking_response(result, is_rate_timeout).await
+}
+
+#[api_v2_operation]
+/// Change the rate of a message sent by the vehicle
+pub async fn vehicle_message_rate_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<MessageRateRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let request = request.into_inner();
+    let (system_id, component_id) = match vehicle_and_component(&req, request.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    let name = req.match_info().query("message").to_string();
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || rates::set(&vehicle, system_id, component_id, &name, request.rate))
+            .await;
+    blocking_response(result, is_rate_timeout).await
+}
+
+#[api_v2_operation]
+/// Provides the message rates applied to every autopilot when it appears
+pub async fn rates_profile() -> actix_web::Result<HttpResponse> {
+    ok_response(parse_query(&rates::profile())).await
+}
+
+#[api_v2_operation]
+/// Replace the message rates applied to every autopilot, online autopilots are updated
+pub async fn rates_profile_post(
+    profile: web::Json<std::collections::BTreeMap<String, f32>>,
+) -> actix_web::Result<HttpResponse> {
+    // The rates worker applies the new profile to the online autopilots
+    if let Err(error) = rates::set_profile(profile.into_inner()) {
+        return not_found_response(parse_query(&error.to_string())).await;
+    }
+    ok_response(parse_query(&rates::profile())).await
+}
+
+#[api_v2_operation]
+/// Provides all camera components with their latest information, settings and storage
+pub async fn cameras() -> actix_web::Result<HttpResponse> {
+    ok_response(parse_query(&camera::cameras())).await
+}
+
+fn camera_ids(req: &HttpRequest) -> Option<(u8, u8)> {
+    let camera_id = req.match_info().query("camera_id").parse::<u8>().ok()?;
+    vehicle_and_component(req, Some(camera_id))
+}
+
+#[api_v2_operation]
+/// Provides a camera with its information, settings and storage, requesting them again with refresh
+pub async fn vehicle_camera(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<CameraQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+
+    if !query.refresh.unwrap_or(false) {
+        return match camera::cameras()
+            .into_iter()
+            .find(|camera| camera.system_id == system_id && camera.component_id == component_id)
+        {
+            Some(camera) => ok_response(parse_query(&camera)).await,
+            None => not_found_response(parse_query(&"Camera not found, try refresh.")).await,
+        };
+    }
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || camera::refresh(&vehicle, system_id, component_id)).await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Take a photo, or photos at an interval until the count is reached or the capture is stopped
+pub async fn vehicle_camera_photo_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<PhotoRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+    let interval = request.interval.unwrap_or(1.0);
+    let count = request.count.unwrap_or(1);
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        camera::start_photos(&vehicle, system_id, component_id, interval, count)
+    })
+    .await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Stop an interval photo capture
+pub async fn vehicle_camera_photo_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || camera::stop_photos(&vehicle, system_id, component_id)).await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Start a video recording
+pub async fn vehicle_camera_video_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || camera::start_video(&vehicle, system_id, component_id)).await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Stop a video recording
+pub async fn vehicle_camera_video_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || camera::stop_video(&vehicle, system_id, component_id)).await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Change the camera mode
+pub async fn vehicle_camera_mode_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<CameraModeRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || camera::set_mode(&vehicle, system_id, component_id, &request.mode))
+            .await;
+    blocking_response(result, camera::CameraError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Provides the images captured by a camera with their position and orientation
+pub async fn vehicle_camera_images(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match camera_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or camera ID.")).await,
+    };
+    ok_response(parse_query(&camera::images(system_id, component_id))).await
+}
+
+#[api_v2_operation]
+/// Provides all gimbals with their limits, control and attitude in degrees
+pub async fn gimbals() -> actix_web::Result<HttpResponse> {
+    ok_response(parse_query(&gimbal::gimbals())).await
+}
+
+fn gimbal_ids(req: &HttpRequest) -> Option<(u8, u8)> {
+    let system_id = req.match_info().query("id").parse::<u8>().ok()?;
+    let gimbal_id = req.match_info().query("gimbal_id").parse::<u8>().ok()?;
+    Some((system_id, gimbal_id))
+}
+
+#[api_v2_operation]
+/// Provides a gimbal with its limits, control and attitude in degrees
+pub async fn vehicle_gimbal(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+    match gimbal::find(system_id, gimbal_id) {
+        Ok(gimbal) => ok_response(parse_query(&gimbal)).await,
+        Err(error) => not_found_response(parse_query(&error.to_string())).await,
+    }
+}
+
+#[api_v2_operation]
+/// Take the primary control of a gimbal
+pub async fn vehicle_gimbal_control_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || gimbal::set_control(&vehicle, system_id, gimbal_id, true)).await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Release the control of a gimbal
+pub async fn vehicle_gimbal_control_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result =
+        web::block(move || gimbal::set_control(&vehicle, system_id, gimbal_id, false)).await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Point a gimbal to pitch and yaw angles in degrees, taking control if needed
+pub async fn vehicle_gimbal_attitude_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<GimbalAttitudeRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        gimbal::pitch_yaw(
+            &vehicle,
+            system_id,
+            gimbal_id,
+            [request.pitch, request.yaw],
+            [None, None],
+            request.yaw_lock.unwrap_or(false),
+        )
+    })
+    .await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Move a gimbal with pitch and yaw rates in degrees per second, taking control if needed
+pub async fn vehicle_gimbal_rate_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<GimbalRateRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        gimbal::pitch_yaw(
+            &vehicle,
+            system_id,
+            gimbal_id,
+            [None, None],
+            [request.pitch_rate, request.yaw_rate],
+            request.yaw_lock.unwrap_or(false),
+        )
+    })
+    .await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Point a gimbal to a location, taking control if needed
+pub async fn vehicle_gimbal_roi_post(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    request: web::Json<GimbalRoiRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || {
+        gimbal::set_roi(
+            &vehicle,
+            system_id,
+            gimbal_id,
+            request.latitude,
+            request.longitude,
+            request.altitude,
+        )
+    })
+    .await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Stop pointing a gimbal to a location
+pub async fn vehicle_gimbal_roi_delete(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, gimbal_id) = match gimbal_ids(&req) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle or gimbal ID.")).await,
+    };
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || gimbal::clear_roi(&vehicle, system_id, gimbal_id)).await;
+    blocking_response(result, gimbal::GimbalError::is_timeout).await
+}
+
+#[api_v2_operation]
+/// Provides the clock offset, drift and round-trip time of all components estimated with TIMESYNC
+pub async fn timesync() -> actix_web::Result<HttpResponse> {
+    ok_response(parse_query(&timesync::clocks())).await
+}
+
+#[api_v2_operation]
+/// Provides the clock offset, drift and round-trip time of a vehicle component estimated with TIMESYNC
+pub async fn vehicle_timesync(
+    req: HttpRequest,
+    query: web::Query<ComponentQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+    match timesync::clock(system_id, component_id) {
+        Some(clock) => ok_response(parse_query(&clock)).await,
+        None => not_found_response(parse_query(&"Component did not answer TIMESYNC.")).await,
+    }
+}
+
+#[api_v2_operation]
+/// Provides the decoded AUTOPILOT_VERSION: software versions, git hashes and capabilities
+pub async fn vehicle_version(
+    data: web::Data<MAVLinkVehicleArcMutex>,
+    req: HttpRequest,
+    query: web::Query<VersionQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (system_id, component_id) = match vehicle_and_component(&req, query.component_id) {
+        Some(ids) => ids,
+        None => return not_found_response(parse_query(&"Invalid vehicle ID.")).await,
+    };
+
+    if !query.refresh.unwrap_or(false) {
+        if let Some(version) = version::version(system_id, component_id) {
+            return ok_response(parse_query(&version)).await;
+        }
+    }
+
+    let vehicle = data.get_ref().clone();
+    let result = web::block(move || version::request(&vehicle, system_id, component_id)).await;
+    blocking_response(result, |error| {
+        matches!(error, transaction::TransactionError::Timeout)
+    })
+    .await
+}
+
+#[api_v2_operation]
+/// Stream the received MAVLink messages in InfluxDB line protocol
+pub async fn timeseries_influxdb(
+    query: web::Query<TimeseriesStreamQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let filter = query.into_inner().filter.unwrap_or_else(|| ".*".to_owned());
+    let filter = match Regex::new(&filter) {
+        Ok(filter) => filter,
+        Err(error) => {
+            return not_found_response(parse_query(&format!("Invalid filter: {error}"))).await
+        }
+    };
+
+    let stream =
+        timeseries::stream(filter).map(|line| Ok::<_, actix_web::Error>(web::Bytes::from(line)));
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .streaming(stream)
+        .await
+}
+
+#[api_v2_operation]
+/// Download the recorded MAVLink messages of a time range as CSV, check --timeseries-filter
+pub async fn timeseries_csv(
+    query: web::Query<TimeseriesCsvQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let filter = match Regex::new(query.filter.as_deref().unwrap_or(".*")) {
+        Ok(filter) => filter,
+        Err(error) => {
+            return not_found_response(parse_query(&format!("Invalid filter: {error}"))).await
+        }
+    };
+
+    let (start, end) = match parse_time_range(query.start, query.end) {
+        Ok(range) => range,
+        Err(error) => return not_found_response(parse_query(&error)).await,
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"mavlink2rest-timeseries.csv\"",
+        )
+        .body(timeseries::csv(
+            &filter,
+            start,
+            end,
+            query.system_id,
+            query.component_id,
+        ))
+        .await
+}
+
+#[api_v2_operation]
+/// Websocket used to receive and send MAVLink messages asynchronously
+pub async fn websocket(
+    req: HttpRequest,
+    query: web::Query<WebsocketQuery>,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+    let filter = match query.filter {
+        Some(filter) => filter,
+        _ => ".*".to_owned(),
+    };
+
+    debug!("New websocket with filter {:#?}", &filter);
+
+    ws::start(
+        WebsocketActor::new(
+            filter,
+            views::Options {
+                decode: query.decode.unwrap_or(false),
+                units: query.units.unwrap_or(false),
+            },
+        ),
+        &req,
+        stream,
+    )
+}
+
+#[api_v2_operation]
+/// Websocket used to receive events of the service, like vehicle summary changes
+pub async fn websocket_events(
+    req: HttpRequest,
+    query: web::Query<EventsWebsocketQuery>,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = query.into_inner().filter.unwrap_or_else(|| ".*".to_owned());
+
+    debug!("New events websocket with filter {:#?}", &filter);
+
+    ws::start(WebsocketActor::events(filter), &req, stream)
+}
+
+#[api_v2_operation]
+/// Websocket used to control gimbals with pitch and yaw angles or rates, it streams the gimbal attitudes
+pub async fn websocket_gimbal(
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    debug!("New gimbal websocket");
+
+    ws::start(WebsocketActor::gimbals(), &req, stream)
+}
+
+async fn not_found_response(message: String) -> actix_web::Result<HttpResponse> {
+    HttpResponse::NotFound()
+        .content_type("application/json")
+        .body(message)
+        .await
+}
+
+async fn timeout_response(message: String) -> actix_web::Result<HttpResponse> {
+    HttpResponse::RequestTimeout()
+        .content_type("application/json")
+        .body(message)
+        .await
+}
+
+async fn internal_error_response(message: String) -> actix_web::Result<HttpResponse> {
+    HttpResponse::InternalServerError()
         .content_type("application/json")
         .body(message)
         .await
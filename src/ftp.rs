@@ -0,0 +1,537 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use log::*;
+use mavlink::ardupilotmega::{MavMessage, MavProtocolCapability, FILE_TRANSFER_PROTOCOL_DATA};
+use serde::Serialize;
+
+use crate::data::{self, MAVLinkMessage};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{self, Protocol, RETRY_TIMEOUT, TRANSFER_RETRIES};
+use crate::version;
+
+// Size of the FILE_TRANSFER_PROTOCOL payload and of its header
+const PAYLOAD_SIZE: usize = 251;
+const HEADER_SIZE: usize = 12;
+const MAX_DATA_SIZE: usize = PAYLOAD_SIZE - HEADER_SIZE;
+
+// Opcodes of the MAVLink FTP protocol
+const TERMINATE_SESSION: u8 = 1;
+const LIST_DIRECTORY: u8 = 3;
+const OPEN_FILE_RO: u8 = 4;
+const CREATE_FILE: u8 = 6;
+const WRITE_FILE: u8 = 7;
+const REMOVE_FILE: u8 = 8;
+const CREATE_DIRECTORY: u8 = 9;
+const REMOVE_DIRECTORY: u8 = 10;
+const CALC_FILE_CRC32: u8 = 14;
+const BURST_READ_FILE: u8 = 15;
+const ACK: u8 = 128;
+const NAK: u8 = 129;
+
+// NAK error codes with extra handling
+const NAK_FAIL_ERRNO: u8 = 2;
+const NAK_EOF: u8 = 6;
+
+const NAK_ERRORS: [&str; 11] = [
+    "None",
+    "Fail",
+    "FailErrno",
+    "InvalidDataSize",
+    "InvalidSession",
+    "NoSessionsAvailable",
+    "EOF",
+    "UnknownCommand",
+    "FileExists",
+    "FileProtected",
+    "FileNotFound",
+];
+
+// Only one operation per component, autopilots have few sessions
+fn component_lock(system_id: u8, component_id: u8) -> Arc<Mutex<()>> {
+    transaction::component_lock(Protocol::Ftp, system_id, component_id)
+}
+
+fn check_support(system_id: u8, component_id: u8) -> Result<(), FtpError> {
+    if !version::supports(
+        system_id,
+        component_id,
+        MavProtocolCapability::MAV_PROTOCOL_CAPABILITY_FTP,
+    ) {
+        return Err(FtpError::Unsupported);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum FtpError {
+    InvalidPath(String),
+    Send(std::io::Error),
+    Timeout,
+    /// Vehicle answered with a NAK, E.g: FileNotFound
+    Nak(String),
+    /// Capabilities of the component do not include MAVLink FTP
+    Unsupported,
+    /// File ended before the size reported when it was opened, contains the received and expected sizes
+    Truncated(u32, u32),
+    /// Chunks of an upload stopped before the end of the file
+    Aborted,
+}
+
+impl std::fmt::Display for FtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidPath(path) => write!(f, "Invalid path: {path}"),
+            Self::Send(error) => write!(f, "Failed to send message: {error}"),
+            Self::Timeout => write!(f, "Timeout while waiting for the vehicle."),
+            Self::Nak(error) => write!(f, "Vehicle answered with an error: {error}"),
+            Self::Unsupported => write!(f, "Component does not support MAVLink FTP."),
+            Self::Truncated(received, size) => {
+                write!(f, "File ended after {received} of {size} bytes.")
+            }
+            Self::Aborted => write!(f, "Upload was aborted before the end of the file."),
+        }
+    }
+}
+
+/// Entry of a directory listing
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    /// "file" or "directory"
+    pub kind: &'static str,
+    /// Size in bytes of files
+    pub size: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Packet {
+    seq: u16,
+    session: u8,
+    opcode: u8,
+    /// Size of the data, or number of bytes to read
+    size: u8,
+    req_opcode: u8,
+    burst_complete: bool,
+    offset: u32,
+    data: Vec<u8>,
+}
+
+impl Packet {
+    fn encode(&self) -> [u8; PAYLOAD_SIZE] {
+        let mut payload = [0; PAYLOAD_SIZE];
+        let size = self.data.len().min(MAX_DATA_SIZE);
+        payload[0..2].copy_from_slice(&self.seq.to_le_bytes());
+        payload[2] = self.session;
+        payload[3] = self.opcode;
+        payload[4] = self.size;
+        payload[5] = self.req_opcode;
+        payload[6] = self.burst_complete as u8;
+        payload[8..12].copy_from_slice(&self.offset.to_le_bytes());
+        payload[HEADER_SIZE..HEADER_SIZE + size].copy_from_slice(&self.data[..size]);
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < HEADER_SIZE {
+            return None;
+        }
+        let size = (payload[4] as usize).min(payload.len() - HEADER_SIZE);
+        Some(Self {
+            seq: u16::from_le_bytes([payload[0], payload[1]]),
+            session: payload[2],
+            opcode: payload[3],
+            size: size as u8,
+            req_opcode: payload[5],
+            burst_complete: payload[6] != 0,
+            offset: u32::from_le_bytes([payload[8], payload[9], payload[10], payload[11]]),
+            data: payload[HEADER_SIZE..HEADER_SIZE + size].to_vec(),
+        })
+    }
+
+    fn nak_error(&self) -> FtpError {
+        let code = self.data.first().copied().unwrap_or(0);
+        let name = NAK_ERRORS.get(code as usize).copied().unwrap_or("Unknown");
+        match (code, self.data.get(1)) {
+            (NAK_FAIL_ERRNO, Some(errno)) => FtpError::Nak(format!("{name} (errno {errno})")),
+            _ => FtpError::Nak(name.to_string()),
+        }
+    }
+
+    fn is_eof(&self) -> bool {
+        self.opcode == NAK && self.data.first() == Some(&NAK_EOF)
+    }
+}
+
+fn path_data(path: &str) -> Result<Vec<u8>, FtpError> {
+    if path.is_empty() || path.len() > MAX_DATA_SIZE {
+        return Err(FtpError::InvalidPath(path.to_string()));
+    }
+    Ok(path.as_bytes().to_vec())
+}
+
+fn u32_data(packet: &Packet) -> Option<u32> {
+    let bytes = packet.data.get(0..4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+struct Session {
+    vehicle: MAVLinkVehicleArcMutex,
+    receiver: mpsc::Receiver<MAVLinkMessage<MavMessage>>,
+    system_id: u8,
+    component_id: u8,
+    seq: u16,
+    session: u8,
+}
+
+impl Session {
+    fn new(vehicle: &MAVLinkVehicleArcMutex, system_id: u8, component_id: u8) -> Self {
+        Self {
+            vehicle: vehicle.clone(),
+            receiver: data::subscribe(),
+            system_id,
+            component_id,
+            seq: 0,
+            session: 0,
+        }
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), FtpError> {
+        let message = MavMessage::FILE_TRANSFER_PROTOCOL(FILE_TRANSFER_PROTOCOL_DATA {
+            target_network: 0,
+            target_system: self.system_id,
+            target_component: self.component_id,
+            payload: packet.encode(),
+        });
+        self.vehicle
+            .lock()
+            .unwrap()
+            .send_default(&message)
+            .map(|_| ())
+            .map_err(FtpError::Send)
+    }
+
+    // Next FTP packet of the component until the deadline
+    fn receive(&self, deadline: Instant) -> Option<Packet> {
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let answer = self.receiver.recv_timeout(remaining).ok()?;
+            if answer.header.system_id != self.system_id
+                || answer.header.component_id != self.component_id
+            {
+                continue;
+            }
+            if let MavMessage::FILE_TRANSFER_PROTOCOL(ftp) = &answer.message {
+                if let Some(packet) = Packet::decode(&ftp.payload[..]) {
+                    return Some(packet);
+                }
+            }
+        }
+        None
+    }
+
+    // Sends the request until it's answered, retries use the same sequence so the
+    // component can repeat its last answer instead of running the operation again
+    fn exchange(&mut self, opcode: u8, offset: u32, data: Vec<u8>) -> Result<Packet, FtpError> {
+        let request = Packet {
+            seq: self.seq,
+            session: self.session,
+            opcode,
+            size: data.len().min(MAX_DATA_SIZE) as u8,
+            offset,
+            data,
+            ..Default::default()
+        };
+
+        for _ in 0..TRANSFER_RETRIES {
+            self.send(&request)?;
+
+            let deadline = Instant::now() + RETRY_TIMEOUT;
+            while let Some(answer) = self.receive(deadline) {
+                if answer.req_opcode != opcode || answer.seq != request.seq.wrapping_add(1) {
+                    continue;
+                }
+                self.seq = answer.seq.wrapping_add(1);
+                return match answer.opcode {
+                    ACK => Ok(answer),
+                    _ if answer.is_eof() => Ok(answer),
+                    _ => Err(answer.nak_error()),
+                };
+            }
+        }
+
+        Err(FtpError::Timeout)
+    }
+
+    fn terminate(&mut self) {
+        if let Err(error) = self.exchange(TERMINATE_SESSION, 0, vec![]) {
+            warn!("Failed to terminate FTP session: {error}");
+        }
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<Entry>, FtpError> {
+        let path = path_data(path)?;
+        let mut entries = vec![];
+        let mut index = 0;
+        loop {
+            let answer = self.exchange(LIST_DIRECTORY, index, path.clone())?;
+            if answer.is_eof() {
+                return Ok(entries);
+            }
+
+            // Entries are separated by NUL, E.g: "Ffile.txt\t42\0Dlogs\0Sskipped\0"
+            let names: Vec<&[u8]> = answer
+                .data
+                .split(|byte| *byte == 0)
+                .filter(|entry| !entry.is_empty())
+                .collect();
+            if names.is_empty() {
+                return Ok(entries);
+            }
+            index += names.len() as u32;
+
+            for name in names {
+                let text = String::from_utf8_lossy(&name[1..]).to_string();
+                match name[0] {
+                    b'F' => {
+                        let mut fields = text.splitn(2, '\t');
+                        entries.push(Entry {
+                            name: fields.next().unwrap_or_default().to_string(),
+                            kind: "file",
+                            size: fields.next().and_then(|size| size.parse().ok()),
+                        });
+                    }
+                    b'D' if text != "." && text != ".." => entries.push(Entry {
+                        name: text,
+                        kind: "directory",
+                        size: None,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Opens the file for reading and returns its size
+    fn open_read(&mut self, path: &str) -> Result<u32, FtpError> {
+        let answer = self.exchange(OPEN_FILE_RO, 0, path_data(path)?)?;
+        if answer.is_eof() {
+            return Err(answer.nak_error());
+        }
+        self.session = answer.session;
+        Ok(u32_data(&answer).unwrap_or(0))
+    }
+
+    // Reads the open file with bursts, gaps are requested again from the first missing offset
+    fn read(&mut self, size: u32, mut write: impl FnMut(Vec<u8>) -> bool) -> Result<(), FtpError> {
+        let mut offset = 0;
+        let mut retries = 0;
+
+        while offset < size {
+            let request = Packet {
+                seq: self.seq,
+                session: self.session,
+                opcode: BURST_READ_FILE,
+                size: MAX_DATA_SIZE as u8,
+                offset,
+                ..Default::default()
+            };
+            self.send(&request)?;
+
+            let start_offset = offset;
+            let mut deadline = Instant::now() + RETRY_TIMEOUT;
+            while let Some(answer) = self.receive(deadline) {
+                if answer.req_opcode != BURST_READ_FILE || answer.session != self.session {
+                    continue;
+                }
+                self.seq = answer.seq.wrapping_add(1);
+                if answer.opcode == NAK {
+                    return match answer.is_eof() {
+                        true => Err(FtpError::Truncated(offset, size)),
+                        false => Err(answer.nak_error()),
+                    };
+                }
+
+                // Out of order packets are dropped and read again in the next burst
+                if answer.offset == offset && !answer.data.is_empty() {
+                    offset += answer.data.len() as u32;
+                    if !write(answer.data) {
+                        return Ok(());
+                    }
+                }
+                if answer.burst_complete || offset >= size {
+                    break;
+                }
+                deadline = Instant::now() + RETRY_TIMEOUT;
+            }
+
+            match offset == start_offset {
+                true => retries += 1,
+                false => retries = 0,
+            }
+            if retries >= TRANSFER_RETRIES {
+                return Err(FtpError::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    // None marks the end of the file, a sender dropped before it aborts the write
+    fn write(
+        &mut self,
+        path: &str,
+        chunks: mpsc::Receiver<Option<Vec<u8>>>,
+    ) -> Result<u32, FtpError> {
+        let answer = self.exchange(CREATE_FILE, 0, path_data(path)?)?;
+        self.session = answer.session;
+
+        let mut offset = 0;
+        loop {
+            let chunk = match chunks.recv() {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return Ok(offset),
+                Err(_) => return Err(FtpError::Aborted),
+            };
+            for data in chunk.chunks(MAX_DATA_SIZE) {
+                self.exchange(WRITE_FILE, offset, data.to_vec())?;
+                offset += data.len() as u32;
+            }
+        }
+    }
+
+    // Runs an operation that does not need a session
+    fn request(&mut self, opcode: u8, path: &str) -> Result<Packet, FtpError> {
+        let answer = self.exchange(opcode, 0, path_data(path)?)?;
+        match answer.is_eof() {
+            true => Err(answer.nak_error()),
+            false => Ok(answer),
+        }
+    }
+}
+
+/// Lists the files and directories of a path, E.g: "/" or "@SYS"
+pub fn list(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<Vec<Entry>, FtpError> {
+    check_support(system_id, component_id)?;
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    Session::new(vehicle, system_id, component_id).list(path)
+}
+
+/// Opens the file and streams its content, the returned value is the file size.
+/// The stream ends with an error if the file is shorter than its size.
+#[allow(clippy::type_complexity)]
+pub fn download(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<(u32, UnboundedReceiver<Result<Vec<u8>, FtpError>>), FtpError> {
+    check_support(system_id, component_id)?;
+    let (opened_sender, opened_receiver) = mpsc::channel();
+    let (sender, receiver) = unbounded();
+    let vehicle = vehicle.clone();
+    let path = path.to_string();
+
+    std::thread::spawn(move || {
+        let lock = component_lock(system_id, component_id);
+        let _guard = lock.lock().unwrap();
+        let mut session = Session::new(&vehicle, system_id, component_id);
+        let size = match session.open_read(&path) {
+            Ok(size) => size,
+            Err(error) => {
+                let _ = opened_sender.send(Err(error));
+                return;
+            }
+        };
+        let _ = opened_sender.send(Ok(size));
+
+        // Stops reading when the client disconnects
+        let result = session.read(size, |data| sender.unbounded_send(Ok(data)).is_ok());
+        if let Err(error) = result {
+            warn!("FTP download of {path} failed: {error}");
+            let _ = sender.unbounded_send(Err(error));
+        }
+        session.terminate();
+    });
+
+    let size = opened_receiver.recv().unwrap_or(Err(FtpError::Timeout))?;
+    Ok((size, receiver))
+}
+
+/// Creates or truncates the file and writes the chunks until a None marks the end,
+/// the returned value is the number of bytes written.
+/// The partial file is removed when the sender is dropped before the end.
+pub fn upload(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+    chunks: mpsc::Receiver<Option<Vec<u8>>>,
+) -> Result<u32, FtpError> {
+    check_support(system_id, component_id)?;
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let mut session = Session::new(vehicle, system_id, component_id);
+    let result = session.write(path, chunks);
+    session.terminate();
+    if let Err(FtpError::Aborted) = result {
+        if let Err(error) = session.request(REMOVE_FILE, path) {
+            warn!("Failed to remove partial upload {path}: {error}");
+        }
+    }
+    result
+}
+
+/// Removes a file, or an empty directory
+pub fn remove(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+    directory: bool,
+) -> Result<(), FtpError> {
+    check_support(system_id, component_id)?;
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let opcode = match directory {
+        true => REMOVE_DIRECTORY,
+        false => REMOVE_FILE,
+    };
+    Session::new(vehicle, system_id, component_id)
+        .request(opcode, path)
+        .map(|_| ())
+}
+
+pub fn create_directory(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<(), FtpError> {
+    check_support(system_id, component_id)?;
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    Session::new(vehicle, system_id, component_id)
+        .request(CREATE_DIRECTORY, path)
+        .map(|_| ())
+}
+
+/// CRC32 of a file calculated by the component
+pub fn crc32(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<u32, FtpError> {
+    check_support(system_id, component_id)?;
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let answer = Session::new(vehicle, system_id, component_id).request(CALC_FILE_CRC32, path)?;
+    u32_data(&answer).ok_or_else(|| FtpError::Nak("InvalidDataSize".to_string()))
+}
@@ -0,0 +1,382 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::command::degrees_e7;
+use crate::data::is_false;
+use crate::mission::{MissionError, MissionItem};
+
+const POLYGON_INCLUSION: &str = "MAV_CMD_NAV_FENCE_POLYGON_VERTEX_INCLUSION";
+const POLYGON_EXCLUSION: &str = "MAV_CMD_NAV_FENCE_POLYGON_VERTEX_EXCLUSION";
+const CIRCLE_INCLUSION: &str = "MAV_CMD_NAV_FENCE_CIRCLE_INCLUSION";
+const CIRCLE_EXCLUSION: &str = "MAV_CMD_NAV_FENCE_CIRCLE_EXCLUSION";
+const RETURN_POINT: &str = "MAV_CMD_NAV_FENCE_RETURN_POINT";
+const RALLY_POINT: &str = "MAV_CMD_NAV_RALLY_POINT";
+
+fn feature_collection_type() -> String {
+    "FeatureCollection".to_string()
+}
+
+fn feature_type() -> String {
+    "Feature".to_string()
+}
+
+fn default_inclusion() -> bool {
+    true
+}
+
+/// GeoJSON FeatureCollection of fences or rally points
+#[derive(Apiv2Schema, Clone, Debug, Deserialize, Serialize)]
+pub struct FeatureCollection {
+    #[serde(rename = "type", default = "feature_collection_type")]
+    pub kind: String,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Apiv2Schema, Clone, Debug, Deserialize, Serialize)]
+pub struct Feature {
+    #[serde(rename = "type", default = "feature_type")]
+    pub kind: String,
+    pub geometry: Geometry,
+    #[serde(default)]
+    pub properties: Properties,
+}
+
+/// GeoJSON geometry, coordinates are [longitude, latitude] or [longitude, latitude, altitude]
+#[derive(Apiv2Schema, Clone, Debug, Deserialize, Serialize)]
+pub struct Geometry {
+    /// Polygon or Point
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub coordinates: Value,
+}
+
+#[derive(Apiv2Schema, Clone, Debug, Deserialize, Serialize)]
+pub struct Properties {
+    /// Inclusion or exclusion zone, default is inclusion
+    #[serde(default = "default_inclusion")]
+    pub inclusion: bool,
+    /// Radius in meters of a circular zone, its geometry is the center Point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f32>,
+    /// Point where the vehicle goes when the fence is breached
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub return_point: bool,
+}
+
+impl Default for Properties {
+    fn default() -> Self {
+        Self {
+            inclusion: default_inclusion(),
+            radius: None,
+            return_point: false,
+        }
+    }
+}
+
+fn invalid(error: impl Into<String>) -> MissionError {
+    MissionError::InvalidItem(error.into())
+}
+
+// Longitude, latitude and the optional altitude of a GeoJSON position
+fn position(value: &Value) -> Result<(f64, f64, f64), MissionError> {
+    let numbers: Vec<f64> = value
+        .as_array()
+        .and_then(|values| values.iter().map(Value::as_f64).collect())
+        .ok_or_else(|| invalid(format!("Invalid position: {value}")))?;
+    let (longitude, latitude) = match numbers[..] {
+        [longitude, latitude] | [longitude, latitude, _] => (longitude, latitude),
+        _ => return Err(invalid(format!("Invalid position: {value}"))),
+    };
+    if !(-180.0..=180.0).contains(&longitude) || !(-90.0..=90.0).contains(&latitude) {
+        return Err(invalid(format!("Position out of range: {value}")));
+    }
+    Ok((longitude, latitude, numbers.get(2).copied().unwrap_or(0.0)))
+}
+
+fn from_degrees_e7(degrees_e7: i32) -> f64 {
+    degrees_e7 as f64 / 1e7
+}
+
+fn item(command: &str, frame: &str, longitude: f64, latitude: f64) -> MissionItem {
+    MissionItem {
+        command: command.to_string(),
+        frame: frame.to_string(),
+        param1: 0.0,
+        param2: 0.0,
+        param3: 0.0,
+        param4: 0.0,
+        x: degrees_e7(latitude),
+        y: degrees_e7(longitude),
+        z: 0.0,
+        autocontinue: true,
+    }
+}
+
+fn orientation(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn segments_cross(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    orientation(a, b, c) * orientation(a, b, d) < 0.0
+        && orientation(c, d, a) * orientation(c, d, b) < 0.0
+}
+
+// Vertices of a polygon without holes, the closing vertex is removed
+fn polygon(coordinates: &Value) -> Result<Vec<(f64, f64)>, MissionError> {
+    let rings = coordinates
+        .as_array()
+        .ok_or_else(|| invalid("Polygon coordinates should be a list of rings"))?;
+    let ring = match &rings[..] {
+        [ring] => ring,
+        _ => {
+            return Err(invalid(
+                "Polygons should have a single ring, holes are not supported",
+            ))
+        }
+    };
+    let mut vertices = ring
+        .as_array()
+        .ok_or_else(|| invalid("Polygon ring should be a list of positions"))?
+        .iter()
+        .map(|vertex| position(vertex).map(|(longitude, latitude, _)| (longitude, latitude)))
+        .collect::<Result<Vec<(f64, f64)>, MissionError>>()?;
+    if vertices.len() > 1 && vertices.first() == vertices.last() {
+        vertices.pop();
+    }
+    if vertices.len() < 3 {
+        return Err(invalid("Polygons should have at least 3 vertices"));
+    }
+
+    let edges: Vec<((f64, f64), (f64, f64))> = (0..vertices.len())
+        .map(|index| (vertices[index], vertices[(index + 1) % vertices.len()]))
+        .collect();
+    for (index, (a, b)) in edges.iter().enumerate() {
+        for (c, d) in edges.iter().skip(index + 2) {
+            if segments_cross(*a, *b, *c, *d) {
+                return Err(invalid("Polygon edges should not cross each other"));
+            }
+        }
+    }
+    Ok(vertices)
+}
+
+/// Validates the fences and converts them to mission items of MAV_MISSION_TYPE_FENCE
+pub fn fence_to_items(fence: &FeatureCollection) -> Result<Vec<MissionItem>, MissionError> {
+    let mut items = vec![];
+    for feature in &fence.features {
+        let properties = &feature.properties;
+        match (feature.geometry.kind.as_str(), properties.radius) {
+            ("Polygon", None) => {
+                let vertices = polygon(&feature.geometry.coordinates)?;
+                let command = match properties.inclusion {
+                    true => POLYGON_INCLUSION,
+                    false => POLYGON_EXCLUSION,
+                };
+                items.extend(vertices.iter().map(|(longitude, latitude)| MissionItem {
+                    param1: vertices.len() as f32,
+                    ..item(command, "MAV_FRAME_GLOBAL", *longitude, *latitude)
+                }));
+            }
+            ("Point", Some(radius)) => {
+                if !radius.is_finite() || radius <= 0.0 {
+                    return Err(invalid(format!("Invalid circle radius: {radius}")));
+                }
+                let (longitude, latitude, _) = position(&feature.geometry.coordinates)?;
+                let command = match properties.inclusion {
+                    true => CIRCLE_INCLUSION,
+                    false => CIRCLE_EXCLUSION,
+                };
+                items.push(MissionItem {
+                    param1: radius,
+                    ..item(command, "MAV_FRAME_GLOBAL", longitude, latitude)
+                });
+            }
+            ("Point", None) if properties.return_point => {
+                let (longitude, latitude, altitude) = position(&feature.geometry.coordinates)?;
+                items.push(MissionItem {
+                    z: altitude as f32,
+                    ..item(
+                        RETURN_POINT,
+                        "MAV_FRAME_GLOBAL_RELATIVE_ALT",
+                        longitude,
+                        latitude,
+                    )
+                });
+            }
+            (kind, _) => {
+                return Err(invalid(format!(
+                    "Unsupported fence {kind}, use a Polygon, a Point with radius or a return_point"
+                )))
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Converts the mission items of MAV_MISSION_TYPE_FENCE to fences
+pub fn fence_from_items(items: &[MissionItem]) -> Result<FeatureCollection, MissionError> {
+    let mut features = vec![];
+    let mut index = 0;
+    while index < items.len() {
+        let first = &items[index];
+        let coordinates = json!([from_degrees_e7(first.y), from_degrees_e7(first.x)]);
+        let (geometry, properties, length) = match first.command.as_str() {
+            POLYGON_INCLUSION | POLYGON_EXCLUSION => {
+                let length = first.param1 as usize;
+                let vertices = items
+                    .get(index..index + length)
+                    .filter(|vertices| {
+                        length >= 3 && vertices.iter().all(|v| v.command == first.command)
+                    })
+                    .ok_or_else(|| invalid(format!("Incomplete polygon at item {index}")))?;
+                let mut ring: Vec<Value> = vertices
+                    .iter()
+                    .map(|vertex| json!([from_degrees_e7(vertex.y), from_degrees_e7(vertex.x)]))
+                    .collect();
+                ring.push(ring[0].clone());
+                let properties = Properties {
+                    inclusion: first.command == POLYGON_INCLUSION,
+                    ..Default::default()
+                };
+                (("Polygon", json!([ring])), properties, length)
+            }
+            CIRCLE_INCLUSION | CIRCLE_EXCLUSION => {
+                let properties = Properties {
+                    inclusion: first.command == CIRCLE_INCLUSION,
+                    radius: Some(first.param1),
+                    return_point: false,
+                };
+                (("Point", coordinates), properties, 1)
+            }
+            RETURN_POINT => {
+                let properties = Properties {
+                    return_point: true,
+                    ..Default::default()
+                };
+                let coordinates =
+                    json!([from_degrees_e7(first.y), from_degrees_e7(first.x), first.z]);
+                (("Point", coordinates), properties, 1)
+            }
+            command => return Err(invalid(format!("Unsupported fence item {command}"))),
+        };
+
+        features.push(Feature {
+            kind: feature_type(),
+            geometry: Geometry {
+                kind: geometry.0.to_string(),
+                coordinates: geometry.1,
+            },
+            properties,
+        });
+        index += length;
+    }
+
+    Ok(FeatureCollection {
+        kind: feature_collection_type(),
+        features,
+    })
+}
+
+/// Validates the rally points and converts them to mission items of MAV_MISSION_TYPE_RALLY,
+/// the altitude is relative to home
+pub fn rally_to_items(rally: &FeatureCollection) -> Result<Vec<MissionItem>, MissionError> {
+    rally
+        .features
+        .iter()
+        .map(|feature| {
+            if feature.geometry.kind != "Point" {
+                return Err(invalid("Rally points should be a Point"));
+            }
+            let (longitude, latitude, altitude) = position(&feature.geometry.coordinates)?;
+            Ok(MissionItem {
+                z: altitude as f32,
+                ..item(
+                    RALLY_POINT,
+                    "MAV_FRAME_GLOBAL_RELATIVE_ALT",
+                    longitude,
+                    latitude,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Converts the mission items of MAV_MISSION_TYPE_RALLY to rally points
+pub fn rally_from_items(items: &[MissionItem]) -> FeatureCollection {
+    FeatureCollection {
+        kind: feature_collection_type(),
+        features: items
+            .iter()
+            .map(|item| Feature {
+                kind: feature_type(),
+                geometry: Geometry {
+                    kind: "Point".to_string(),
+                    coordinates: json!([from_degrees_e7(item.y), from_degrees_e7(item.x), item.z]),
+                },
+                properties: Default::default(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fence_items() {
+        let fence: FeatureCollection = serde_json::from_value(json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[-48.5, -27.5], [-48.4, -27.5], [-48.4, -27.4], [-48.5, -27.5]]]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-48.45, -27.45] },
+                    "properties": { "inclusion": false, "radius": 50.0 }
+                }
+            ]
+        }))
+        .unwrap();
+
+        let items = fence_to_items(&fence).unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].command, POLYGON_INCLUSION);
+        assert_eq!(items[0].param1, 3.0);
+        assert_eq!((items[1].x, items[1].y), (-275_000_000, -484_000_000));
+        assert_eq!(items[3].command, CIRCLE_EXCLUSION);
+
+        let fence = fence_from_items(&items).unwrap();
+        assert_eq!(fence.features.len(), 2);
+        assert_eq!(
+            fence.features[0].geometry.coordinates[0]
+                .as_array()
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(fence.features[1].properties.radius, Some(50.0));
+
+        let crossed: FeatureCollection = serde_json::from_value(json!({
+            "features": [{
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[0.0, 0.0], [1.0, 1.0], [1.0, 0.0], [0.0, 1.0]]]
+                }
+            }]
+        }))
+        .unwrap();
+        assert!(fence_to_items(&crossed).is_err());
+
+        assert!(position(&json!([-48.5, -27.5, 10.0])).is_ok());
+        assert!(position(&json!([-48.5, "-27.5"])).is_err());
+        assert!(position(&json!([-48.5, null, -27.5])).is_err());
+        assert!(position(&json!([-48.5, 91.0])).is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::*;
+use mavlink::ardupilotmega::{
+    GimbalDeviceFlags, GimbalManagerFlags, MavCmd, MavFrame, MavMessage, MavProtocolCapability,
+    GIMBAL_MANAGER_INFORMATION_DATA, GIMBAL_MANAGER_SET_PITCHYAW_DATA, GIMBAL_MANAGER_STATUS_DATA,
+};
+use mavlink::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::camera;
+use crate::cli;
+use crate::command::{self, Command, CommandError, CommandResult};
+use crate::data::{MAVLinkMessage, Reception};
+use crate::inventory;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::version;
+use crate::websocket_manager;
+
+// Time to wait for the answer of each command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Gimbal attitude from GIMBAL_DEVICE_ATTITUDE_STATUS, sent to the gimbal_attitude topic of the events websocket
+#[derive(Clone, Debug, Serialize)]
+pub struct Attitude {
+    pub time: DateTime<Utc>,
+    pub system_id: u8,
+    pub gimbal_id: u8,
+    /// Roll, pitch and yaw in degrees, yaw is relative to the vehicle unless yaw_in_earth_frame
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+    /// Angular velocities in degrees per second, null if unknown
+    pub roll_rate: f64,
+    pub pitch_rate: f64,
+    pub yaw_rate: f64,
+    pub yaw_in_earth_frame: bool,
+    /// GIMBAL_DEVICE_FLAGS bits
+    pub flags: u16,
+    /// GIMBAL_DEVICE_ERROR_FLAGS bits, 0 if the gimbal is healthy
+    pub failure_flags: u32,
+}
+
+/// Gimbal announced by a gimbal manager with GIMBAL_MANAGER_INFORMATION
+#[derive(Clone, Debug, Serialize)]
+pub struct Gimbal {
+    pub system_id: u8,
+    /// Component ID of the gimbal manager, usually the autopilot
+    pub manager_component_id: u8,
+    /// Gimbal device ID, used in the commands
+    pub gimbal_id: u8,
+    pub online: bool,
+    /// GIMBAL_MANAGER_CAP_FLAGS bits
+    pub capabilities: u32,
+    /// Angle limits in degrees as [min, max]
+    pub roll_limits: [f64; 2],
+    pub pitch_limits: [f64; 2],
+    pub yaw_limits: [f64; 2],
+    /// System and component IDs in primary control, E.g: this service after taking control
+    pub primary_control: Option<[u8; 2]>,
+    pub secondary_control: Option<[u8; 2]>,
+    pub attitude: Option<Attitude>,
+}
+
+/// Pitch and yaw control message of the gimbal websocket, values in degrees
+#[derive(Clone, Debug, Deserialize)]
+pub struct Control {
+    pub system_id: u8,
+    pub gimbal_id: u8,
+    pub pitch: Option<f32>,
+    pub yaw: Option<f32>,
+    pub pitch_rate: Option<f32>,
+    pub yaw_rate: Option<f32>,
+    /// Keep the yaw relative to north instead of the vehicle heading
+    #[serde(default)]
+    pub yaw_lock: bool,
+}
+
+#[derive(Debug)]
+pub enum GimbalError {
+    NotFound(u8),
+    Command(CommandError),
+    /// Gimbal manager rejected the request to take control
+    Rejected(String),
+}
+
+impl std::fmt::Display for GimbalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Gimbal {id} not found."),
+            Self::Command(error) => write!(f, "{error}"),
+            Self::Rejected(result) => write!(f, "Gimbal manager rejected the control: {result}"),
+        }
+    }
+}
+
+impl GimbalError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Command(CommandError::Timeout(_)))
+    }
+}
+
+lazy_static! {
+    static ref ATTITUDES: Mutex<HashMap<(u8, u8), Attitude>> = Mutex::new(HashMap::new());
+    // The message store keeps one message per component, a manager can have many gimbals,
+    // so the manager component and its messages are indexed by system and gimbal ID
+    static ref INFORMATION: Mutex<HashMap<(u8, u8), (u8, GIMBAL_MANAGER_INFORMATION_DATA)>> =
+        Mutex::new(HashMap::new());
+    static ref STATUSES: Mutex<HashMap<(u8, u8), GIMBAL_MANAGER_STATUS_DATA>> =
+        Mutex::new(HashMap::new());
+}
+
+fn degrees(radians: f32) -> f64 {
+    (radians as f64).to_degrees()
+}
+
+/// Keeps the latest information, status and attitude of each gimbal, new attitudes are sent as events
+pub fn update(message: &MAVLinkMessage<MavMessage>, reception: &Reception) {
+    let system_id = message.header.system_id;
+    let status = match &message.message {
+        MavMessage::GIMBAL_DEVICE_ATTITUDE_STATUS(status) => status,
+        MavMessage::GIMBAL_MANAGER_INFORMATION(information) => {
+            INFORMATION.lock().unwrap().insert(
+                (system_id, information.gimbal_device_id),
+                (message.header.component_id, information.clone()),
+            );
+            return;
+        }
+        MavMessage::GIMBAL_MANAGER_STATUS(status) => {
+            STATUSES
+                .lock()
+                .unwrap()
+                .insert((system_id, status.gimbal_device_id), status.clone());
+            return;
+        }
+        _ => return,
+    };
+
+    // Gimbals without their own MAVLink component are identified by gimbal_device_id
+    let gimbal_id = match status.gimbal_device_id {
+        0 => message.header.component_id,
+        id => id,
+    };
+    let [roll, pitch, yaw] = camera::euler_degrees(status.q);
+    let attitude = Attitude {
+        time: reception.utc,
+        system_id: message.header.system_id,
+        gimbal_id,
+        roll,
+        pitch,
+        yaw,
+        roll_rate: degrees(status.angular_velocity_x),
+        pitch_rate: degrees(status.angular_velocity_y),
+        yaw_rate: degrees(status.angular_velocity_z),
+        yaw_in_earth_frame: status
+            .flags
+            .contains(GimbalDeviceFlags::GIMBAL_DEVICE_FLAGS_YAW_IN_EARTH_FRAME),
+        flags: status.flags.bits(),
+        failure_flags: status.failure_flags.bits(),
+    };
+
+    websocket_manager::send_event("gimbal_attitude", &attitude);
+    websocket_manager::send_gimbal_attitude(&attitude);
+    ATTITUDES
+        .lock()
+        .unwrap()
+        .insert((attitude.system_id, gimbal_id), attitude);
+}
+
+fn status(system_id: u8, gimbal_id: u8) -> Option<GIMBAL_MANAGER_STATUS_DATA> {
+    STATUSES
+        .lock()
+        .unwrap()
+        .get(&(system_id, gimbal_id))
+        .cloned()
+}
+
+fn control(system_id: u8, component_id: u8) -> Option<[u8; 2]> {
+    match (system_id, component_id) {
+        (0, 0) => None,
+        ids => Some([ids.0, ids.1]),
+    }
+}
+
+fn gimbal(
+    system_id: u8,
+    manager_component_id: u8,
+    information: &GIMBAL_MANAGER_INFORMATION_DATA,
+    online: bool,
+) -> Gimbal {
+    let gimbal_id = information.gimbal_device_id;
+    let status = status(system_id, gimbal_id);
+    Gimbal {
+        system_id,
+        manager_component_id,
+        gimbal_id,
+        online,
+        capabilities: information.cap_flags.bits(),
+        roll_limits: [degrees(information.roll_min), degrees(information.roll_max)],
+        pitch_limits: [
+            degrees(information.pitch_min),
+            degrees(information.pitch_max),
+        ],
+        yaw_limits: [degrees(information.yaw_min), degrees(information.yaw_max)],
+        primary_control: status.as_ref().and_then(|status| {
+            control(status.primary_control_sysid, status.primary_control_compid)
+        }),
+        secondary_control: status.as_ref().and_then(|status| {
+            control(
+                status.secondary_control_sysid,
+                status.secondary_control_compid,
+            )
+        }),
+        attitude: ATTITUDES
+            .lock()
+            .unwrap()
+            .get(&(system_id, gimbal_id))
+            .cloned(),
+    }
+}
+
+/// Gimbals of all vehicles, sorted by system and gimbal ID
+pub fn gimbals() -> Vec<Gimbal> {
+    let online: HashSet<(u8, u8)> = inventory::inventory()
+        .into_iter()
+        .filter(|component| component.online)
+        .map(|component| (component.system_id, component.component_id))
+        .collect();
+
+    let information = INFORMATION.lock().unwrap().clone();
+    let mut gimbals: Vec<Gimbal> = information
+        .iter()
+        .map(|(&(system_id, _), (component_id, information))| {
+            let online = online.contains(&(system_id, *component_id));
+            gimbal(system_id, *component_id, information, online)
+        })
+        .collect();
+    gimbals.sort_by_key(|gimbal| (gimbal.system_id, gimbal.gimbal_id));
+    gimbals
+}
+
+pub fn find(system_id: u8, gimbal_id: u8) -> Result<Gimbal, GimbalError> {
+    gimbals()
+        .into_iter()
+        .find(|gimbal| gimbal.system_id == system_id && gimbal.gimbal_id == gimbal_id)
+        .ok_or(GimbalError::NotFound(gimbal_id))
+}
+
+// Control messages arrive at a high rate, only the manager component is looked up
+fn manager_component_id(system_id: u8, gimbal_id: u8) -> Result<u8, GimbalError> {
+    INFORMATION
+        .lock()
+        .unwrap()
+        .get(&(system_id, gimbal_id))
+        .map(|(component_id, _)| *component_id)
+        .ok_or(GimbalError::NotFound(gimbal_id))
+}
+
+fn send(
+    vehicle: &MAVLinkVehicleArcMutex,
+    gimbal: &Gimbal,
+    command: &Command,
+) -> Result<CommandResult, GimbalError> {
+    command::send(
+        vehicle,
+        gimbal.system_id,
+        gimbal.manager_component_id,
+        command,
+        COMMAND_TIMEOUT,
+    )
+    .map_err(GimbalError::Command)
+}
+
+/// Makes this service the primary control of the gimbal, or releases it
+pub fn set_control(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    gimbal_id: u8,
+    take: bool,
+) -> Result<CommandResult, GimbalError> {
+    let gimbal = find(system_id, gimbal_id)?;
+    let (own_system_id, own_component_id) = cli::mavlink_system_and_component_id();
+    // -1 leaves the secondary control unchanged, -3 removes the control if this service has it
+    let primary = if take {
+        [own_system_id as f32, own_component_id as f32]
+    } else {
+        [-3.0, -3.0]
+    };
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_GIMBAL_MANAGER_CONFIGURE,
+        [
+            primary[0],
+            primary[1],
+            -1.0,
+            -1.0,
+            0.0,
+            0.0,
+            gimbal_id as f32,
+        ],
+    );
+    send(vehicle, &gimbal, &command)
+}
+
+// Gimbal managers ignore the commands of clients that are not in control
+fn take_control(vehicle: &MAVLinkVehicleArcMutex, gimbal: &Gimbal) -> Result<(), GimbalError> {
+    let (own_system_id, own_component_id) = cli::mavlink_system_and_component_id();
+    if gimbal.primary_control == Some([own_system_id, own_component_id]) {
+        return Ok(());
+    }
+    let result = set_control(vehicle, gimbal.system_id, gimbal.gimbal_id, true)?;
+    if !result.accepted {
+        return Err(GimbalError::Rejected(result.result));
+    }
+    Ok(())
+}
+
+fn flags(yaw_lock: bool) -> GimbalManagerFlags {
+    if yaw_lock {
+        GimbalManagerFlags::GIMBAL_MANAGER_FLAGS_YAW_LOCK
+    } else {
+        GimbalManagerFlags::empty()
+    }
+}
+
+/// Points the gimbal to angles, or moves it with rates, in degrees.
+/// Values that are not used are NaN, the yaw is relative to north with yaw_lock.
+pub fn pitch_yaw(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    gimbal_id: u8,
+    angles: [Option<f32>; 2],
+    rates: [Option<f32>; 2],
+    yaw_lock: bool,
+) -> Result<CommandResult, GimbalError> {
+    let gimbal = find(system_id, gimbal_id)?;
+    take_control(vehicle, &gimbal)?;
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_GIMBAL_MANAGER_PITCHYAW,
+        [
+            angles[0].unwrap_or(f32::NAN),
+            angles[1].unwrap_or(f32::NAN),
+            rates[0].unwrap_or(f32::NAN),
+            rates[1].unwrap_or(f32::NAN),
+            flags(yaw_lock).bits() as f32,
+            0.0,
+            gimbal_id as f32,
+        ],
+    );
+    send(vehicle, &gimbal, &command)
+}
+
+/// Points the gimbal to a location, the altitude is relative to home in meters
+pub fn set_roi(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    gimbal_id: u8,
+    latitude: f64,
+    longitude: f64,
+    altitude: f32,
+) -> Result<CommandResult, GimbalError> {
+    let gimbal = find(system_id, gimbal_id)?;
+    take_control(vehicle, &gimbal)?;
+    let command = Command::global(
+        MavCmd::MAV_CMD_DO_SET_ROI_LOCATION,
+        MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT,
+        [gimbal_id as f32, 0.0, 0.0, 0.0],
+        latitude,
+        longitude,
+        altitude,
+    );
+    send(vehicle, &gimbal, &command)
+}
+
+/// Stops pointing the gimbal to a location
+pub fn clear_roi(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    gimbal_id: u8,
+) -> Result<CommandResult, GimbalError> {
+    let gimbal = find(system_id, gimbal_id)?;
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_SET_ROI_NONE,
+        [gimbal_id as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    );
+    send(vehicle, &gimbal, &command)
+}
+
+/// Sends a control of the gimbal websocket with GIMBAL_MANAGER_SET_PITCHYAW, without acknowledgement.
+/// Clients should take control with the REST API first.
+pub fn send_control(
+    vehicle: &MAVLinkVehicleArcMutex,
+    control: &Control,
+) -> Result<(), GimbalError> {
+    let manager_component_id = manager_component_id(control.system_id, control.gimbal_id)?;
+    let radians = |value: Option<f32>| value.map_or(f32::NAN, f32::to_radians);
+    let message = MavMessage::GIMBAL_MANAGER_SET_PITCHYAW(GIMBAL_MANAGER_SET_PITCHYAW_DATA {
+        flags: flags(control.yaw_lock),
+        pitch: radians(control.pitch),
+        yaw: radians(control.yaw),
+        pitch_rate: radians(control.pitch_rate),
+        yaw_rate: radians(control.yaw_rate),
+        target_system: control.system_id,
+        target_component: manager_component_id,
+        gimbal_device_id: control.gimbal_id,
+    });
+    vehicle
+        .lock()
+        .unwrap()
+        .send_default(&message)
+        .map(|_| ())
+        .map_err(|error| GimbalError::Command(CommandError::Send(error)))
+}
+
+/// Requests GIMBAL_MANAGER_INFORMATION of every autopilot and gimbal that appears or comes back online
+pub fn run(vehicle: &MAVLinkVehicleArcMutex, interval: Duration) {
+    let vehicle = vehicle.clone();
+    std::thread::spawn(move || {
+        let mut online: HashSet<(u8, u8)> = HashSet::new();
+        loop {
+            std::thread::sleep(interval);
+
+            let managers: HashSet<(u8, u8)> = inventory::inventory()
+                .into_iter()
+                .filter(|component| {
+                    component.online
+                        && (component.autopilot != "MAV_AUTOPILOT_INVALID"
+                            || component.mavtype == "MAV_TYPE_GIMBAL")
+                })
+                .map(|component| (component.system_id, component.component_id))
+                .collect();
+
+            for &(system_id, component_id) in managers.difference(&online) {
+                if !version::supports(
+                    system_id,
+                    component_id,
+                    MavProtocolCapability::MAV_PROTOCOL_CAPABILITY_COMPONENT_IMPLEMENTS_GIMBAL_MANAGER,
+                ) {
+                    continue;
+                }
+                let vehicle = vehicle.clone();
+                std::thread::spawn(move || {
+                    let id = MavMessage::message_id_from_name("GIMBAL_MANAGER_INFORMATION")
+                        .unwrap_or_default();
+                    let command = Command::long(
+                        MavCmd::MAV_CMD_REQUEST_MESSAGE,
+                        [id as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                    );
+                    match command::send(&vehicle, system_id, component_id, &command, COMMAND_TIMEOUT)
+                    {
+                        Ok(result) if result.accepted => {
+                            info!("Vehicle {system_id} component {component_id} is a gimbal manager.")
+                        }
+                        Ok(_) => {}
+                        Err(error) => debug!(
+                            "Failed to request gimbals of vehicle {system_id} component {component_id}: {error}"
+                        ),
+                    }
+                });
+            }
+            online = managers;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(component_id: u8, message: MavMessage) {
+        let message = MAVLinkMessage {
+            header: mavlink::MavHeader {
+                system_id: 201,
+                component_id,
+                sequence: 0,
+            },
+            message,
+        };
+        update(&message, &Reception::new("test"));
+    }
+
+    #[test]
+    fn gimbals_of_one_manager() {
+        for gimbal_device_id in [1, 2] {
+            receive(
+                1,
+                MavMessage::GIMBAL_MANAGER_INFORMATION(GIMBAL_MANAGER_INFORMATION_DATA {
+                    gimbal_device_id,
+                    ..Default::default()
+                }),
+            );
+        }
+        receive(
+            1,
+            MavMessage::GIMBAL_MANAGER_STATUS(GIMBAL_MANAGER_STATUS_DATA {
+                gimbal_device_id: 1,
+                primary_control_sysid: 255,
+                primary_control_compid: 190,
+                ..Default::default()
+            }),
+        );
+
+        let ids: Vec<(u8, u8)> = gimbals()
+            .iter()
+            .filter(|gimbal| gimbal.system_id == 201)
+            .map(|gimbal| (gimbal.gimbal_id, gimbal.manager_component_id))
+            .collect();
+        assert_eq!(ids, vec![(1, 1), (2, 1)]);
+        assert_eq!(find(201, 1).unwrap().primary_control, Some([255, 190]));
+        assert_eq!(find(201, 2).unwrap().primary_control, None);
+        assert_eq!(manager_component_id(201, 2).unwrap(), 1);
+        assert!(matches!(
+            manager_component_id(201, 3),
+            Err(GimbalError::NotFound(3))
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use mavlink::ardupilotmega::MavMessage;
+use serde::Serialize;
+
+use crate::cli;
+use crate::data;
+use crate::definitions;
+use crate::websocket_manager;
+
+/// System or component that sends heartbeats
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Component {
+    pub system_id: u8,
+    pub component_id: u8,
+    /// MAV_COMPONENT name of the component ID, E.g: MAV_COMP_ID_CAMERA
+    pub component: Option<String>,
+    /// MAV_TYPE of the component, E.g: MAV_TYPE_GIMBAL
+    pub mavtype: String,
+    /// MAV_AUTOPILOT of the component, E.g: MAV_AUTOPILOT_INVALID for non flight controllers
+    pub autopilot: String,
+    /// MAVLink version from the heartbeat
+    pub mavlink_version: u8,
+    pub first_seen: chrono::DateTime<chrono::Local>,
+    pub last_seen: chrono::DateTime<chrono::Local>,
+    /// False if no heartbeat was received within --offline-timeout, 5 seconds by default
+    pub online: bool,
+    /// Latest AUTOPILOT_VERSION of the component, if received
+    pub autopilot_version: Option<serde_json::Value>,
+    /// Latest COMPONENT_INFORMATION of the component, if received
+    pub component_information: Option<serde_json::Value>,
+}
+
+fn details(system_id: u8, component_id: u8, name: &str) -> Option<serde_json::Value> {
+    data::message(system_id, component_id, name).map(|value| value["message"].clone())
+}
+
+/// All systems and components that sent a heartbeat, sorted by their IDs
+pub fn inventory() -> Vec<Component> {
+    let now = chrono::Local::now();
+    let offline_timeout =
+        chrono::Duration::from_std(cli::offline_timeout()).unwrap_or(chrono::Duration::MAX);
+    let mut components: Vec<Component> = data::messages_with_time("HEARTBEAT")
+        .into_iter()
+        .filter_map(|(message, first_seen, last_seen)| {
+            let heartbeat = match message.message {
+                MavMessage::HEARTBEAT(heartbeat) => heartbeat,
+                _ => return None,
+            };
+            let system_id = message.header.system_id;
+            let component_id = message.header.component_id;
+
+            Some(Component {
+                system_id,
+                component_id,
+                component: definitions::enumeration("MAV_COMPONENT")
+                    .and_then(|components| components.entry_by_value(component_id as u64))
+                    .map(|entry| entry.name.clone()),
+                mavtype: format!("{:?}", heartbeat.mavtype),
+                autopilot: format!("{:?}", heartbeat.autopilot),
+                mavlink_version: heartbeat.mavlink_version,
+                first_seen,
+                last_seen,
+                online: now - last_seen < offline_timeout,
+                autopilot_version: details(system_id, component_id, "AUTOPILOT_VERSION"),
+                component_information: details(system_id, component_id, "COMPONENT_INFORMATION"),
+            })
+        })
+        .collect();
+
+    components.sort_by_key(|component| (component.system_id, component.component_id));
+    components
+}
+
+/// Pushes the inventory to the events websocket when components appear, disappear or change,
+/// components that go offline are also sent alone
+pub fn run(interval: Duration) {
+    std::thread::spawn(move || {
+        let mut last_inventory: Vec<Component> = vec![];
+        loop {
+            std::thread::sleep(interval);
+
+            // Heartbeats alone are not a change
+            let inventory = inventory();
+            let changed = inventory.len() != last_inventory.len()
+                || inventory
+                    .iter()
+                    .zip(&last_inventory)
+                    .any(|(component, last)| {
+                        Component {
+                            last_seen: last.last_seen,
+                            ..component.clone()
+                        } != *last
+                    });
+
+            for component in &inventory {
+                let was_online = last_inventory.iter().any(|last| {
+                    (last.system_id, last.component_id)
+                        == (component.system_id, component.component_id)
+                        && last.online
+                });
+                if was_online && !component.online {
+                    websocket_manager::send_event("component_offline", component);
+                }
+            }
+            if changed {
+                websocket_manager::send_event("inventory", &inventory);
+            }
+            last_inventory = inventory;
+        }
+    });
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use log::*;
+use mavlink::ardupilotmega::{
+    MavMessage, LOG_ENTRY_DATA, LOG_ERASE_DATA, LOG_REQUEST_DATA_DATA, LOG_REQUEST_END_DATA,
+    LOG_REQUEST_LIST_DATA,
+};
+use serde::Serialize;
+
+use crate::data::{self, MAVLinkMessage};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{self, Protocol, RETRY_TIMEOUT, TRANSFER_RETRIES};
+use crate::websocket_manager;
+
+// Bytes of each LOG_DATA
+const CHUNK_SIZE: u32 = 90;
+// Bytes requested at once, missing chunks are requested again before moving on
+const WINDOW_SIZE: u32 = CHUNK_SIZE * 512;
+
+// Only one operation per component, the autopilot sends the data of a single request
+fn component_lock(system_id: u8, component_id: u8) -> Arc<Mutex<()>> {
+    transaction::component_lock(Protocol::Logs, system_id, component_id)
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    NotFound(u16),
+    Send(std::io::Error),
+    Timeout,
+}
+
+impl std::fmt::Display for LogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Log {id} does not exist."),
+            Self::Send(error) => write!(f, "Failed to send message: {error}"),
+            Self::Timeout => write!(f, "Timeout while waiting for the vehicle."),
+        }
+    }
+}
+
+/// Log stored in the vehicle
+#[derive(Clone, Debug, Serialize)]
+pub struct LogEntry {
+    pub id: u16,
+    /// Creation time, if the vehicle had a valid clock
+    pub time_utc: Option<DateTime<Utc>>,
+    /// Size in bytes
+    pub size: u32,
+}
+
+impl From<&LOG_ENTRY_DATA> for LogEntry {
+    fn from(entry: &LOG_ENTRY_DATA) -> Self {
+        Self {
+            id: entry.id,
+            time_utc: match entry.time_utc {
+                0 => None,
+                time => Utc.timestamp_opt(time as i64, 0).single(),
+            },
+            size: entry.size,
+        }
+    }
+}
+
+/// Download progress, sent to the log_progress topic of the events websocket
+#[derive(Clone, Debug, Serialize)]
+pub struct Progress {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub id: u16,
+    pub received: u32,
+    pub size: u32,
+}
+
+struct Transfer {
+    vehicle: MAVLinkVehicleArcMutex,
+    receiver: mpsc::Receiver<MAVLinkMessage<MavMessage>>,
+    system_id: u8,
+    component_id: u8,
+}
+
+impl Transfer {
+    fn new(vehicle: &MAVLinkVehicleArcMutex, system_id: u8, component_id: u8) -> Self {
+        Self {
+            vehicle: vehicle.clone(),
+            receiver: data::subscribe(),
+            system_id,
+            component_id,
+        }
+    }
+
+    fn send(&self, message: &MavMessage) -> Result<(), LogError> {
+        self.vehicle
+            .lock()
+            .unwrap()
+            .send_default(message)
+            .map(|_| ())
+            .map_err(LogError::Send)
+    }
+
+    // Next message of the component until the deadline
+    fn receive(&self, deadline: Instant) -> Option<MavMessage> {
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let answer = self.receiver.recv_timeout(remaining).ok()?;
+            if answer.header.system_id == self.system_id
+                && answer.header.component_id == self.component_id
+            {
+                return Some(answer.message);
+            }
+        }
+        None
+    }
+
+    fn request_list(&self, start: u16, end: u16) -> Result<(), LogError> {
+        self.send(&MavMessage::LOG_REQUEST_LIST(LOG_REQUEST_LIST_DATA {
+            start,
+            end,
+            target_system: self.system_id,
+            target_component: self.component_id,
+        }))
+    }
+
+    // Entries are requested again one by one when some of them are lost
+    fn list(&self) -> Result<Vec<LogEntry>, LogError> {
+        let mut entries: BTreeMap<u16, LogEntry> = BTreeMap::new();
+        let mut expected: Option<(u16, u16)> = None;
+
+        for _ in 0..TRANSFER_RETRIES {
+            match expected {
+                None => self.request_list(0, u16::MAX)?,
+                Some((first, last)) => {
+                    for id in (first..=last).filter(|id| !entries.contains_key(id)) {
+                        self.request_list(id, id)?;
+                    }
+                }
+            }
+
+            let mut deadline = Instant::now() + RETRY_TIMEOUT;
+            while let Some(message) = self.receive(deadline) {
+                let entry = match message {
+                    MavMessage::LOG_ENTRY(entry) => entry,
+                    _ => continue,
+                };
+                if entry.num_logs == 0 {
+                    return Ok(vec![]);
+                }
+
+                // Log IDs are consecutive and end at last_log_num
+                let first = entry.last_log_num.saturating_sub(entry.num_logs - 1);
+                expected = Some((first, entry.last_log_num));
+                entries.insert(entry.id, LogEntry::from(&entry));
+                if entries.len() >= entry.num_logs as usize {
+                    return Ok(entries.into_values().collect());
+                }
+                deadline = Instant::now() + RETRY_TIMEOUT;
+            }
+        }
+
+        Err(LogError::Timeout)
+    }
+
+    fn entry(&self, id: u16) -> Result<LogEntry, LogError> {
+        for _ in 0..TRANSFER_RETRIES {
+            self.request_list(id, id)?;
+
+            let deadline = Instant::now() + RETRY_TIMEOUT;
+            while let Some(message) = self.receive(deadline) {
+                match message {
+                    MavMessage::LOG_ENTRY(entry) if entry.num_logs == 0 => {
+                        return Err(LogError::NotFound(id))
+                    }
+                    MavMessage::LOG_ENTRY(entry) if entry.id == id => {
+                        return Ok(LogEntry::from(&entry))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Err(LogError::Timeout)
+    }
+
+    fn request_data(&self, id: u16, ofs: u32, count: u32) -> Result<(), LogError> {
+        self.send(&MavMessage::LOG_REQUEST_DATA(LOG_REQUEST_DATA_DATA {
+            ofs,
+            count,
+            id,
+            target_system: self.system_id,
+            target_component: self.component_id,
+        }))
+    }
+
+    // Reads the log window by window, each window is written once it has no gaps
+    fn read(
+        &self,
+        entry: &LogEntry,
+        mut write: impl FnMut(Vec<u8>) -> bool,
+    ) -> Result<(), LogError> {
+        let mut start = 0;
+        while start < entry.size {
+            let end = (start + WINDOW_SIZE).min(entry.size);
+            let mut chunks: Vec<Option<Vec<u8>>> =
+                vec![None; (end - start).div_ceil(CHUNK_SIZE) as usize];
+
+            let mut retries = 0;
+            while let Some(missing) = chunks.iter().position(Option::is_none) {
+                if retries >= TRANSFER_RETRIES {
+                    return Err(LogError::Timeout);
+                }
+                let ofs = start + missing as u32 * CHUNK_SIZE;
+                self.request_data(entry.id, ofs, end - ofs)?;
+                retries += 1;
+
+                let mut deadline = Instant::now() + RETRY_TIMEOUT;
+                while let Some(message) = self.receive(deadline) {
+                    let chunk = match message {
+                        MavMessage::LOG_DATA(chunk) if chunk.id == entry.id => chunk,
+                        _ => continue,
+                    };
+                    if chunk.ofs < start || chunk.ofs >= end || chunk.ofs % CHUNK_SIZE != 0 {
+                        continue;
+                    }
+                    let index = ((chunk.ofs - start) / CHUNK_SIZE) as usize;
+                    // Chunks that were already received are not progress
+                    if chunks.get(index).is_none_or(Option::is_some) {
+                        continue;
+                    }
+                    let count = (chunk.count as usize).min(chunk.data.len());
+                    chunks[index] = Some(chunk.data[..count].to_vec());
+                    retries = 0;
+
+                    // A short chunk is the end of the log, even if its size was wrong
+                    if count < CHUNK_SIZE as usize {
+                        chunks.truncate(index + 1);
+                    }
+                    if chunks.iter().all(Option::is_some) {
+                        break;
+                    }
+                    deadline = Instant::now() + RETRY_TIMEOUT;
+                }
+            }
+
+            let data: Vec<u8> = chunks.into_iter().flatten().flatten().collect();
+            let short = (data.len() as u32) < end - start;
+            start += data.len() as u32;
+            if !write(data) {
+                return Ok(());
+            }
+            websocket_manager::send_event(
+                "log_progress",
+                &Progress {
+                    system_id: self.system_id,
+                    component_id: self.component_id,
+                    id: entry.id,
+                    received: start,
+                    size: entry.size,
+                },
+            );
+            if short {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end(&self) {
+        let message = MavMessage::LOG_REQUEST_END(LOG_REQUEST_END_DATA {
+            target_system: self.system_id,
+            target_component: self.component_id,
+        });
+        if let Err(error) = self.send(&message) {
+            warn!("Failed to send LOG_REQUEST_END: {error}");
+        }
+    }
+}
+
+/// Logs stored in the vehicle, sorted by ID
+pub fn list(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<Vec<LogEntry>, LogError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let transfer = Transfer::new(vehicle, system_id, component_id);
+    let result = transfer.list();
+    transfer.end();
+    result
+}
+
+/// Streams the content of a log, the returned value is its entry
+#[allow(clippy::type_complexity)]
+pub fn download(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    id: u16,
+) -> Result<(LogEntry, UnboundedReceiver<Result<Vec<u8>, LogError>>), LogError> {
+    let (entry_sender, entry_receiver) = mpsc::channel();
+    let (sender, receiver) = unbounded();
+    let vehicle = vehicle.clone();
+
+    std::thread::spawn(move || {
+        let lock = component_lock(system_id, component_id);
+        let _guard = lock.lock().unwrap();
+        let transfer = Transfer::new(&vehicle, system_id, component_id);
+        let entry = match transfer.entry(id) {
+            Ok(entry) => entry,
+            Err(error) => {
+                let _ = entry_sender.send(Err(error));
+                return;
+            }
+        };
+        let _ = entry_sender.send(Ok(entry.clone()));
+
+        // Stops reading when the client disconnects
+        let result = transfer.read(&entry, |data| sender.unbounded_send(Ok(data)).is_ok());
+        if let Err(error) = result {
+            warn!("Download of log {id} failed: {error}");
+            let _ = sender.unbounded_send(Err(error));
+        }
+        transfer.end();
+    });
+
+    let entry = entry_receiver.recv().unwrap_or(Err(LogError::Timeout))?;
+    Ok((entry, receiver))
+}
+
+/// Erases all logs, the vehicle does not answer LOG_ERASE
+pub fn erase(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<(), LogError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    Transfer::new(vehicle, system_id, component_id).send(&MavMessage::LOG_ERASE(LOG_ERASE_DATA {
+        target_system: system_id,
+        target_component: component_id,
+    }))
+}
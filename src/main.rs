@@ -1,8 +1,30 @@
+mod actions;
+mod camera;
 mod cli;
+mod command;
 mod data;
+mod definitions;
 mod endpoints;
+mod ftp;
+mod geofence;
+mod gimbal;
+mod inventory;
+mod logs;
 mod mavlink_vehicle;
+mod metrics;
+mod mission;
+mod modes;
+mod params;
+mod rates;
 mod server;
+mod snapshot;
+mod statustext;
+mod summary;
+mod timeseries;
+mod timesync;
+mod transaction;
+mod version;
+mod views;
 mod websocket_manager;
 
 use std::sync::{Arc, Mutex};
@@ -21,6 +43,19 @@ fn main() -> std::io::Result<()> {
         _ => panic!("Invalid mavlink version."),
     };
 
+    if let Some(path) = cli::snapshot_file() {
+        let path = std::path::PathBuf::from(path);
+        snapshot::restore(&path);
+        snapshot::run(path, cli::snapshot_interval());
+    }
+
+    summary::run(std::time::Duration::from_millis(250));
+    inventory::run(std::time::Duration::from_secs(1));
+
+    if let Some(url) = cli::influxdb_url() {
+        timeseries::run_pusher(url.to_string(), cli::influxdb_token().map(String::from));
+    }
+
     let (system_id, component_id) = cli::mavlink_system_and_component_id();
     let vehicle = mavlink_vehicle::MAVLinkVehicleHandle::<mavlink::ardupilotmega::MavMessage>::new(
         cli::mavlink_connection_string(),
@@ -31,6 +66,12 @@ fn main() -> std::io::Result<()> {
     );
 
     let inner_vehicle = vehicle.mavlink_vehicle.clone();
+    rates::set_profile(cli::message_rates()).expect("Invalid message rate profile.");
+    rates::run(&inner_vehicle, std::time::Duration::from_secs(1));
+    gimbal::run(&inner_vehicle, std::time::Duration::from_secs(1));
+    timesync::run(&inner_vehicle, std::time::Duration::from_secs(1));
+    version::run(&inner_vehicle, std::time::Duration::from_secs(1));
+    statustext::run(std::time::Duration::from_secs(1));
     server::run(cli::server_address(), &inner_vehicle);
 
     //TODO: Do inside endpoint and use web::Data ?
@@ -41,20 +82,50 @@ fn main() -> std::io::Result<()> {
         ws_callback(inner_vehicle.clone(), value)
     }));
 
+    let gimbal_vehicle = vehicle.mavlink_vehicle.clone();
+    websocket_manager::gimbal_manager()
+        .lock()
+        .unwrap()
+        .new_message_callback = Some(Arc::new(move |value| {
+        gimbal_ws_callback(&gimbal_vehicle, value)
+    }));
+
     loop {
         std::thread::sleep(std::time::Duration::from_secs(1));
 
-        while let Ok((header, message)) = vehicle.thread_rx_channel.recv() {
+        while let Ok((header, message, mut reception)) = vehicle.thread_rx_channel.recv() {
             debug!("Received: {:#?} {:#?}", header, message);
-            websocket_manager::send(&MAVLinkMessage {
+            let received = MAVLinkMessage {
                 header,
                 message: message.clone(),
-            });
-            data::update((header, message));
+            };
+            timesync::update(&received, &reception);
+            reception.vehicle_time_utc = timesync::message_time_utc(&received, &reception);
+            websocket_manager::send(&received, &reception);
+            timeseries::update(&received, &reception);
+            statustext::update(&received, &reception);
+            camera::update(&received, &reception);
+            gimbal::update(&received, &reception);
+            // Caches must be updated before the message reaches the subscribers of data
+            params::update(&received);
+            data::update((header, message), reception);
         }
     }
 }
 
+fn gimbal_ws_callback(
+    inner_vehicle: &Arc<Mutex<mavlink_vehicle::MAVLinkVehicle<mavlink::ardupilotmega::MavMessage>>>,
+    value: &str,
+) -> String {
+    match serde_json::from_str::<gimbal::Control>(value) {
+        Ok(control) => match gimbal::send_control(inner_vehicle, &control) {
+            Ok(()) => String::from("Ok"),
+            Err(error) => error.to_string(),
+        },
+        Err(error) => format!("Could not convert input control: {error}"),
+    }
+}
+
 fn ws_callback(
     inner_vehicle: Arc<Mutex<mavlink_vehicle::MAVLinkVehicle<mavlink::ardupilotmega::MavMessage>>>,
     value: &str,
@@ -67,7 +138,10 @@ fn ws_callback(
             .unwrap()
             .send(&content.header, &content.message);
         if result.is_ok() {
-            data::update((content.header, content.message));
+            data::update(
+                (content.header, content.message),
+                data::Reception::new("websocket"),
+            );
         }
 
         format!("{result:?}")
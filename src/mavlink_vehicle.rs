@@ -1,9 +1,36 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 
 use log::*;
 
+use crate::data::Reception;
+
 pub type MAVLinkVehicleArcMutex = Arc<Mutex<MAVLinkVehicle<mavlink::ardupilotmega::MavMessage>>>;
 
+/// Counters of the MAVLink connection
+#[derive(Debug)]
+pub struct LinkStatistics {
+    pub received_messages: AtomicU64,
+    pub receive_errors: AtomicU64,
+    pub sent_messages: AtomicU64,
+    pub send_errors: AtomicU64,
+}
+
+pub static LINK_STATISTICS: LinkStatistics = LinkStatistics {
+    received_messages: AtomicU64::new(0),
+    receive_errors: AtomicU64::new(0),
+    sent_messages: AtomicU64::new(0),
+    send_errors: AtomicU64::new(0),
+};
+
+fn count_send<T, E>(result: &Result<T, E>) {
+    let counter = match result {
+        Ok(_) => &LINK_STATISTICS.sent_messages,
+        Err(_) => &LINK_STATISTICS.send_errors,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
 #[derive(Clone)]
 pub struct MAVLinkVehicle<M: mavlink::Message> {
     //TODO: Check if Arc<Box can be only Arc or Box
@@ -14,17 +41,28 @@ pub struct MAVLinkVehicle<M: mavlink::Message> {
 impl<M: mavlink::Message> MAVLinkVehicle<M> {
     pub fn send(&self, header: &mavlink::MavHeader, message: &M) -> std::io::Result<usize> {
         let result = self.vehicle.send(header, message);
+        count_send(&result);
 
         // Convert from mavlink error to io error
         match result {
             Err(mavlink::error::MessageWriteError::Io(error)) => Err(error),
-            Err(mavlink::error::MessageWriteError::MAVLink2Only) => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "MAVLink2Only",
-            )),
+            Err(mavlink::error::MessageWriteError::MAVLink2Only) => {
+                Err(std::io::Error::other("MAVLink2Only"))
+            }
             Ok(something) => Ok(something),
         }
     }
+
+    /// Sends a message with the header of this service, incrementing its sequence
+    pub fn send_default(&self, message: &M) -> std::io::Result<usize> {
+        let header = {
+            let mut header = self.header.lock().unwrap();
+            let current = *header;
+            header.sequence = header.sequence.wrapping_add(1);
+            current
+        };
+        self.send(&header, message)
+    }
 }
 
 #[allow(dead_code)]
@@ -34,7 +72,7 @@ pub struct MAVLinkVehicleHandle<M: mavlink::Message> {
     heartbeat_thread: std::thread::JoinHandle<()>,
     receive_message_thread: std::thread::JoinHandle<()>,
     //TODO: Add a channel for errors
-    pub thread_rx_channel: std::sync::mpsc::Receiver<(mavlink::MavHeader, M)>,
+    pub thread_rx_channel: std::sync::mpsc::Receiver<(mavlink::MavHeader, M, Reception)>,
 }
 
 impl<M: mavlink::Message + Send + Sync> MAVLinkVehicle<M> {
@@ -95,14 +133,18 @@ impl MAVLinkVehicleHandle<mavlink::ardupilotmega::MavMessage> {
         let heartbeat_mavlink_vehicle = mavlink_vehicle.clone();
         let receive_message_mavlink_vehicle = mavlink_vehicle.clone();
 
-        let (tx_channel, rx_channel) =
-            mpsc::channel::<(mavlink::MavHeader, mavlink::ardupilotmega::MavMessage)>();
+        let (tx_channel, rx_channel) = mpsc::channel::<(
+            mavlink::MavHeader,
+            mavlink::ardupilotmega::MavMessage,
+            Reception,
+        )>();
+        let link = connection_string.to_string();
 
         Self {
             mavlink_vehicle,
             heartbeat_thread: std::thread::spawn(move || heartbeat_loop(heartbeat_mavlink_vehicle)),
             receive_message_thread: std::thread::spawn(move || {
-                receive_message_loop(receive_message_mavlink_vehicle, tx_channel);
+                receive_message_loop(receive_message_mavlink_vehicle, tx_channel, link);
             }),
             thread_rx_channel: rx_channel,
         }
@@ -111,7 +153,12 @@ impl MAVLinkVehicleHandle<mavlink::ardupilotmega::MavMessage> {
 
 fn receive_message_loop(
     mavlink_vehicle: Arc<Mutex<MAVLinkVehicle<mavlink::ardupilotmega::MavMessage>>>,
-    channel: std::sync::mpsc::Sender<(mavlink::MavHeader, mavlink::ardupilotmega::MavMessage)>,
+    channel: std::sync::mpsc::Sender<(
+        mavlink::MavHeader,
+        mavlink::ardupilotmega::MavMessage,
+        Reception,
+    )>,
+    link: String,
 ) {
     let mavlink_vehicle = mavlink_vehicle.as_ref().lock().unwrap();
 
@@ -121,11 +168,17 @@ fn receive_message_loop(
     loop {
         match vehicle.recv() {
             Ok((header, msg)) => {
-                if let Err(error) = channel.send((header, msg)) {
+                LINK_STATISTICS
+                    .received_messages
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Err(error) = channel.send((header, msg, Reception::new(&link))) {
                     error!("Failed to send message though channel: {:#?}", error);
                 }
             }
             Err(error) => {
+                LINK_STATISTICS
+                    .receive_errors
+                    .fetch_add(1, Ordering::Relaxed);
                 error!("Recv error: {:?}", error);
                 if let mavlink::error::MessageReadError::Io(error) = error {
                     if error.kind() == std::io::ErrorKind::UnexpectedEof {
@@ -142,7 +195,9 @@ fn send_heartbeat(mavlink_vehicle: Arc<Mutex<MAVLinkVehicle<mavlink::ardupilotme
     let mavlink_vehicle = mavlink_vehicle.as_ref().lock().unwrap();
     let vehicle = mavlink_vehicle.vehicle.clone();
     let mut header = mavlink_vehicle.header.lock().unwrap();
-    if let Err(error) = vehicle.as_ref().send(&header, &heartbeat_message()) {
+    let result = vehicle.as_ref().send(&header, &heartbeat_message());
+    count_send(&result);
+    if let Err(error) = result {
         error!("Failed to send heartbeat: {:?}", error);
     }
     header.sequence = header.sequence.wrapping_add(1);
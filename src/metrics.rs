@@ -0,0 +1,182 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use crate::cli;
+use crate::data;
+use crate::mavlink_vehicle::LINK_STATISTICS;
+use crate::websocket_manager;
+
+// Labels values can't have quotes, backslashes or new lines
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".into();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "+Inf" } else { "-Inf" }.into();
+    }
+    value.to_string()
+}
+
+// Metric names can only have letters, numbers and underscores
+fn metric_name(name: &str) -> String {
+    name.chars()
+        .map(|character| match character {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => character.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(output, "# HELP {name} {help}").unwrap();
+    writeln!(output, "# TYPE {name} {kind}").unwrap();
+}
+
+fn messages(output: &mut String) {
+    let statistics = data::statistics();
+    let labels = |message: &data::MessageStatistics| {
+        format!(
+            "system_id=\"{}\",component_id=\"{}\",message=\"{}\"",
+            message.system_id, message.component_id, message.name
+        )
+    };
+
+    let name = "mavlink2rest_messages_total";
+    header(output, name, "counter", "Number of received messages.");
+    for message in &statistics {
+        writeln!(output, "{name}{{{}}} {}", labels(message), message.counter).unwrap();
+    }
+
+    let name = "mavlink2rest_message_frequency_hz";
+    header(
+        output,
+        name,
+        "gauge",
+        "Average reception rate of the messages.",
+    );
+    for message in &statistics {
+        let frequency = number(message.frequency as f64);
+        writeln!(output, "{name}{{{}}} {frequency}", labels(message)).unwrap();
+    }
+
+    let name = "mavlink2rest_message_last_update_seconds";
+    header(
+        output,
+        name,
+        "gauge",
+        "Unix time of the last reception of the messages.",
+    );
+    for message in &statistics {
+        let last_update = message.last_update.timestamp_millis() as f64 / 1000.0;
+        writeln!(output, "{name}{{{}}} {last_update}", labels(message)).unwrap();
+    }
+}
+
+fn link(output: &mut String) {
+    let link = escape(cli::mavlink_connection_string());
+    let counters = [
+        (
+            "mavlink2rest_link_received_messages_total",
+            "Number of messages received by the MAVLink connection.",
+            &LINK_STATISTICS.received_messages,
+        ),
+        (
+            "mavlink2rest_link_receive_errors_total",
+            "Number of errors while receiving from the MAVLink connection.",
+            &LINK_STATISTICS.receive_errors,
+        ),
+        (
+            "mavlink2rest_link_sent_messages_total",
+            "Number of messages sent by the MAVLink connection.",
+            &LINK_STATISTICS.sent_messages,
+        ),
+        (
+            "mavlink2rest_link_send_errors_total",
+            "Number of errors while sending to the MAVLink connection.",
+            &LINK_STATISTICS.send_errors,
+        ),
+    ];
+
+    for (name, help, counter) in counters {
+        header(output, name, "counter", help);
+        let value = counter.load(Ordering::Relaxed);
+        writeln!(output, "{name}{{link=\"{link}\"}} {value}").unwrap();
+    }
+}
+
+fn websockets(output: &mut String) {
+    let managers = websocket_manager::managers();
+
+    let name = "mavlink2rest_websocket_clients";
+    header(
+        output,
+        name,
+        "gauge",
+        "Number of connected websocket clients.",
+    );
+    for (kind, manager) in &managers {
+        let clients = manager.lock().unwrap().clients.len();
+        writeln!(output, "{name}{{kind=\"{kind}\"}} {clients}").unwrap();
+    }
+
+    let name = "mavlink2rest_websocket_queue_depth";
+    header(
+        output,
+        name,
+        "gauge",
+        "Messages waiting to be sent to each websocket client.",
+    );
+    for (kind, manager) in &managers {
+        let manager = manager.lock().unwrap();
+        for (index, client) in manager.clients.iter().enumerate() {
+            let filter = escape(client.re.as_ref().map_or("", |re| re.as_str()));
+            let pending = client.pending.load(Ordering::Relaxed);
+            writeln!(
+                output,
+                "{name}{{kind=\"{kind}\",client=\"{index}\",filter=\"{filter}\"}} {pending}"
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn telemetry(output: &mut String) {
+    for field in cli::metrics_fields() {
+        let (message_name, field_path) = match field.split_once('.') {
+            Some(content) => content,
+            None => continue,
+        };
+
+        let name = metric_name(&format!("mavlink2rest_{message_name}_{field_path}"));
+        header(output, &name, "gauge", &format!("Value of {field}."));
+
+        let pointer = format!("/message/{}", field_path.replace('.', "/"));
+        for (system_id, component_id, value) in data::messages_by_name(message_name) {
+            if let Some(value) = value.pointer(&pointer).and_then(|value| value.as_f64()) {
+                let value = number(value);
+                writeln!(
+                    output,
+                    "{name}{{system_id=\"{system_id}\",component_id=\"{component_id}\"}} {value}"
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+/// Renders all metrics using the Prometheus text format
+pub fn render() -> String {
+    let mut output = String::new();
+    messages(&mut output);
+    link(&mut output);
+    websockets(&mut output);
+    telemetry(&mut output);
+    output
+}
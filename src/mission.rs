@@ -0,0 +1,700 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::{
+    MavCmd, MavFrame, MavMessage, MavMissionResult, MavMissionType, MISSION_ACK_DATA,
+    MISSION_CLEAR_ALL_DATA, MISSION_COUNT_DATA, MISSION_ITEM_INT_DATA, MISSION_REQUEST_INT_DATA,
+    MISSION_REQUEST_LIST_DATA,
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{self, Command, CommandError};
+use crate::data::{self, MAVLinkMessage};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{self, Protocol, RETRIES, RETRY_TIMEOUT, TRANSFER_RETRIES};
+use crate::websocket_manager;
+
+// The mission protocol gives the other system 1500 ms to answer each message
+const ITEM_TIMEOUT: Duration = Duration::from_millis(1500);
+
+// Only one transfer per component, the protocol has a single state for all mission types
+fn component_lock(system_id: u8, component_id: u8) -> Arc<Mutex<()>> {
+    transaction::component_lock(Protocol::Mission, system_id, component_id)
+}
+
+fn default_frame() -> String {
+    "MAV_FRAME_GLOBAL_RELATIVE_ALT".to_string()
+}
+
+fn default_autocontinue() -> bool {
+    true
+}
+
+/// Mission item, the fields follow MISSION_ITEM_INT, x and y are degE7 for global frames
+#[derive(Apiv2Schema, Clone, Debug, Deserialize, Serialize)]
+pub struct MissionItem {
+    /// MAV_CMD name, E.g: MAV_CMD_NAV_WAYPOINT
+    pub command: String,
+    /// MAV_FRAME name, default is MAV_FRAME_GLOBAL_RELATIVE_ALT
+    #[serde(default = "default_frame")]
+    pub frame: String,
+    #[serde(default)]
+    pub param1: f32,
+    #[serde(default)]
+    pub param2: f32,
+    #[serde(default)]
+    pub param3: f32,
+    #[serde(default)]
+    pub param4: f32,
+    /// Latitude in degE7 for global frames
+    #[serde(default)]
+    pub x: i32,
+    /// Longitude in degE7 for global frames
+    #[serde(default)]
+    pub y: i32,
+    /// Altitude in meters
+    #[serde(default)]
+    pub z: f32,
+    #[serde(default = "default_autocontinue")]
+    pub autocontinue: bool,
+}
+
+// Enums are serialized as {"type": "NAME"}
+fn enum_from_name<T: serde::de::DeserializeOwned>(name: &str) -> Result<T, MissionError> {
+    serde_json::from_value(serde_json::json!({ "type": name }))
+        .map_err(|_| MissionError::InvalidItem(format!("Unknown name {name}")))
+}
+
+impl MissionItem {
+    fn to_message(
+        &self,
+        seq: u16,
+        system_id: u8,
+        component_id: u8,
+        mission_type: MavMissionType,
+    ) -> Result<MavMessage, MissionError> {
+        Ok(MavMessage::MISSION_ITEM_INT(MISSION_ITEM_INT_DATA {
+            param1: self.param1,
+            param2: self.param2,
+            param3: self.param3,
+            param4: self.param4,
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            seq,
+            command: enum_from_name::<MavCmd>(&self.command)?,
+            target_system: system_id,
+            target_component: component_id,
+            frame: enum_from_name::<MavFrame>(&self.frame)?,
+            current: 0,
+            autocontinue: self.autocontinue as u8,
+            mission_type,
+        }))
+    }
+
+    fn from_message(item: &MISSION_ITEM_INT_DATA) -> Self {
+        Self {
+            command: format!("{:?}", item.command),
+            frame: format!("{:?}", item.frame),
+            param1: item.param1,
+            param2: item.param2,
+            param3: item.param3,
+            param4: item.param4,
+            x: item.x,
+            y: item.y,
+            z: item.z,
+            autocontinue: item.autocontinue != 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MissionError {
+    InvalidItem(String),
+    Send(std::io::Error),
+    Timeout,
+    /// Vehicle answered with an error, E.g: MAV_MISSION_NO_SPACE
+    Rejected(String),
+}
+
+impl std::fmt::Display for MissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidItem(error) => write!(f, "Invalid mission item: {error}"),
+            Self::Send(error) => write!(f, "Failed to send message: {error}"),
+            Self::Timeout => write!(f, "Timeout while waiting for the vehicle."),
+            Self::Rejected(result) => write!(f, "Vehicle rejected the mission: {result}"),
+        }
+    }
+}
+
+/// Progress of the last transfer of a vehicle and mission type
+#[derive(Clone, Debug, Serialize)]
+pub struct Progress {
+    pub system_id: u8,
+    /// MAV_MISSION_TYPE name, E.g: MAV_MISSION_TYPE_MISSION
+    pub mission_type: String,
+    /// upload, download or clear
+    pub operation: &'static str,
+    /// Number of items transferred
+    pub current: u16,
+    pub total: u16,
+    /// in_progress, completed or failed
+    pub state: &'static str,
+    pub error: Option<String>,
+}
+
+lazy_static! {
+    static ref PROGRESS: Mutex<HashMap<(u8, String), Progress>> = Mutex::new(HashMap::new());
+}
+
+/// Progress of the last transfers of a vehicle
+pub fn progress(system_id: u8) -> Vec<Progress> {
+    PROGRESS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|progress| progress.system_id == system_id)
+        .cloned()
+        .collect()
+}
+
+fn ack_result(
+    ack: &MISSION_ACK_DATA,
+    mission_type: MavMissionType,
+) -> Option<Result<(), MissionError>> {
+    if ack.mission_type != mission_type {
+        return None;
+    }
+    Some(match ack.mavtype {
+        MavMissionResult::MAV_MISSION_ACCEPTED => Ok(()),
+        result => Err(MissionError::Rejected(format!("{result:?}"))),
+    })
+}
+
+type Link<'a> = Box<dyn Fn(&MavMessage) -> std::io::Result<()> + 'a>;
+
+// Handles one transfer with a vehicle component, every answer is read from the same subscription
+struct Transfer<'a> {
+    link: Link<'a>,
+    receiver: mpsc::Receiver<MAVLinkMessage<MavMessage>>,
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+    progress: Progress,
+}
+
+impl<'a> Transfer<'a> {
+    fn new(
+        vehicle: &'a MAVLinkVehicleArcMutex,
+        system_id: u8,
+        component_id: u8,
+        mission_type: MavMissionType,
+        operation: &'static str,
+    ) -> Self {
+        let link =
+            move |message: &MavMessage| vehicle.lock().unwrap().send_default(message).map(|_| ());
+        Self::with_link(
+            Box::new(link),
+            data::subscribe(),
+            system_id,
+            component_id,
+            mission_type,
+            operation,
+        )
+    }
+
+    fn with_link(
+        link: Link<'a>,
+        receiver: mpsc::Receiver<MAVLinkMessage<MavMessage>>,
+        system_id: u8,
+        component_id: u8,
+        mission_type: MavMissionType,
+        operation: &'static str,
+    ) -> Self {
+        Self {
+            link,
+            receiver,
+            system_id,
+            component_id,
+            mission_type,
+            progress: Progress {
+                system_id,
+                mission_type: format!("{mission_type:?}"),
+                operation,
+                current: 0,
+                total: 0,
+                state: "in_progress",
+                error: None,
+            },
+        }
+    }
+
+    fn report(&mut self, current: u16, total: u16) {
+        self.progress.current = current;
+        self.progress.total = total;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        websocket_manager::send_event("mission_progress", &self.progress);
+        PROGRESS.lock().unwrap().insert(
+            (self.system_id, self.progress.mission_type.clone()),
+            self.progress.clone(),
+        );
+    }
+
+    fn finish<T>(mut self, result: Result<T, MissionError>) -> Result<T, MissionError> {
+        match &result {
+            Ok(_) => self.progress.state = "completed",
+            Err(error) => {
+                self.progress.state = "failed";
+                self.progress.error = Some(error.to_string());
+            }
+        }
+        self.publish();
+        result
+    }
+
+    fn send(&self, message: &MavMessage) -> Result<(), MissionError> {
+        (self.link)(message).map_err(MissionError::Send)
+    }
+
+    // Sends the message until an answer from the component is accepted by the filter
+    fn exchange<T>(
+        &self,
+        message: &MavMessage,
+        filter: impl Fn(&MavMessage) -> Option<T>,
+    ) -> Result<T, MissionError> {
+        for _ in 0..TRANSFER_RETRIES {
+            self.send(message)?;
+
+            let deadline = Instant::now() + ITEM_TIMEOUT;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                let answer = match self.receiver.recv_timeout(remaining) {
+                    Ok(answer) => answer,
+                    Err(_) => break,
+                };
+                if answer.header.system_id != self.system_id
+                    || answer.header.component_id != self.component_id
+                {
+                    continue;
+                }
+                if let Some(result) = filter(&answer.message) {
+                    return Ok(result);
+                }
+            }
+        }
+
+        Err(MissionError::Timeout)
+    }
+
+    fn upload(&mut self, items: &[MissionItem]) -> Result<(), MissionError> {
+        let messages = items
+            .iter()
+            .enumerate()
+            .map(|(seq, item)| {
+                item.to_message(
+                    seq as u16,
+                    self.system_id,
+                    self.component_id,
+                    self.mission_type,
+                )
+            })
+            .collect::<Result<Vec<MavMessage>, MissionError>>()?;
+        let total = messages.len() as u16;
+
+        enum Answer {
+            Request(u16),
+            Ack(Result<(), MissionError>),
+        }
+        // Vehicles can still use the deprecated MISSION_REQUEST
+        let mission_type = self.mission_type;
+        #[allow(deprecated)]
+        let filter = |message: &MavMessage| match message {
+            MavMessage::MISSION_REQUEST_INT(request) if request.mission_type == mission_type => {
+                Some(Answer::Request(request.seq))
+            }
+            MavMessage::MISSION_REQUEST(request) if request.mission_type == mission_type => {
+                Some(Answer::Request(request.seq))
+            }
+            MavMessage::MISSION_ACK(ack) => ack_result(ack, mission_type).map(Answer::Ack),
+            _ => None,
+        };
+
+        self.report(0, total);
+        let mut answer = self.exchange(
+            &MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+                count: total,
+                target_system: self.system_id,
+                target_component: self.component_id,
+                mission_type: self.mission_type,
+                ..Default::default()
+            }),
+            filter,
+        )?;
+
+        loop {
+            match answer {
+                Answer::Request(seq) => {
+                    let message = messages.get(seq as usize).ok_or_else(|| {
+                        MissionError::Rejected(format!("Vehicle requested invalid item {seq}"))
+                    })?;
+                    self.report(seq, total);
+                    answer = self.exchange(message, filter)?;
+                }
+                Answer::Ack(result) => {
+                    if result.is_ok() {
+                        self.report(total, total);
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+
+    fn download(&mut self) -> Result<Vec<MissionItem>, MissionError> {
+        let total = self.exchange(
+            &MavMessage::MISSION_REQUEST_LIST(MISSION_REQUEST_LIST_DATA {
+                target_system: self.system_id,
+                target_component: self.component_id,
+                mission_type: self.mission_type,
+            }),
+            |message| match message {
+                MavMessage::MISSION_COUNT(count) if count.mission_type == self.mission_type => {
+                    Some(count.count)
+                }
+                _ => None,
+            },
+        )?;
+
+        let mut items = Vec::with_capacity(total as usize);
+        for seq in 0..total {
+            self.report(seq, total);
+            let item = self.exchange(
+                &MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                    seq,
+                    target_system: self.system_id,
+                    target_component: self.component_id,
+                    mission_type: self.mission_type,
+                }),
+                |message| match message {
+                    MavMessage::MISSION_ITEM_INT(item)
+                        if item.seq == seq && item.mission_type == self.mission_type =>
+                    {
+                        Some(MissionItem::from_message(item))
+                    }
+                    _ => None,
+                },
+            )?;
+            items.push(item);
+        }
+        self.report(total, total);
+
+        self.send(&MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+            target_system: self.system_id,
+            target_component: self.component_id,
+            mavtype: MavMissionResult::MAV_MISSION_ACCEPTED,
+            mission_type: self.mission_type,
+            ..Default::default()
+        }))?;
+
+        Ok(items)
+    }
+
+    fn clear(&mut self) -> Result<(), MissionError> {
+        self.exchange(
+            &MavMessage::MISSION_CLEAR_ALL(MISSION_CLEAR_ALL_DATA {
+                target_system: self.system_id,
+                target_component: self.component_id,
+                mission_type: self.mission_type,
+            }),
+            |message| match message {
+                MavMessage::MISSION_ACK(ack) => ack_result(ack, self.mission_type),
+                _ => None,
+            },
+        )?
+    }
+}
+
+/// Uploads the items to the vehicle, replacing its current list
+pub fn upload(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+    items: &[MissionItem],
+) -> Result<(), MissionError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let mut transfer = Transfer::new(vehicle, system_id, component_id, mission_type, "upload");
+    let result = transfer.upload(items);
+    transfer.finish(result)
+}
+
+/// Downloads the items from the vehicle
+pub fn download(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+) -> Result<Vec<MissionItem>, MissionError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let mut transfer = Transfer::new(vehicle, system_id, component_id, mission_type, "download");
+    let result = transfer.download();
+    transfer.finish(result)
+}
+
+/// Removes all items of the vehicle
+pub fn clear(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+) -> Result<(), MissionError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+    let mut transfer = Transfer::new(vehicle, system_id, component_id, mission_type, "clear");
+    let result = transfer.clear();
+    transfer.finish(result)
+}
+
+/// Changes the current mission item with MAV_CMD_DO_SET_MISSION_CURRENT,
+/// vehicles that do not support it receive the deprecated MISSION_SET_CURRENT
+pub fn set_current(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    seq: u16,
+) -> Result<(), MissionError> {
+    let lock = component_lock(system_id, component_id);
+    let _guard = lock.lock().unwrap();
+
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_SET_MISSION_CURRENT,
+        [seq as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    );
+    let timeout = RETRY_TIMEOUT * RETRIES as u32;
+    match command::send(vehicle, system_id, component_id, &command, timeout) {
+        Ok(result) if result.accepted => return Ok(()),
+        Ok(result) if result.result != "MAV_RESULT_UNSUPPORTED" => {
+            return Err(MissionError::Rejected(result.result))
+        }
+        Err(CommandError::Send(error)) => return Err(MissionError::Send(error)),
+        // Older vehicles may not answer unknown commands
+        Ok(_) | Err(CommandError::Timeout(_)) => {}
+    }
+
+    // The deprecated message has no answer, the vehicle reports the new item with MISSION_CURRENT
+    let transfer = Transfer::new(
+        vehicle,
+        system_id,
+        component_id,
+        MavMissionType::MAV_MISSION_TYPE_MISSION,
+        "set_current",
+    );
+    #[allow(deprecated)]
+    let message =
+        MavMessage::MISSION_SET_CURRENT(mavlink::ardupilotmega::MISSION_SET_CURRENT_DATA {
+            seq,
+            target_system: system_id,
+            target_component: component_id,
+        });
+    transfer.exchange(&message, |message| match message {
+        MavMessage::MISSION_CURRENT(current) if current.seq == seq => Some(()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Autopilot {
+        items: RefCell<Vec<MISSION_ITEM_INT_DATA>>,
+        count: RefCell<u16>,
+        acked: RefCell<bool>,
+        // Answers with the deprecated MISSION_REQUEST and asks twice for the first item
+        legacy: bool,
+        requested: RefCell<Vec<u16>>,
+        result: Option<MavMissionResult>,
+    }
+
+    impl Autopilot {
+        fn request(&self, seq: u16) -> MavMessage {
+            self.requested.borrow_mut().push(seq);
+            #[allow(deprecated)]
+            if self.legacy {
+                return MavMessage::MISSION_REQUEST(mavlink::ardupilotmega::MISSION_REQUEST_DATA {
+                    seq,
+                    ..Default::default()
+                });
+            }
+            MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                seq,
+                ..Default::default()
+            })
+        }
+
+        fn ack(result: MavMissionResult) -> MavMessage {
+            MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+                mavtype: result,
+                ..Default::default()
+            })
+        }
+
+        fn answer(&self, message: &MavMessage) -> Option<MavMessage> {
+            match message {
+                MavMessage::MISSION_COUNT(count) => {
+                    if let Some(result) = self.result {
+                        return Some(Self::ack(result));
+                    }
+                    *self.count.borrow_mut() = count.count;
+                    Some(self.request(0))
+                }
+                MavMessage::MISSION_ITEM_INT(item) => {
+                    let mut items = self.items.borrow_mut();
+                    items.retain(|stored| stored.seq != item.seq);
+                    items.push(item.clone());
+                    let repeat = self.legacy && self.requested.borrow().len() == 1;
+                    if repeat {
+                        Some(self.request(item.seq))
+                    } else if item.seq + 1 < *self.count.borrow() {
+                        Some(self.request(item.seq + 1))
+                    } else {
+                        Some(Self::ack(MavMissionResult::MAV_MISSION_ACCEPTED))
+                    }
+                }
+                MavMessage::MISSION_REQUEST_LIST(_) => {
+                    Some(MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+                        count: self.items.borrow().len() as u16,
+                        ..Default::default()
+                    }))
+                }
+                MavMessage::MISSION_REQUEST_INT(request) => self
+                    .items
+                    .borrow()
+                    .iter()
+                    .find(|item| item.seq == request.seq)
+                    .cloned()
+                    .map(MavMessage::MISSION_ITEM_INT),
+                MavMessage::MISSION_ACK(_) => {
+                    *self.acked.borrow_mut() = true;
+                    None
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn transfer<'a>(autopilot: &'a Autopilot, operation: &'static str) -> Transfer<'a> {
+        let (sender, receiver) = mpsc::channel();
+        let link = move |message: &MavMessage| {
+            if let Some(answer) = autopilot.answer(message) {
+                let header = mavlink::MavHeader {
+                    system_id: 1,
+                    component_id: 1,
+                    sequence: 0,
+                };
+                sender
+                    .send(MAVLinkMessage {
+                        header,
+                        message: answer,
+                    })
+                    .unwrap();
+            }
+            Ok(())
+        };
+        Transfer::with_link(
+            Box::new(link),
+            receiver,
+            1,
+            1,
+            MavMissionType::MAV_MISSION_TYPE_MISSION,
+            operation,
+        )
+    }
+
+    fn waypoints(count: i32) -> Vec<MissionItem> {
+        (0..count)
+            .map(|index| MissionItem {
+                command: "MAV_CMD_NAV_WAYPOINT".to_string(),
+                frame: default_frame(),
+                param1: 0.0,
+                param2: 0.0,
+                param3: 0.0,
+                param4: 0.0,
+                x: -275999999 + index,
+                y: -485123457,
+                z: 20.0,
+                autocontinue: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn upload_items() {
+        let autopilot = Autopilot::default();
+        transfer(&autopilot, "upload")
+            .upload(&waypoints(3))
+            .unwrap();
+
+        let items = autopilot.items.borrow();
+        assert_eq!(*autopilot.requested.borrow(), vec![0, 1, 2]);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[2].x, -275999997);
+        assert_eq!(items[2].command, MavCmd::MAV_CMD_NAV_WAYPOINT);
+    }
+
+    #[test]
+    fn upload_with_mission_request() {
+        let autopilot = Autopilot {
+            legacy: true,
+            ..Default::default()
+        };
+        transfer(&autopilot, "upload")
+            .upload(&waypoints(2))
+            .unwrap();
+
+        assert_eq!(*autopilot.requested.borrow(), vec![0, 0, 1]);
+        assert_eq!(autopilot.items.borrow().len(), 2);
+    }
+
+    #[test]
+    fn upload_rejected() {
+        let autopilot = Autopilot {
+            result: Some(MavMissionResult::MAV_MISSION_NO_SPACE),
+            ..Default::default()
+        };
+        match transfer(&autopilot, "upload").upload(&waypoints(2)) {
+            Err(MissionError::Rejected(result)) => assert_eq!(result, "MAV_MISSION_NO_SPACE"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(autopilot.items.borrow().is_empty());
+    }
+
+    #[test]
+    fn download_items() {
+        let autopilot = Autopilot::default();
+        for (seq, item) in waypoints(2).iter().enumerate() {
+            let message = item
+                .to_message(seq as u16, 1, 1, MavMissionType::MAV_MISSION_TYPE_MISSION)
+                .unwrap();
+            if let MavMessage::MISSION_ITEM_INT(item) = message {
+                autopilot.items.borrow_mut().push(item);
+            }
+        }
+
+        let items = transfer(&autopilot, "download").download().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].x, -275999998);
+        assert_eq!(items[1].frame, "MAV_FRAME_GLOBAL_RELATIVE_ALT");
+        assert!(*autopilot.acked.borrow());
+    }
+}
@@ -0,0 +1,411 @@
+use std::time::{Duration, Instant};
+
+use mavlink::ardupilotmega::{MavCmd, MavMessage, HEARTBEAT_DATA};
+
+use crate::command::{self, Command, CommandError, CommandResult};
+use crate::data;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{RETRIES, RETRY_TIMEOUT};
+
+// https://ardupilot.org/copter/docs/parameters.html#fltmode1
+const ARDUCOPTER: &[(u32, &str)] = &[
+    (0, "STABILIZE"),
+    (1, "ACRO"),
+    (2, "ALT_HOLD"),
+    (3, "AUTO"),
+    (4, "GUIDED"),
+    (5, "LOITER"),
+    (6, "RTL"),
+    (7, "CIRCLE"),
+    (9, "LAND"),
+    (11, "DRIFT"),
+    (13, "SPORT"),
+    (14, "FLIP"),
+    (15, "AUTOTUNE"),
+    (16, "POSHOLD"),
+    (17, "BRAKE"),
+    (18, "THROW"),
+    (19, "AVOID_ADSB"),
+    (20, "GUIDED_NOGPS"),
+    (21, "SMART_RTL"),
+    (22, "FLOWHOLD"),
+    (23, "FOLLOW"),
+    (24, "ZIGZAG"),
+    (25, "SYSTEMID"),
+    (26, "AUTOROTATE"),
+    (27, "AUTO_RTL"),
+    (28, "TURTLE"),
+];
+
+// https://ardupilot.org/plane/docs/parameters.html#fltmode1
+const ARDUPLANE: &[(u32, &str)] = &[
+    (0, "MANUAL"),
+    (1, "CIRCLE"),
+    (2, "STABILIZE"),
+    (3, "TRAINING"),
+    (4, "ACRO"),
+    (5, "FLY_BY_WIRE_A"),
+    (6, "FLY_BY_WIRE_B"),
+    (7, "CRUISE"),
+    (8, "AUTOTUNE"),
+    (10, "AUTO"),
+    (11, "RTL"),
+    (12, "LOITER"),
+    (13, "TAKEOFF"),
+    (14, "AVOID_ADSB"),
+    (15, "GUIDED"),
+    (17, "QSTABILIZE"),
+    (18, "QHOVER"),
+    (19, "QLOITER"),
+    (20, "QLAND"),
+    (21, "QRTL"),
+    (22, "QAUTOTUNE"),
+    (23, "QACRO"),
+    (24, "THERMAL"),
+    (25, "LOITER_ALT_QLAND"),
+];
+
+// https://ardupilot.org/rover/docs/parameters.html#mode1
+const ARDUROVER: &[(u32, &str)] = &[
+    (0, "MANUAL"),
+    (1, "ACRO"),
+    (3, "STEERING"),
+    (4, "HOLD"),
+    (5, "LOITER"),
+    (6, "FOLLOW"),
+    (7, "SIMPLE"),
+    (8, "DOCK"),
+    (9, "CIRCLE"),
+    (10, "AUTO"),
+    (11, "RTL"),
+    (12, "SMART_RTL"),
+    (15, "GUIDED"),
+    (16, "INITIALISING"),
+];
+
+// https://www.ardusub.com/developers/full-parameter-list.html#flightmode
+const ARDUSUB: &[(u32, &str)] = &[
+    (0, "STABILIZE"),
+    (1, "ACRO"),
+    (2, "ALT_HOLD"),
+    (3, "AUTO"),
+    (4, "GUIDED"),
+    (7, "CIRCLE"),
+    (9, "SURFACE"),
+    (16, "POSHOLD"),
+    (19, "MANUAL"),
+    (20, "MOTOR_DETECT"),
+    (21, "SURFTRAK"),
+];
+
+// PX4 uses the main mode in the third byte and the sub mode in the fourth byte of custom_mode
+// https://github.com/PX4/PX4-Autopilot/blob/main/src/modules/commander/px4_custom_mode.h
+const PX4: &[(u32, &str)] = &[
+    (px4_mode(1, 0), "MANUAL"),
+    (px4_mode(2, 0), "ALTCTL"),
+    (px4_mode(3, 0), "POSCTL"),
+    (px4_mode(4, 1), "AUTO.READY"),
+    (px4_mode(4, 2), "AUTO.TAKEOFF"),
+    (px4_mode(4, 3), "AUTO.LOITER"),
+    (px4_mode(4, 4), "AUTO.MISSION"),
+    (px4_mode(4, 5), "AUTO.RTL"),
+    (px4_mode(4, 6), "AUTO.LAND"),
+    (px4_mode(4, 8), "AUTO.FOLLOW_TARGET"),
+    (px4_mode(4, 9), "AUTO.PRECLAND"),
+    (px4_mode(5, 0), "ACRO"),
+    (px4_mode(6, 0), "OFFBOARD"),
+    (px4_mode(7, 0), "STABILIZED"),
+    (px4_mode(8, 0), "RATTITUDE"),
+];
+
+const fn px4_mode(main_mode: u32, sub_mode: u32) -> u32 {
+    (sub_mode << 24) | (main_mode << 16)
+}
+
+/// Firmwares with known custom modes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Firmware {
+    ArduCopter,
+    ArduPlane,
+    ArduRover,
+    ArduSub,
+    PX4,
+}
+
+impl Firmware {
+    /// Detects the firmware from the MAV_AUTOPILOT and MAV_TYPE names of a heartbeat
+    pub fn from_names(autopilot: &str, mavtype: &str) -> Option<Self> {
+        match autopilot {
+            "MAV_AUTOPILOT_PX4" => Some(Self::PX4),
+            "MAV_AUTOPILOT_ARDUPILOTMEGA" => match mavtype {
+                "MAV_TYPE_SUBMARINE" => Some(Self::ArduSub),
+                "MAV_TYPE_GROUND_ROVER" | "MAV_TYPE_SURFACE_BOAT" => Some(Self::ArduRover),
+                "MAV_TYPE_FIXED_WING" => Some(Self::ArduPlane),
+                // Quadplanes report one of the many VTOL types
+                vtol if vtol.starts_with("MAV_TYPE_VTOL") => Some(Self::ArduPlane),
+                "MAV_TYPE_QUADROTOR"
+                | "MAV_TYPE_HEXAROTOR"
+                | "MAV_TYPE_OCTOROTOR"
+                | "MAV_TYPE_TRICOPTER"
+                | "MAV_TYPE_COAXIAL"
+                | "MAV_TYPE_HELICOPTER"
+                | "MAV_TYPE_DODECAROTOR"
+                | "MAV_TYPE_DECAROTOR" => Some(Self::ArduCopter),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn from_heartbeat(heartbeat: &HEARTBEAT_DATA) -> Option<Self> {
+        Self::from_names(
+            &format!("{:?}", heartbeat.autopilot),
+            &format!("{:?}", heartbeat.mavtype),
+        )
+    }
+
+    fn modes(&self) -> &'static [(u32, &'static str)] {
+        match self {
+            Self::ArduCopter => ARDUCOPTER,
+            Self::ArduPlane => ARDUPLANE,
+            Self::ArduRover => ARDUROVER,
+            Self::ArduSub => ARDUSUB,
+            Self::PX4 => PX4,
+        }
+    }
+
+    pub fn mode_name(&self, custom_mode: u32) -> Option<&'static str> {
+        // PX4 only uses the two highest bytes
+        let custom_mode = match self {
+            Self::PX4 => custom_mode & 0xFFFF_0000,
+            _ => custom_mode,
+        };
+
+        self.modes()
+            .iter()
+            .find(|(value, _)| *value == custom_mode)
+            .map(|(_, name)| *name)
+    }
+
+    pub fn custom_mode(&self, name: &str) -> Option<u32> {
+        self.modes()
+            .iter()
+            .find(|(_, mode_name)| mode_name.eq_ignore_ascii_case(name))
+            .map(|(value, _)| *value)
+    }
+
+    pub fn mode_names(&self) -> Vec<&'static str> {
+        self.modes().iter().map(|(_, name)| *name).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum SetModeError {
+    /// No heartbeat from an autopilot with known modes
+    UnknownVehicle,
+    /// Mode does not exist, contains the available modes
+    UnknownMode(Vec<&'static str>),
+    Send(std::io::Error),
+    /// Vehicle answered with an error, E.g: MAV_RESULT_DENIED
+    Rejected(String),
+    Timeout,
+}
+
+impl std::fmt::Display for SetModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownVehicle => write!(f, "Vehicle not found or its firmware is unknown."),
+            Self::UnknownMode(modes) => {
+                write!(f, "Unknown mode, available modes: {}.", modes.join(", "))
+            }
+            Self::Send(error) => write!(f, "Failed to send command: {error}"),
+            Self::Rejected(result) => write!(f, "Vehicle rejected the mode: {result}"),
+            Self::Timeout => write!(f, "Vehicle did not change its mode."),
+        }
+    }
+}
+
+/// Changes the mode of a vehicle by name, blocks until its heartbeat reports the new mode
+/// or the vehicle rejects the command, the timeout starts after the command is sent
+pub fn set_mode(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    name: &str,
+    timeout: Duration,
+) -> Result<&'static str, SetModeError> {
+    let autopilot = data::autopilot_heartbeat(system_id).ok_or(SetModeError::UnknownVehicle)?;
+    let component_id = autopilot.header.component_id;
+    let firmware = match &autopilot.message {
+        MavMessage::HEARTBEAT(heartbeat) => Firmware::from_heartbeat(heartbeat),
+        _ => None,
+    }
+    .ok_or(SetModeError::UnknownVehicle)?;
+
+    let custom_mode = firmware
+        .custom_mode(name)
+        .ok_or_else(|| SetModeError::UnknownMode(firmware.mode_names()))?;
+    let mode_name = firmware.mode_name(custom_mode).unwrap();
+
+    // SET_MODE can't carry MAV_MODE_FLAG_CUSTOM_MODE_ENABLED as its base_mode is a MAV_MODE,
+    // which ArduPilot and PX4 require, so every firmware gets MAV_CMD_DO_SET_MODE,
+    // PX4 expects the main and sub modes in separated params
+    let (param2, param3) = match firmware {
+        Firmware::PX4 => (
+            ((custom_mode >> 16) & 0xFF) as f32,
+            (custom_mode >> 24) as f32,
+        ),
+        _ => (custom_mode as f32, 0.0),
+    };
+    let command = Command::long(
+        MavCmd::MAV_CMD_DO_SET_MODE,
+        // MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
+        [1.0, param2, param3, 0.0, 0.0, 0.0, 0.0],
+    );
+
+    change_mode(
+        |command, timeout| command::send(vehicle, system_id, component_id, command, timeout),
+        |timeout| match data::wait_for_message(
+            "HEARTBEAT",
+            Some(system_id),
+            Some(component_id),
+            timeout,
+        )
+        .map(|heartbeat| heartbeat.message)
+        {
+            Some(MavMessage::HEARTBEAT(heartbeat)) => Some(heartbeat),
+            _ => None,
+        },
+        &command,
+        firmware,
+        mode_name,
+        timeout,
+    )
+}
+
+// The command has its own budget, so vehicles that never acknowledge it still get
+// the whole timeout to report the new mode in their heartbeat
+fn change_mode(
+    send: impl FnOnce(&Command, Duration) -> Result<CommandResult, CommandError>,
+    mut next_heartbeat: impl FnMut(Duration) -> Option<HEARTBEAT_DATA>,
+    command: &Command,
+    firmware: Firmware,
+    mode_name: &'static str,
+    timeout: Duration,
+) -> Result<&'static str, SetModeError> {
+    match send(command, RETRY_TIMEOUT * RETRIES as u32) {
+        Ok(result) if !result.accepted => return Err(SetModeError::Rejected(result.result)),
+        Err(CommandError::Send(error)) => return Err(SetModeError::Send(error)),
+        // Some vehicles do not acknowledge the command, their heartbeat still reports the mode
+        Ok(_) | Err(CommandError::Timeout(_)) => {}
+    }
+
+    let deadline = Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if let Some(heartbeat) = next_heartbeat(remaining) {
+            if firmware.mode_name(heartbeat.custom_mode) == Some(mode_name) {
+                return Ok(mode_name);
+            }
+        }
+    }
+
+    Err(SetModeError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_names() {
+        let copter = Firmware::from_names("MAV_AUTOPILOT_ARDUPILOTMEGA", "MAV_TYPE_QUADROTOR");
+        assert_eq!(copter, Some(Firmware::ArduCopter));
+        assert_eq!(copter.unwrap().mode_name(4), Some("GUIDED"));
+        assert_eq!(copter.unwrap().custom_mode("loiter"), Some(5));
+
+        let px4 = Firmware::from_names("MAV_AUTOPILOT_PX4", "MAV_TYPE_FIXED_WING").unwrap();
+        assert_eq!(px4.mode_name(0x0404_0000), Some("AUTO.MISSION"));
+        assert_eq!(px4.custom_mode("POSCTL"), Some(0x0003_0000));
+
+        assert_eq!(
+            Firmware::from_names("MAV_AUTOPILOT_INVALID", "MAV_TYPE_GCS"),
+            None
+        );
+    }
+
+    fn guided() -> Command {
+        Command::long(
+            MavCmd::MAV_CMD_DO_SET_MODE,
+            [1.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        )
+    }
+
+    fn heartbeat(custom_mode: u32) -> Option<HEARTBEAT_DATA> {
+        Some(HEARTBEAT_DATA {
+            custom_mode,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn mode_without_ack() {
+        let timeout = Duration::from_millis(500);
+        let mut heartbeats = vec![heartbeat(4), heartbeat(5)];
+        let result = change_mode(
+            |_, budget| {
+                // The vehicle never answers, the command takes its whole budget
+                assert!(budget > timeout);
+                Err(CommandError::Timeout(None))
+            },
+            |remaining| {
+                assert!(remaining <= timeout);
+                heartbeats.pop().flatten()
+            },
+            &guided(),
+            Firmware::ArduCopter,
+            "GUIDED",
+            timeout,
+        );
+        assert_eq!(result.unwrap(), "GUIDED");
+        assert!(heartbeats.is_empty());
+    }
+
+    #[test]
+    fn mode_rejected() {
+        let result = change_mode(
+            |_, _| {
+                Ok(CommandResult {
+                    result: "MAV_RESULT_DENIED".to_string(),
+                    accepted: false,
+                    progress: None,
+                    result_param2: 0,
+                    attempts: 1,
+                })
+            },
+            |_| panic!("A rejected mode should not wait for heartbeats"),
+            &guided(),
+            Firmware::ArduCopter,
+            "GUIDED",
+            Duration::from_millis(500),
+        );
+        match result {
+            Err(SetModeError::Rejected(result)) => assert_eq!(result, "MAV_RESULT_DENIED"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn mode_timeout() {
+        let result = change_mode(
+            |_, _| Err(CommandError::Timeout(None)),
+            |remaining| {
+                std::thread::sleep(remaining);
+                heartbeat(5)
+            },
+            &guided(),
+            Firmware::ArduCopter,
+            "GUIDED",
+            Duration::from_millis(50),
+        );
+        assert!(matches!(result, Err(SetModeError::Timeout)));
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{mpsc, Mutex};
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::{
+    MavAutopilot, MavMessage, MavParamType, MavProtocolCapability, PARAM_REQUEST_LIST_DATA,
+    PARAM_REQUEST_READ_DATA, PARAM_SET_DATA, PARAM_VALUE_DATA,
+};
+use serde::Serialize;
+
+use crate::data::{self, MAVLinkMessage};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{RETRIES, RETRY_TIMEOUT};
+use crate::version;
+use crate::websocket_manager;
+
+// Missing parameters are requested in batches, to not flood the link
+const BATCH_SIZE: usize = 10;
+
+/// Parameter of a component, with its value decoded by its type
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    /// Number for integer types, float for real types
+    pub value: serde_json::Value,
+    /// MAV_PARAM_TYPE name, E.g: MAV_PARAM_TYPE_REAL32
+    pub param_type: String,
+    pub index: u16,
+    #[serde(skip)]
+    mavtype: MavParamType,
+}
+
+/// Parameters received from a component
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ParameterTable {
+    /// Number of parameters reported by the component
+    pub count: u16,
+    pub parameters: BTreeMap<String, Parameter>,
+}
+
+#[derive(Debug)]
+pub enum ParameterError {
+    NotFound(String),
+    InvalidValue(String),
+    Send(std::io::Error),
+    Timeout,
+    /// Parameters still missing after all retries, received and total count
+    Incomplete(usize, u16),
+    /// Vehicle answered with a different value
+    Rejected(Parameter),
+    /// Index that PARAM_REQUEST_READ can not request, it's limited to i16
+    InvalidIndex(u16),
+}
+
+impl std::fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "Parameter {name} not found."),
+            Self::InvalidValue(error) => write!(f, "Invalid value: {error}"),
+            Self::Send(error) => write!(f, "Failed to send request: {error}"),
+            Self::Timeout => write!(f, "Timeout while waiting for parameters."),
+            Self::Incomplete(received, count) => {
+                write!(f, "Received only {received} of {count} parameters.")
+            }
+            Self::Rejected(parameter) => {
+                write!(f, "Vehicle kept {} as {}.", parameter.name, parameter.value)
+            }
+            Self::InvalidIndex(index) => write!(f, "Parameter index {index} can not be requested."),
+        }
+    }
+}
+
+// Parameters are sent as float, but integers can be a cast or their bytes inside of the float
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Cast,
+    Bytewise,
+}
+
+// The capabilities of AUTOPILOT_VERSION, from the component or its autopilot,
+// have priority over the autopilot type. The autopilot type is returned for the cache.
+fn calculate_encoding(system_id: u8, component_id: u8) -> (Encoding, MavAutopilot) {
+    let heartbeat = data::autopilot_heartbeat(system_id);
+    let autopilot_id = heartbeat
+        .as_ref()
+        .map(|heartbeat| heartbeat.header.component_id);
+    let autopilot = match heartbeat.map(|heartbeat| heartbeat.message) {
+        Some(MavMessage::HEARTBEAT(heartbeat)) => heartbeat.autopilot,
+        _ => MavAutopilot::MAV_AUTOPILOT_INVALID,
+    };
+
+    for id in std::iter::once(component_id).chain(autopilot_id) {
+        let capability = |flag| version::has_capability(system_id, id, flag);
+        if capability(MavProtocolCapability::MAV_PROTOCOL_CAPABILITY_PARAM_ENCODE_C_CAST)
+            == Some(true)
+        {
+            return (Encoding::Cast, autopilot);
+        }
+        if capability(MavProtocolCapability::MAV_PROTOCOL_CAPABILITY_PARAM_ENCODE_BYTEWISE)
+            == Some(true)
+        {
+            return (Encoding::Bytewise, autopilot);
+        }
+    }
+
+    match autopilot {
+        MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA => (Encoding::Cast, autopilot),
+        _ => (Encoding::Bytewise, autopilot),
+    }
+}
+
+fn encoding(system_id: u8, component_id: u8) -> Encoding {
+    ENCODINGS
+        .lock()
+        .unwrap()
+        .entry((system_id, component_id))
+        .or_insert_with(|| calculate_encoding(system_id, component_id))
+        .0
+}
+
+// Encodings are calculated again when the autopilot type or the capabilities change
+fn invalidate_encodings(message: &MAVLinkMessage<MavMessage>) {
+    let system_id = message.header.system_id;
+    let mut encodings = ENCODINGS.lock().unwrap();
+    match &message.message {
+        MavMessage::HEARTBEAT(heartbeat)
+            if heartbeat.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID =>
+        {
+            encodings.retain(|(id, _), (_, autopilot)| {
+                *id != system_id || *autopilot == heartbeat.autopilot
+            });
+        }
+        MavMessage::AUTOPILOT_VERSION(_) => encodings.retain(|(id, _), _| *id != system_id),
+        _ => {}
+    }
+}
+
+fn decode(value: f32, mavtype: MavParamType, encoding: Encoding) -> serde_json::Value {
+    let bits = value.to_bits();
+    let integer: i64 = match (mavtype, encoding) {
+        (MavParamType::MAV_PARAM_TYPE_REAL32 | MavParamType::MAV_PARAM_TYPE_REAL64, _) => {
+            // Shortest representation, E.g: 0.1 instead of 0.10000000149011612
+            return serde_json::json!(value.to_string().parse::<f64>().unwrap_or_default());
+        }
+        (_, Encoding::Cast) => value as i64,
+        (MavParamType::MAV_PARAM_TYPE_UINT8, _) => bits as u8 as i64,
+        (MavParamType::MAV_PARAM_TYPE_INT8, _) => bits as u8 as i8 as i64,
+        (MavParamType::MAV_PARAM_TYPE_UINT16, _) => bits as u16 as i64,
+        (MavParamType::MAV_PARAM_TYPE_INT16, _) => bits as u16 as i16 as i64,
+        (MavParamType::MAV_PARAM_TYPE_INT32, _) => bits as i32 as i64,
+        _ => bits as i64,
+    };
+    serde_json::json!(integer)
+}
+
+fn encode(
+    value: &serde_json::Value,
+    mavtype: MavParamType,
+    encoding: Encoding,
+) -> Result<f32, ParameterError> {
+    let invalid = || ParameterError::InvalidValue(format!("{value} is not a number"));
+
+    if matches!(
+        mavtype,
+        MavParamType::MAV_PARAM_TYPE_REAL32 | MavParamType::MAV_PARAM_TYPE_REAL64
+    ) {
+        return value.as_f64().map(|value| value as f32).ok_or_else(invalid);
+    }
+
+    let integer = value.as_i64().ok_or_else(|| {
+        ParameterError::InvalidValue(format!("{value} is not an integer, type is {mavtype:?}"))
+    })?;
+    let (minimum, maximum) = match mavtype {
+        MavParamType::MAV_PARAM_TYPE_UINT8 => (u8::MIN as i64, u8::MAX as i64),
+        MavParamType::MAV_PARAM_TYPE_INT8 => (i8::MIN as i64, i8::MAX as i64),
+        MavParamType::MAV_PARAM_TYPE_UINT16 => (u16::MIN as i64, u16::MAX as i64),
+        MavParamType::MAV_PARAM_TYPE_INT16 => (i16::MIN as i64, i16::MAX as i64),
+        MavParamType::MAV_PARAM_TYPE_UINT32 => (u32::MIN as i64, u32::MAX as i64),
+        _ => (i32::MIN as i64, i32::MAX as i64),
+    };
+    if integer < minimum || integer > maximum {
+        return Err(ParameterError::InvalidValue(format!(
+            "{integer} is out of the range of {mavtype:?}"
+        )));
+    }
+
+    Ok(match encoding {
+        Encoding::Cast => integer as f32,
+        Encoding::Bytewise => f32::from_bits(match mavtype {
+            MavParamType::MAV_PARAM_TYPE_INT8 => integer as i8 as u8 as u32,
+            MavParamType::MAV_PARAM_TYPE_INT16 => integer as i16 as u16 as u32,
+            _ => integer as u32,
+        }),
+    })
+}
+
+fn param_name(param_id: &[u8]) -> String {
+    let length = param_id
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(param_id.len());
+    String::from_utf8_lossy(&param_id[..length]).to_string()
+}
+
+fn param_id(name: &str) -> [u8; 16] {
+    let mut id = [0; 16];
+    let length = name.len().min(id.len());
+    id[..length].copy_from_slice(&name.as_bytes()[..length]);
+    id
+}
+
+lazy_static! {
+    static ref TABLES: Mutex<HashMap<(u8, u8), ParameterTable>> = Mutex::new(HashMap::new());
+    // Encoding of each component with the autopilot type used to calculate it
+    static ref ENCODINGS: Mutex<HashMap<(u8, u8), (Encoding, MavAutopilot)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Updates the parameter cache with PARAM_VALUE messages, changes are sent as events
+pub fn update(message: &MAVLinkMessage<MavMessage>) {
+    let value: &PARAM_VALUE_DATA = match &message.message {
+        MavMessage::PARAM_VALUE(value) => value,
+        _ => return invalidate_encodings(message),
+    };
+
+    let system_id = message.header.system_id;
+    let component_id = message.header.component_id;
+    let mut tables = TABLES.lock().unwrap();
+    let table = tables.entry((system_id, component_id)).or_default();
+    table.count = value.param_count;
+
+    let name = param_name(&value.param_id[..]);
+    let previous = table.parameters.get(&name);
+    // Answers to requests by name may not have the index
+    let index = match (value.param_index, previous) {
+        (u16::MAX, Some(previous)) => previous.index,
+        (index, _) => index,
+    };
+    let parameter = Parameter {
+        name: name.clone(),
+        value: decode(
+            value.param_value,
+            value.param_type,
+            encoding(system_id, component_id),
+        ),
+        param_type: format!("{:?}", value.param_type),
+        index,
+        mavtype: value.param_type,
+    };
+
+    if previous.is_some_and(|previous| previous.value != parameter.value) {
+        websocket_manager::send_event(
+            "parameter",
+            &serde_json::json!({
+                "system_id": system_id,
+                "component_id": component_id,
+                "parameter": parameter,
+            }),
+        );
+    }
+    table.parameters.insert(name, parameter);
+}
+
+/// Cached parameters of a component
+pub fn table(system_id: u8, component_id: u8) -> Option<ParameterTable> {
+    TABLES
+        .lock()
+        .unwrap()
+        .get(&(system_id, component_id))
+        .cloned()
+}
+
+fn cached(system_id: u8, component_id: u8, name: &str) -> Option<Parameter> {
+    TABLES
+        .lock()
+        .unwrap()
+        .get(&(system_id, component_id))?
+        .parameters
+        .get(name)
+        .cloned()
+}
+
+fn is_param_value(
+    message: &MAVLinkMessage<MavMessage>,
+    system_id: u8,
+    component_id: u8,
+) -> Option<&PARAM_VALUE_DATA> {
+    if message.header.system_id != system_id || message.header.component_id != component_id {
+        return None;
+    }
+    match &message.message {
+        MavMessage::PARAM_VALUE(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Collects PARAM_VALUE messages until nothing arrives for the retry timeout
+fn receive_values(
+    receiver: &mpsc::Receiver<MAVLinkMessage<MavMessage>>,
+    system_id: u8,
+    component_id: u8,
+    received: &mut HashSet<u16>,
+    count: &mut Option<u16>,
+) {
+    while let Ok(message) = receiver.recv_timeout(RETRY_TIMEOUT) {
+        if let Some(value) = is_param_value(&message, system_id, component_id) {
+            *count = Some(value.param_count);
+            received.insert(value.param_index);
+            if received.len() >= value.param_count as usize {
+                return;
+            }
+        }
+    }
+}
+
+/// Downloads all parameters of a component, missing parameters are requested by their index
+pub fn download(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<ParameterTable, ParameterError> {
+    let receiver = data::subscribe();
+    let send = |message: MavMessage| {
+        vehicle
+            .lock()
+            .unwrap()
+            .send_default(&message)
+            .map_err(ParameterError::Send)
+    };
+
+    let mut received = HashSet::new();
+    let mut count = None;
+    for _ in 0..RETRIES {
+        send(MavMessage::PARAM_REQUEST_LIST(PARAM_REQUEST_LIST_DATA {
+            target_system: system_id,
+            target_component: component_id,
+        }))?;
+        receive_values(
+            &receiver,
+            system_id,
+            component_id,
+            &mut received,
+            &mut count,
+        );
+        if count.is_some() {
+            break;
+        }
+    }
+    let count = count.ok_or(ParameterError::Timeout)?;
+
+    for _ in 0..RETRIES {
+        let missing: Vec<u16> = (0..count)
+            .filter(|index| !received.contains(index))
+            .collect();
+        if missing.is_empty() {
+            break;
+        }
+
+        for batch in missing.chunks(BATCH_SIZE) {
+            for index in batch {
+                let param_index =
+                    i16::try_from(*index).map_err(|_| ParameterError::InvalidIndex(*index))?;
+                send(MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+                    param_index,
+                    target_system: system_id,
+                    target_component: component_id,
+                    ..Default::default()
+                }))?;
+            }
+            let mut count = Some(count);
+            receive_values(
+                &receiver,
+                system_id,
+                component_id,
+                &mut received,
+                &mut count,
+            );
+        }
+    }
+
+    let received = (0..count).filter(|index| received.contains(index)).count();
+    if received < count as usize {
+        return Err(ParameterError::Incomplete(received, count));
+    }
+
+    // The cache is updated before the messages reach the subscribers
+    Ok(table(system_id, component_id).unwrap_or_default())
+}
+
+// Sends the message until a PARAM_VALUE with the name arrives
+fn request_value(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    message: MavMessage,
+) -> Result<Parameter, ParameterError> {
+    let receiver = data::subscribe();
+
+    for _ in 0..RETRIES {
+        vehicle
+            .lock()
+            .unwrap()
+            .send_default(&message)
+            .map_err(ParameterError::Send)?;
+
+        let deadline = Instant::now() + RETRY_TIMEOUT;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let message = match receiver.recv_timeout(remaining) {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            let value = match is_param_value(&message, system_id, component_id) {
+                Some(value) => value,
+                None => continue,
+            };
+            if param_name(&value.param_id[..]) == name {
+                return cached(system_id, component_id, name)
+                    .ok_or_else(|| ParameterError::NotFound(name.to_string()));
+            }
+        }
+    }
+
+    Err(ParameterError::Timeout)
+}
+
+/// Reads a parameter from the component, or from the cache if it was already received
+pub fn get(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    refresh: bool,
+) -> Result<Parameter, ParameterError> {
+    if !refresh {
+        if let Some(parameter) = cached(system_id, component_id, name) {
+            return Ok(parameter);
+        }
+    }
+
+    let request = MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+        param_index: -1,
+        target_system: system_id,
+        target_component: component_id,
+        param_id: param_id(name).into(),
+    });
+
+    request_value(vehicle, system_id, component_id, name, request).map_err(|error| match error {
+        ParameterError::Timeout => ParameterError::NotFound(name.to_string()),
+        error => error,
+    })
+}
+
+/// Changes a parameter, waits for the component to confirm its new value
+pub fn set(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    value: &serde_json::Value,
+) -> Result<Parameter, ParameterError> {
+    // The type is necessary to encode the value
+    let current = get(vehicle, system_id, component_id, name, false)?;
+    let encoding = encoding(system_id, component_id);
+    let encoded = encode(value, current.mavtype, encoding)?;
+
+    let request = MavMessage::PARAM_SET(PARAM_SET_DATA {
+        param_value: encoded,
+        target_system: system_id,
+        target_component: component_id,
+        param_id: param_id(name).into(),
+        param_type: current.mavtype,
+    });
+
+    let parameter = request_value(vehicle, system_id, component_id, name, request)?;
+    if parameter.value != decode(encoded, current.mavtype, encoding) {
+        return Err(ParameterError::Rejected(parameter));
+    }
+
+    Ok(parameter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Encodes and decodes the value, returning the float sent to the vehicle
+    fn round_trip(value: serde_json::Value, mavtype: MavParamType, encoding: Encoding) -> f32 {
+        let encoded = encode(&value, mavtype, encoding).unwrap();
+        assert_eq!(decode(encoded, mavtype, encoding), value);
+        encoded
+    }
+
+    fn assert_out_of_range(value: i64, mavtype: MavParamType) {
+        for encoding in [Encoding::Cast, Encoding::Bytewise] {
+            assert!(encode(&json!(value), mavtype, encoding).is_err());
+        }
+    }
+
+    #[test]
+    fn uint8() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_UINT8;
+        assert_eq!(round_trip(json!(200), mavtype, Encoding::Cast), 200.0);
+        assert_eq!(
+            round_trip(json!(200), mavtype, Encoding::Bytewise).to_bits(),
+            200
+        );
+        assert_out_of_range(256, mavtype);
+        assert_out_of_range(-1, mavtype);
+    }
+
+    #[test]
+    fn int8() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_INT8;
+        assert_eq!(round_trip(json!(-100), mavtype, Encoding::Cast), -100.0);
+        assert_eq!(
+            round_trip(json!(-100), mavtype, Encoding::Bytewise).to_bits(),
+            0x9c
+        );
+        assert_out_of_range(128, mavtype);
+        assert_out_of_range(-129, mavtype);
+    }
+
+    #[test]
+    fn uint16() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_UINT16;
+        assert_eq!(round_trip(json!(60000), mavtype, Encoding::Cast), 60000.0);
+        assert_eq!(
+            round_trip(json!(60000), mavtype, Encoding::Bytewise).to_bits(),
+            60000
+        );
+        assert_out_of_range(65536, mavtype);
+        assert_out_of_range(-1, mavtype);
+    }
+
+    #[test]
+    fn int16() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_INT16;
+        assert_eq!(round_trip(json!(-30000), mavtype, Encoding::Cast), -30000.0);
+        assert_eq!(
+            round_trip(json!(-30000), mavtype, Encoding::Bytewise).to_bits(),
+            (-30000i16) as u16 as u32
+        );
+        assert_out_of_range(32768, mavtype);
+        assert_out_of_range(-32769, mavtype);
+    }
+
+    #[test]
+    fn uint32() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_UINT32;
+        assert_eq!(round_trip(json!(65536), mavtype, Encoding::Cast), 65536.0);
+        // Bytewise keeps values that a float can not represent
+        let value = 4_000_000_001u32;
+        assert_eq!(
+            round_trip(json!(value), mavtype, Encoding::Bytewise).to_bits(),
+            value
+        );
+        assert_out_of_range(1 << 32, mavtype);
+        assert_out_of_range(-1, mavtype);
+    }
+
+    #[test]
+    fn int32() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_INT32;
+        assert_eq!(round_trip(json!(-70000), mavtype, Encoding::Cast), -70000.0);
+        let value = -16_777_217i32;
+        assert_eq!(
+            round_trip(json!(value), mavtype, Encoding::Bytewise).to_bits(),
+            value as u32
+        );
+        assert_out_of_range(1 << 31, mavtype);
+        assert_out_of_range(-(1 << 31) - 1, mavtype);
+    }
+
+    #[test]
+    fn real32() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_REAL32;
+        for encoding in [Encoding::Cast, Encoding::Bytewise] {
+            assert_eq!(round_trip(json!(0.1), mavtype, encoding), 0.1);
+            assert_eq!(round_trip(json!(-2.5), mavtype, encoding), -2.5);
+        }
+        assert!(encode(&json!("text"), mavtype, Encoding::Cast).is_err());
+    }
+
+    #[test]
+    fn integers_are_required() {
+        let mavtype = MavParamType::MAV_PARAM_TYPE_INT32;
+        assert!(encode(&json!(1.5), mavtype, Encoding::Cast).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::*;
+#[allow(deprecated)]
+use mavlink::ardupilotmega::{MavCmd, MavMessage, REQUEST_DATA_STREAM_DATA};
+use mavlink::Message;
+use serde::Serialize;
+
+use crate::command::{self, Command, CommandError};
+use crate::data;
+use crate::inventory;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+
+// Time to wait for each command of the rate requests
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Legacy MAV_DATA_STREAM of the messages, following the ArduPilot stream groups
+const STREAMS: &[(u8, &[&str])] = &[
+    (
+        1, // MAV_DATA_STREAM_RAW_SENSORS
+        &[
+            "RAW_IMU",
+            "SCALED_IMU2",
+            "SCALED_IMU3",
+            "SCALED_PRESSURE",
+            "SCALED_PRESSURE2",
+            "SCALED_PRESSURE3",
+        ],
+    ),
+    (
+        2, // MAV_DATA_STREAM_EXTENDED_STATUS
+        &[
+            "SYS_STATUS",
+            "POWER_STATUS",
+            "MEMINFO",
+            "MISSION_CURRENT",
+            "GPS_RAW_INT",
+            "GPS_RTK",
+            "GPS2_RAW",
+            "GPS2_RTK",
+            "NAV_CONTROLLER_OUTPUT",
+            "FENCE_STATUS",
+        ],
+    ),
+    (
+        3, // MAV_DATA_STREAM_RC_CHANNELS
+        &["SERVO_OUTPUT_RAW", "RC_CHANNELS", "RC_CHANNELS_RAW"],
+    ),
+    (
+        6, // MAV_DATA_STREAM_POSITION
+        &["GLOBAL_POSITION_INT", "LOCAL_POSITION_NED"],
+    ),
+    (
+        10, // MAV_DATA_STREAM_EXTRA1
+        &["ATTITUDE", "SIMSTATE", "AHRS2", "PID_TUNING"],
+    ),
+    (
+        11, // MAV_DATA_STREAM_EXTRA2
+        &["VFR_HUD"],
+    ),
+    (
+        12, // MAV_DATA_STREAM_EXTRA3
+        &[
+            "AHRS",
+            "HWSTATUS",
+            "SYSTEM_TIME",
+            "RANGEFINDER",
+            "DISTANCE_SENSOR",
+            "TERRAIN_REQUEST",
+            "BATTERY_STATUS",
+            "OPTICAL_FLOW",
+            "EKF_STATUS_REPORT",
+            "VIBRATION",
+            "ESC_TELEMETRY_1_TO_4",
+        ],
+    ),
+];
+
+lazy_static! {
+    // Message rates in Hz that are applied to every autopilot that appears
+    static ref PROFILE: Mutex<BTreeMap<String, f32>> = Mutex::new(BTreeMap::new());
+}
+
+// Set when the profile changes, the worker applies it again to the online autopilots
+static PROFILE_CHANGED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum RateError {
+    UnknownMessage(String),
+    InvalidRate(f32),
+    Command(CommandError),
+    /// Vehicle rejected the request and the message has no legacy stream
+    Rejected(String),
+    /// Rate that REQUEST_DATA_STREAM can't request for the legacy stream
+    UnsupportedLegacyRate(Option<f32>),
+}
+
+impl std::fmt::Display for RateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownMessage(name) => write!(f, "Unknown message: {name}"),
+            Self::InvalidRate(rate) => write!(f, "Invalid rate: {rate}"),
+            Self::Command(error) => write!(f, "{error}"),
+            Self::Rejected(result) => write!(f, "Vehicle rejected the rate: {result}"),
+            Self::UnsupportedLegacyRate(None) => write!(
+                f,
+                "Vehicle does not support SET_MESSAGE_INTERVAL, the default rate can't be restored"
+            ),
+            Self::UnsupportedLegacyRate(Some(rate)) => write!(
+                f,
+                "Vehicle does not support SET_MESSAGE_INTERVAL, REQUEST_DATA_STREAM rates are whole Hz: {rate}"
+            ),
+        }
+    }
+}
+
+/// Rate of a message, as configured in the vehicle and as received
+#[derive(Clone, Debug, Serialize)]
+pub struct MessageRate {
+    pub message: String,
+    /// Interval reported by the vehicle, -1 if disabled and 0 if unknown
+    pub interval_us: Option<i32>,
+    pub rate_hz: Option<f32>,
+    /// Frequency of the received messages
+    pub measured_hz: Option<f32>,
+    /// Request used to change the rate: SET_MESSAGE_INTERVAL or REQUEST_DATA_STREAM
+    pub method: Option<&'static str>,
+    /// MAV_DATA_STREAM changed by REQUEST_DATA_STREAM, with all the messages of its group
+    pub stream_id: Option<u8>,
+}
+
+fn message_id(name: &str) -> Result<u32, RateError> {
+    MavMessage::message_id_from_name(name).ok_or_else(|| RateError::UnknownMessage(name.into()))
+}
+
+fn stream_id(name: &str) -> Option<u8> {
+    STREAMS
+        .iter()
+        .find(|(_, messages)| messages.contains(&name))
+        .map(|(stream_id, _)| *stream_id)
+}
+
+fn measured_rate(system_id: u8, component_id: u8, name: &str) -> Option<f32> {
+    data::statistics()
+        .into_iter()
+        .find(|statistics| {
+            statistics.system_id == system_id
+                && statistics.component_id == component_id
+                && statistics.name == name
+        })
+        .map(|statistics| statistics.frequency)
+}
+
+/// Current interval of a message, requested with MAV_CMD_GET_MESSAGE_INTERVAL
+pub fn get(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+) -> Result<MessageRate, RateError> {
+    let id = message_id(name)?;
+
+    // The answer may arrive before the COMMAND_ACK
+    let receiver = data::subscribe();
+    #[allow(deprecated)]
+    let command = Command::long(
+        MavCmd::MAV_CMD_GET_MESSAGE_INTERVAL,
+        [id as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    );
+    let result = command::send(vehicle, system_id, component_id, &command, COMMAND_TIMEOUT)
+        .map_err(RateError::Command)?;
+
+    let mut interval_us = None;
+    if result.accepted {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let message = match receiver.recv_timeout(remaining) {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            match message.message {
+                MavMessage::MESSAGE_INTERVAL(interval)
+                    if interval.message_id as u32 == id
+                        && message.header.system_id == system_id
+                        && message.header.component_id == component_id =>
+                {
+                    interval_us = Some(interval.interval_us);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(MessageRate {
+        message: name.to_string(),
+        interval_us,
+        rate_hz: interval_us
+            .filter(|interval| *interval > 0)
+            .map(|interval| 1e6 / interval as f32),
+        measured_hz: measured_rate(system_id, component_id, name),
+        method: None,
+        stream_id: None,
+    })
+}
+
+/// REQUEST_DATA_STREAM for the legacy stream of a message, it has no default rate
+/// and only whole rates in Hz
+#[allow(deprecated)]
+fn legacy_request(
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    rate: Option<f32>,
+) -> Result<REQUEST_DATA_STREAM_DATA, RateError> {
+    let stream_id = stream_id(name)
+        .ok_or_else(|| RateError::Rejected(format!("{name} has no legacy data stream")))?;
+    let req_message_rate = match rate {
+        Some(rate) if rate == 0.0 || rate.round() >= 1.0 => rate.round().min(u16::MAX as f32),
+        _ => return Err(RateError::UnsupportedLegacyRate(rate)),
+    };
+    Ok(REQUEST_DATA_STREAM_DATA {
+        req_message_rate: req_message_rate as u16,
+        target_system: system_id,
+        target_component: component_id,
+        req_stream_id: stream_id,
+        start_stop: (req_message_rate != 0.0) as u8,
+    })
+}
+
+/// Changes the rate of a message in Hz, 0 disables it and None restores the default rate.
+/// Vehicles without MAV_CMD_SET_MESSAGE_INTERVAL receive a REQUEST_DATA_STREAM of its stream,
+/// which changes every message of the group.
+pub fn set(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    rate: Option<f32>,
+) -> Result<MessageRate, RateError> {
+    let id = message_id(name)?;
+    let interval_us = match rate {
+        None => 0.0,
+        Some(0.0) => -1.0,
+        Some(rate) if rate.is_finite() && rate > 0.0 => 1e6 / rate,
+        Some(rate) => return Err(RateError::InvalidRate(rate)),
+    };
+
+    let command = Command::long(
+        MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
+        [id as f32, interval_us, 0.0, 0.0, 0.0, 0.0, 0.0],
+    );
+    let result = command::send(vehicle, system_id, component_id, &command, COMMAND_TIMEOUT);
+    match result {
+        Ok(result) if result.accepted => Ok(MessageRate {
+            message: name.to_string(),
+            interval_us: Some(interval_us as i32),
+            rate_hz: rate,
+            measured_hz: measured_rate(system_id, component_id, name),
+            method: Some("SET_MESSAGE_INTERVAL"),
+            stream_id: None,
+        }),
+        Ok(result) if result.result != "MAV_RESULT_UNSUPPORTED" => {
+            Err(RateError::Rejected(result.result))
+        }
+        // Older autopilots do not answer or do not support the command
+        Ok(_) | Err(CommandError::Timeout(None)) => {
+            let request = legacy_request(system_id, component_id, name, rate)?;
+            #[allow(deprecated)]
+            let (rate, stream_id) = (request.req_message_rate as f32, request.req_stream_id);
+            #[allow(deprecated)]
+            let message = MavMessage::REQUEST_DATA_STREAM(request);
+            vehicle
+                .lock()
+                .unwrap()
+                .send_default(&message)
+                .map_err(|error| RateError::Command(CommandError::Send(error)))?;
+            Ok(MessageRate {
+                message: name.to_string(),
+                interval_us: Some(if rate > 0.0 { (1e6 / rate) as i32 } else { -1 }),
+                rate_hz: Some(rate),
+                measured_hz: measured_rate(system_id, component_id, name),
+                method: Some("REQUEST_DATA_STREAM"),
+                stream_id: Some(stream_id),
+            })
+        }
+        Err(error) => Err(RateError::Command(error)),
+    }
+}
+
+pub fn profile() -> BTreeMap<String, f32> {
+    PROFILE.lock().unwrap().clone()
+}
+
+/// Replaces the rate profile, unknown messages are not accepted
+pub fn set_profile(profile: BTreeMap<String, f32>) -> Result<(), RateError> {
+    for (name, rate) in &profile {
+        message_id(name)?;
+        if !rate.is_finite() || *rate < 0.0 {
+            return Err(RateError::InvalidRate(*rate));
+        }
+    }
+    *PROFILE.lock().unwrap() = profile;
+    PROFILE_CHANGED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Applies the rate profile to a vehicle component
+fn apply_profile(vehicle: &MAVLinkVehicleArcMutex, system_id: u8, component_id: u8) {
+    for (name, rate) in profile() {
+        if let Err(error) = set(vehicle, system_id, component_id, &name, Some(rate)) {
+            warn!("Failed to set rate of {name} for vehicle {system_id}: {error}");
+        }
+    }
+}
+
+/// Applies the rate profile to every autopilot that appears or comes back online,
+/// and to all online autopilots when the profile changes
+pub fn run(vehicle: &MAVLinkVehicleArcMutex, interval: Duration) {
+    let vehicle = vehicle.clone();
+    std::thread::spawn(move || {
+        let mut online: HashSet<(u8, u8)> = HashSet::new();
+        loop {
+            std::thread::sleep(interval);
+            if PROFILE_CHANGED.swap(false, Ordering::Relaxed) {
+                online.clear();
+            }
+
+            let autopilots: HashSet<(u8, u8)> = inventory::inventory()
+                .into_iter()
+                .filter(|component| {
+                    component.online && component.autopilot != "MAV_AUTOPILOT_INVALID"
+                })
+                .map(|component| (component.system_id, component.component_id))
+                .collect();
+
+            for &(system_id, component_id) in autopilots.difference(&online) {
+                if profile().is_empty() {
+                    break;
+                }
+                info!("Applying rate profile to vehicle {system_id} component {component_id}.");
+                let vehicle = vehicle.clone();
+                std::thread::spawn(move || apply_profile(&vehicle, system_id, component_id));
+            }
+            online = autopilots;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_requests() {
+        let request = legacy_request(1, 1, "ATTITUDE", Some(9.6)).unwrap();
+        assert_eq!(request.req_stream_id, 10);
+        assert_eq!(request.req_message_rate, 10);
+        assert_eq!(request.start_stop, 1);
+
+        let request = legacy_request(1, 1, "VFR_HUD", Some(0.0)).unwrap();
+        assert_eq!(request.req_stream_id, 11);
+        assert_eq!(request.start_stop, 0);
+
+        assert!(matches!(
+            legacy_request(1, 1, "ATTITUDE", None),
+            Err(RateError::UnsupportedLegacyRate(None))
+        ));
+        assert!(matches!(
+            legacy_request(1, 1, "ATTITUDE", Some(0.4)),
+            Err(RateError::UnsupportedLegacyRate(Some(_)))
+        ));
+        assert!(matches!(
+            legacy_request(1, 1, "HEARTBEAT", Some(1.0)),
+            Err(RateError::Rejected(_))
+        ));
+    }
+}
@@ -14,7 +14,7 @@ use crate::cli;
 use log::*;
 
 fn json_error_handler(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
-    warn!("Problem with json: {}", error.to_string());
+    warn!("Problem with json: {}", error);
     match error {
         JsonPayloadError::Overflow => JsonPayloadError::Overflow.into(),
         _ => ErrorBadRequest(error.to_string()),
@@ -26,9 +26,248 @@ fn add_v1_paths(scope: Scope) -> Scope {
         .route("/helper/mavlink", web::get().to(endpoints::helper_mavlink))
         .route("/mavlink", web::get().to(endpoints::mavlink))
         .route("/mavlink", web::post().to(endpoints::mavlink_post))
+        .route("/mavlink/wait", web::get().to(endpoints::mavlink_wait))
+        .route(
+            "/mavlink/transaction",
+            web::post().to(endpoints::mavlink_transaction),
+        )
         .route(r"/mavlink/{path:.*}", web::get().to(endpoints::mavlink))
+        .route("/snapshot", web::get().to(endpoints::snapshot))
+        .route("/snapshot", web::post().to(endpoints::snapshot_save))
+        .route("/inventory", web::get().to(endpoints::inventory))
+        .route("/cameras", web::get().to(endpoints::cameras))
+        .route("/gimbals", web::get().to(endpoints::gimbals))
+        .route("/timesync", web::get().to(endpoints::timesync))
+        .route("/rates/profile", web::get().to(endpoints::rates_profile))
+        .route(
+            "/rates/profile",
+            web::post().to(endpoints::rates_profile_post),
+        )
+        .route(
+            "/vehicles/{id}/summary",
+            web::get().to(endpoints::vehicle_summary),
+        )
+        .route(
+            "/vehicles/{id}/version",
+            web::get().to(endpoints::vehicle_version),
+        )
+        .route(
+            "/vehicles/{id}/mode",
+            web::post().to(endpoints::vehicle_mode_post),
+        )
+        .route(
+            "/vehicles/{id}/command",
+            web::post().to(endpoints::vehicle_command_post),
+        )
+        .route(
+            "/vehicles/{id}/actions/arm",
+            web::post().to(endpoints::vehicle_arm),
+        )
+        .route(
+            "/vehicles/{id}/actions/disarm",
+            web::post().to(endpoints::vehicle_disarm),
+        )
+        .route(
+            "/vehicles/{id}/actions/takeoff",
+            web::post().to(endpoints::vehicle_takeoff),
+        )
+        .route(
+            "/vehicles/{id}/actions/land",
+            web::post().to(endpoints::vehicle_land),
+        )
+        .route(
+            "/vehicles/{id}/actions/rtl",
+            web::post().to(endpoints::vehicle_rtl),
+        )
+        .route(
+            "/vehicles/{id}/actions/goto",
+            web::post().to(endpoints::vehicle_goto),
+        )
+        .route(
+            "/vehicles/{id}/actions/speed",
+            web::post().to(endpoints::vehicle_speed),
+        )
+        .route(
+            "/vehicles/{id}/actions/reboot",
+            web::post().to(endpoints::vehicle_reboot),
+        )
+        .route(
+            "/vehicles/{id}/parameters",
+            web::get().to(endpoints::vehicle_parameters),
+        )
+        .route(
+            "/vehicles/{id}/parameters/{name}",
+            web::get().to(endpoints::vehicle_parameter),
+        )
+        .route(
+            "/vehicles/{id}/parameters/{name}",
+            web::post().to(endpoints::vehicle_parameter_post),
+        )
+        .route(
+            "/vehicles/{id}/mission",
+            web::get().to(endpoints::vehicle_mission),
+        )
+        .route(
+            "/vehicles/{id}/mission",
+            web::post().to(endpoints::vehicle_mission_post),
+        )
+        .route(
+            "/vehicles/{id}/mission",
+            web::delete().to(endpoints::vehicle_mission_delete),
+        )
+        .route(
+            "/vehicles/{id}/mission/current",
+            web::post().to(endpoints::vehicle_mission_current_post),
+        )
+        .route(
+            "/vehicles/{id}/mission/progress",
+            web::get().to(endpoints::vehicle_mission_progress),
+        )
+        .route(
+            "/vehicles/{id}/ftp/list",
+            web::get().to(endpoints::vehicle_ftp_list),
+        )
+        .route(
+            "/vehicles/{id}/ftp/file",
+            web::get().to(endpoints::vehicle_ftp_download),
+        )
+        .route(
+            "/vehicles/{id}/ftp/file",
+            web::post().to(endpoints::vehicle_ftp_upload),
+        )
+        .route(
+            "/vehicles/{id}/ftp/file",
+            web::delete().to(endpoints::vehicle_ftp_delete),
+        )
+        .route(
+            "/vehicles/{id}/ftp/directory",
+            web::post().to(endpoints::vehicle_ftp_directory_post),
+        )
+        .route(
+            "/vehicles/{id}/ftp/directory",
+            web::delete().to(endpoints::vehicle_ftp_directory_delete),
+        )
+        .route(
+            "/vehicles/{id}/ftp/crc",
+            web::get().to(endpoints::vehicle_ftp_crc),
+        )
+        .route(
+            "/vehicles/{id}/logs",
+            web::get().to(endpoints::vehicle_logs),
+        )
+        .route(
+            "/vehicles/{id}/logs",
+            web::delete().to(endpoints::vehicle_logs_delete),
+        )
+        .route(
+            "/vehicles/{id}/logs/{log_id}",
+            web::get().to(endpoints::vehicle_log),
+        )
+        .route(
+            "/vehicles/{id}/rates/{message}",
+            web::get().to(endpoints::vehicle_message_rate),
+        )
+        .route(
+            "/vehicles/{id}/rates/{message}",
+            web::post().to(endpoints::vehicle_message_rate_post),
+        )
+        .route(
+            "/vehicles/{id}/fence",
+            web::get().to(endpoints::vehicle_fence),
+        )
+        .route(
+            "/vehicles/{id}/fence",
+            web::post().to(endpoints::vehicle_fence_post),
+        )
+        .route(
+            "/vehicles/{id}/fence",
+            web::delete().to(endpoints::vehicle_fence_delete),
+        )
+        .route(
+            "/vehicles/{id}/rally",
+            web::get().to(endpoints::vehicle_rally),
+        )
+        .route(
+            "/vehicles/{id}/rally",
+            web::post().to(endpoints::vehicle_rally_post),
+        )
+        .route(
+            "/vehicles/{id}/rally",
+            web::delete().to(endpoints::vehicle_rally_delete),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}",
+            web::get().to(endpoints::vehicle_camera),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/photo",
+            web::post().to(endpoints::vehicle_camera_photo_post),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/photo",
+            web::delete().to(endpoints::vehicle_camera_photo_delete),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/video",
+            web::post().to(endpoints::vehicle_camera_video_post),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/video",
+            web::delete().to(endpoints::vehicle_camera_video_delete),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/mode",
+            web::post().to(endpoints::vehicle_camera_mode_post),
+        )
+        .route(
+            "/vehicles/{id}/cameras/{camera_id}/images",
+            web::get().to(endpoints::vehicle_camera_images),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}",
+            web::get().to(endpoints::vehicle_gimbal),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/control",
+            web::post().to(endpoints::vehicle_gimbal_control_post),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/control",
+            web::delete().to(endpoints::vehicle_gimbal_control_delete),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/attitude",
+            web::post().to(endpoints::vehicle_gimbal_attitude_post),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/rate",
+            web::post().to(endpoints::vehicle_gimbal_rate_post),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/roi",
+            web::post().to(endpoints::vehicle_gimbal_roi_post),
+        )
+        .route(
+            "/vehicles/{id}/gimbals/{gimbal_id}/roi",
+            web::delete().to(endpoints::vehicle_gimbal_roi_delete),
+        )
+        .route(
+            "/vehicles/{id}/timesync",
+            web::get().to(endpoints::vehicle_timesync),
+        )
+        .route(
+            "/vehicles/{id}/statustext",
+            web::get().to(endpoints::vehicle_statustext),
+        )
+        .route(
+            "/timeseries/influxdb",
+            web::get().to(endpoints::timeseries_influxdb),
+        )
+        .route("/timeseries/csv", web::get().to(endpoints::timeseries_csv))
         .route("/llm", web::get().to(endpoints::llm))
         .service(web::resource("/ws/mavlink").route(web::get().to(endpoints::websocket)))
+        .service(web::resource("/ws/events").route(web::get().to(endpoints::websocket_events)))
+        .service(web::resource("/ws/gimbal").route(web::get().to(endpoints::websocket_gimbal)))
 }
 
 // Start REST API server with the desired address
@@ -61,6 +300,7 @@ pub fn run(server_address: &str, mavlink_vehicle: &MAVLinkVehicleArcMutex) {
                 web::get().to(endpoints::root),
             )
             .route("/info", web::get().to(endpoints::info))
+            .route("/metrics", web::get().to(endpoints::metrics))
             // Be sure to have default as the latest endpoint, otherwise it does not work
             .service(v1)
             .service(default)
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::statustext::{self, StatusText};
+use crate::timeseries::{self, Sample};
+
+/// Received messages with the history of the status texts and recorded time series
+#[derive(Deserialize, Serialize)]
+struct Snapshot {
+    messages: serde_json::Value,
+    #[serde(default)]
+    statustext: Vec<StatusText>,
+    #[serde(default)]
+    timeseries: Vec<Sample>,
+}
+
+impl Snapshot {
+    // Older snapshots only have the received messages
+    fn parse(content: &str) -> serde_json::Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(content)?;
+        if value.get("messages").is_some() {
+            return serde_json::from_value(value);
+        }
+        Ok(Self {
+            messages: value,
+            statustext: vec![],
+            timeseries: vec![],
+        })
+    }
+}
+
+/// Snapshot of the service as JSON
+pub fn content() -> String {
+    serde_json::to_string(&Snapshot {
+        messages: data::snapshot(),
+        statustext: statustext::history(),
+        timeseries: timeseries::history(),
+    })
+    .unwrap()
+}
+
+pub fn save(path: &Path) -> std::io::Result<()> {
+    // Write a temporary file first, so the last snapshot is not lost if something goes wrong
+    let temporary_path = path.with_extension("tmp");
+    std::fs::write(&temporary_path, content())?;
+    std::fs::rename(&temporary_path, path)
+}
+
+pub fn restore(path: &Path) {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) => {
+            info!("No snapshot restored from {path:?}: {error}");
+            return;
+        }
+    };
+
+    let result = Snapshot::parse(&content).and_then(|snapshot| {
+        data::restore(snapshot.messages)?;
+        statustext::restore(snapshot.statustext);
+        timeseries::restore(snapshot.timeseries);
+        Ok(())
+    });
+    match result {
+        Ok(()) => info!("Snapshot restored from {path:?}"),
+        Err(error) => warn!("Failed to restore snapshot from {path:?}: {error}"),
+    }
+}
+
+// Save the snapshot periodically
+pub fn run(path: PathBuf, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if let Err(error) = save(&path) {
+            error!("Failed to save snapshot to {path:?}: {error}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_snapshots() {
+        let old = Snapshot::parse(r#"{"1": {}}"#).unwrap();
+        assert_eq!(old.messages, serde_json::json!({"1": {}}));
+        assert!(old.statustext.is_empty() && old.timeseries.is_empty());
+
+        let current = Snapshot::parse(
+            r#"{
+                "messages": {"1": {}},
+                "statustext": [{
+                    "time": "2026-10-19T05:00:00Z",
+                    "system_id": 1,
+                    "component_id": 1,
+                    "severity": "MAV_SEVERITY_WARNING",
+                    "severity_value": 4,
+                    "text": "PreArm: Compass not calibrated"
+                }],
+                "timeseries": [{
+                    "utc": "2026-10-19T05:00:00Z",
+                    "system_id": 1,
+                    "component_id": 1,
+                    "name": "VFR_HUD",
+                    "fields": [["alt", 12.5]]
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(current.messages, serde_json::json!({"1": {}}));
+        assert_eq!(current.statustext[0].text, "PreArm: Compass not calibrated");
+        assert_eq!(current.timeseries.len(), 1);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::{MavMessage, STATUSTEXT_DATA};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{MAVLinkMessage, Reception};
+use crate::websocket_manager;
+
+// Maximum number of texts kept per vehicle
+const LOG_SIZE: usize = 1000;
+// Texts missing their last chunk are logged as they are after this time
+const CHUNK_TIMEOUT_MS: i64 = 2000;
+
+/// Status text of a vehicle, reassembled from all of its chunks
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatusText {
+    /// UTC time when the first chunk was received
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub system_id: u8,
+    pub component_id: u8,
+    /// Severity name, E.g: MAV_SEVERITY_WARNING
+    pub severity: String,
+    /// Severity value, lower values are more severe
+    pub severity_value: u8,
+    pub text: String,
+}
+
+#[derive(Debug)]
+struct PendingText {
+    status: StatusText,
+    // Chunks may arrive out of order, they are joined by sequence
+    chunks: BTreeMap<u8, String>,
+    // Known once the null terminated chunk arrives
+    last_chunk: Option<u8>,
+}
+
+impl PendingText {
+    fn is_complete(&self) -> bool {
+        self.last_chunk
+            .is_some_and(|last| self.chunks.len() == last as usize + 1)
+    }
+
+    fn into_status(mut self) -> StatusText {
+        self.status.text = self.chunks.into_values().collect();
+        self.status
+    }
+}
+
+#[derive(Debug, Default)]
+struct StatusTextLog {
+    vehicles: HashMap<u8, VecDeque<StatusText>>,
+    // Texts split across chunks, indexed by system ID, component ID and text ID
+    pending: HashMap<(u8, u8, u16), PendingText>,
+}
+
+impl StatusTextLog {
+    fn push(&mut self, status: StatusText) {
+        websocket_manager::send_event("statustext", &status);
+
+        let log = self.vehicles.entry(status.system_id).or_default();
+        log.push_back(status);
+        if log.len() > LOG_SIZE {
+            log.pop_front();
+        }
+    }
+
+    // Texts that never got all of their chunks are logged with what was received
+    fn flush_expired(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| {
+                (now - pending.status.time).num_milliseconds() > CHUNK_TIMEOUT_MS
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            let pending = self.pending.remove(&key).unwrap();
+            self.push(pending.into_status());
+        }
+    }
+
+    fn update(
+        &mut self,
+        header: &mavlink::MavHeader,
+        statustext: &STATUSTEXT_DATA,
+        time: chrono::DateTime<chrono::Utc>,
+    ) {
+        self.flush_expired(time);
+
+        // The text is null terminated only when it's shorter than the field
+        let chunk = &statustext.text[..];
+        let is_last_chunk = chunk.contains(&0);
+        let length = chunk
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(chunk.len());
+        let chunk = String::from_utf8_lossy(&chunk[..length]);
+
+        let status = StatusText {
+            time,
+            system_id: header.system_id,
+            component_id: header.component_id,
+            severity: format!("{:?}", statustext.severity),
+            severity_value: statustext.severity as u8,
+            text: chunk.to_string(),
+        };
+
+        // Texts with ID 0 are not split
+        if statustext.id == 0 {
+            self.push(status);
+            return;
+        }
+
+        let key = (header.system_id, header.component_id, statustext.id);
+        let mut pending = match self.pending.remove(&key) {
+            Some(pending) if !pending.chunks.contains_key(&statustext.chunk_seq) => pending,
+            // A repeated chunk means a new text started, the previous one is logged as it is
+            previous => {
+                if let Some(previous) = previous {
+                    self.push(previous.into_status());
+                }
+                PendingText {
+                    status: StatusText {
+                        text: String::new(),
+                        ..status
+                    },
+                    chunks: BTreeMap::new(),
+                    last_chunk: None,
+                }
+            }
+        };
+        pending
+            .chunks
+            .insert(statustext.chunk_seq, chunk.to_string());
+        if is_last_chunk {
+            pending.last_chunk = Some(statustext.chunk_seq);
+        }
+
+        if pending.is_complete() {
+            self.push(pending.into_status());
+        } else {
+            self.pending.insert(key, pending);
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOG: Mutex<StatusTextLog> = Mutex::new(StatusTextLog::default());
+}
+
+/// Logs the texts that stopped receiving chunks, even when the vehicle sends no other STATUSTEXT
+pub fn run(interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        LOG.lock().unwrap().flush_expired(chrono::Utc::now());
+    });
+}
+
+pub fn update(message: &MAVLinkMessage<MavMessage>, reception: &Reception) {
+    if let MavMessage::STATUSTEXT(statustext) = &message.message {
+        LOG.lock()
+            .unwrap()
+            .update(&message.header, statustext, reception.utc);
+    }
+}
+
+/// Status texts of all vehicles, used by the snapshot
+pub fn history() -> Vec<StatusText> {
+    let log = LOG.lock().unwrap();
+    log.vehicles.values().flatten().cloned().collect()
+}
+
+/// Restores the texts of a snapshot, without sending them as events
+pub fn restore(texts: Vec<StatusText>) {
+    let mut log = LOG.lock().unwrap();
+    for status in texts {
+        let texts = log.vehicles.entry(status.system_id).or_default();
+        texts.push_back(status);
+        if texts.len() > LOG_SIZE {
+            texts.pop_front();
+        }
+    }
+}
+
+/// Status texts of a vehicle, filtered by the minimum severity and time range
+pub fn texts(
+    system_id: u8,
+    severity: Option<u8>,
+    start: Option<chrono::DateTime<chrono::Utc>>,
+    end: Option<chrono::DateTime<chrono::Utc>>,
+) -> Option<Vec<StatusText>> {
+    let mut log = LOG.lock().unwrap();
+    log.flush_expired(chrono::Utc::now());
+    let texts = log
+        .vehicles
+        .get(&system_id)?
+        .iter()
+        .filter(|status| severity.is_none_or(|severity| status.severity_value <= severity))
+        .filter(|status| start.is_none_or(|start| status.time >= start))
+        .filter(|status| end.is_none_or(|end| status.time <= end))
+        .cloned()
+        .collect();
+    Some(texts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mavlink::ardupilotmega::MavSeverity;
+
+    fn header() -> mavlink::MavHeader {
+        mavlink::MavHeader {
+            system_id: 1,
+            component_id: 1,
+            sequence: 0,
+        }
+    }
+
+    // Chunks are 50 bytes long, only the last one is null terminated
+    fn chunk(id: u16, chunk_seq: u8, text: &str, is_last: bool) -> STATUSTEXT_DATA {
+        let mut bytes = if is_last { [0; 50] } else { [b'.'; 50] };
+        bytes[..text.len()].copy_from_slice(text.as_bytes());
+        STATUSTEXT_DATA {
+            severity: MavSeverity::MAV_SEVERITY_WARNING,
+            text: bytes.into(),
+            id,
+            chunk_seq,
+        }
+    }
+
+    fn texts(log: &StatusTextLog) -> Vec<String> {
+        log.vehicles[&1]
+            .iter()
+            .map(|status| status.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn out_of_order_chunks() {
+        let mut log = StatusTextLog::default();
+        let time = chrono::Utc::now();
+        log.update(&header(), &chunk(7, 2, "end", true), time);
+        log.update(&header(), &chunk(7, 0, "start", false), time);
+        assert!(log.vehicles.is_empty());
+
+        log.update(&header(), &chunk(7, 1, "middle", false), time);
+        let expected = format!("start{}middle{}end", ".".repeat(45), ".".repeat(44));
+        assert_eq!(texts(&log), vec![expected]);
+        assert!(log.pending.is_empty());
+    }
+
+    #[test]
+    fn missing_chunk() {
+        let mut log = StatusTextLog::default();
+        let time = chrono::Utc::now();
+        log.update(&header(), &chunk(3, 0, "first", false), time);
+        log.update(&header(), &chunk(3, 2, "third", true), time);
+        assert!(log.vehicles.is_empty());
+
+        // The same text ID starts again, the incomplete text is logged as it is
+        log.update(&header(), &chunk(3, 0, "other", true), time);
+        let expected = format!("first{}third", ".".repeat(45));
+        assert_eq!(texts(&log), vec![expected, "other".to_string()]);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut log = StatusTextLog::default();
+        let time = chrono::Utc::now();
+        log.update(&header(), &chunk(5, 0, "lost", false), time);
+
+        log.flush_expired(time + chrono::Duration::milliseconds(CHUNK_TIMEOUT_MS));
+        assert!(log.vehicles.is_empty());
+
+        log.flush_expired(time + chrono::Duration::milliseconds(CHUNK_TIMEOUT_MS + 1));
+        assert_eq!(texts(&log), vec![format!("lost{}", ".".repeat(46))]);
+        assert!(log.pending.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavMessage, MavModeFlag};
+use serde::Serialize;
+
+use crate::data::{self, MAVLinkMessage};
+use crate::modes::Firmware;
+use crate::websocket_manager;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Mode {
+    /// Autopilot specific mode, from HEARTBEAT.custom_mode
+    custom_mode: u32,
+    /// Name of the mode, E.g: GUIDED, not available if the firmware is unknown
+    name: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Position {
+    /// Latitude in degrees
+    latitude: f64,
+    /// Longitude in degrees
+    longitude: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Altitude {
+    /// Altitude above mean sea level in meters
+    amsl: f64,
+    /// Altitude above home in meters
+    relative: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Attitude {
+    /// Roll angle in degrees
+    roll: f64,
+    /// Pitch angle in degrees
+    pitch: f64,
+    /// Yaw angle in degrees
+    yaw: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Speed {
+    /// Ground speed in m/s
+    ground: f64,
+    /// Air speed in m/s
+    air: f64,
+    /// Climb rate in m/s
+    climb: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Battery {
+    /// Voltage in volts
+    voltage: f64,
+    /// Current in amperes, not available if the autopilot does not measure it
+    current: Option<f64>,
+    /// Remaining capacity in percent, not available if the autopilot does not estimate it
+    remaining: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Gps {
+    /// GPS fix type, E.g: GPS_FIX_TYPE_3D_FIX
+    fix_type: String,
+    /// Number of visible satellites, not available if unknown
+    satellites: Option<u8>,
+}
+
+/// Normalised state of a vehicle, created from its latest messages
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VehicleSummary {
+    system_id: u8,
+    /// Component ID of the autopilot
+    component_id: u8,
+    /// MAV_TYPE of the vehicle, E.g: MAV_TYPE_QUADROTOR
+    vehicle_type: Option<String>,
+    /// MAV_AUTOPILOT of the vehicle, E.g: MAV_AUTOPILOT_ARDUPILOTMEGA
+    autopilot: Option<String>,
+    /// MAV_STATE of the vehicle, E.g: MAV_STATE_ACTIVE
+    system_status: Option<String>,
+    armed: Option<bool>,
+    mode: Option<Mode>,
+    position: Option<Position>,
+    altitude: Option<Altitude>,
+    /// Heading in degrees
+    heading: Option<f64>,
+    attitude: Option<Attitude>,
+    speed: Option<Speed>,
+    battery: Option<Battery>,
+    gps: Option<Gps>,
+    /// Time of the last message received from the vehicle
+    last_seen: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Creates the summary of a vehicle, it's None if no message was received from it
+pub fn summary(system_id: u8) -> Option<VehicleSummary> {
+    let messages = data::vehicle_messages(system_id);
+    if messages.is_empty() {
+        return None;
+    }
+
+    let autopilot = data::autopilot_heartbeat(system_id);
+    let component_id = autopilot
+        .as_ref()
+        .map_or(1, |autopilot| autopilot.header.component_id);
+    let heartbeat = match autopilot.as_ref().map(|autopilot| &autopilot.message) {
+        Some(MavMessage::HEARTBEAT(heartbeat)) => Some(heartbeat),
+        _ => None,
+    };
+
+    // Messages from the autopilot are preferred over the ones from other components
+    let find = |name: &str| -> Option<&MavMessage> {
+        let mut candidates: Vec<&MAVLinkMessage<MavMessage>> = messages
+            .iter()
+            .map(|(message, _)| message)
+            .filter(|message| mavlink::Message::message_name(&message.message) == name)
+            .collect();
+        candidates.sort_by_key(|message| message.header.component_id != component_id);
+        candidates.first().map(|message| &message.message)
+    };
+
+    let mut summary = VehicleSummary {
+        system_id,
+        component_id,
+        vehicle_type: heartbeat.map(|heartbeat| format!("{:?}", heartbeat.mavtype)),
+        autopilot: heartbeat.map(|heartbeat| format!("{:?}", heartbeat.autopilot)),
+        system_status: heartbeat.map(|heartbeat| format!("{:?}", heartbeat.system_status)),
+        armed: heartbeat.map(|heartbeat| {
+            heartbeat
+                .base_mode
+                .contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED)
+        }),
+        mode: heartbeat.map(|heartbeat| Mode {
+            custom_mode: heartbeat.custom_mode,
+            name: Firmware::from_heartbeat(heartbeat)
+                .and_then(|firmware| firmware.mode_name(heartbeat.custom_mode)),
+        }),
+        position: None,
+        altitude: None,
+        heading: None,
+        attitude: None,
+        speed: None,
+        battery: None,
+        gps: None,
+        last_seen: messages.iter().map(|(_, reception)| reception.utc).max(),
+    };
+
+    if let Some(MavMessage::GLOBAL_POSITION_INT(position)) = find("GLOBAL_POSITION_INT") {
+        summary.position = Some(Position {
+            latitude: position.lat as f64 / 1e7,
+            longitude: position.lon as f64 / 1e7,
+        });
+        summary.altitude = Some(Altitude {
+            amsl: position.alt as f64 / 1e3,
+            relative: position.relative_alt as f64 / 1e3,
+        });
+        if position.hdg != u16::MAX {
+            summary.heading = Some(position.hdg as f64 / 1e2);
+        }
+    }
+
+    if let Some(MavMessage::ATTITUDE(attitude)) = find("ATTITUDE") {
+        summary.attitude = Some(Attitude {
+            roll: (attitude.roll as f64).to_degrees(),
+            pitch: (attitude.pitch as f64).to_degrees(),
+            yaw: (attitude.yaw as f64).to_degrees(),
+        });
+    }
+
+    if let Some(MavMessage::VFR_HUD(hud)) = find("VFR_HUD") {
+        summary.speed = Some(Speed {
+            ground: hud.groundspeed as f64,
+            air: hud.airspeed as f64,
+            climb: hud.climb as f64,
+        });
+        summary.heading = summary.heading.or(Some(hud.heading as f64));
+    }
+
+    // BATTERY_STATUS has the individual cells, SYS_STATUS only the total voltage
+    summary.battery = match (find("BATTERY_STATUS"), find("SYS_STATUS")) {
+        (Some(MavMessage::BATTERY_STATUS(battery)), _) => Some(Battery {
+            voltage: battery
+                .voltages
+                .iter()
+                .filter(|voltage| **voltage != u16::MAX)
+                .map(|voltage| *voltage as f64 / 1e3)
+                .sum(),
+            current: (battery.current_battery != -1)
+                .then_some(battery.current_battery as f64 / 1e2),
+            remaining: (battery.battery_remaining >= 0).then_some(battery.battery_remaining as u8),
+        }),
+        (_, Some(MavMessage::SYS_STATUS(status))) if status.voltage_battery != u16::MAX => {
+            Some(Battery {
+                voltage: status.voltage_battery as f64 / 1e3,
+                current: (status.current_battery != -1)
+                    .then_some(status.current_battery as f64 / 1e2),
+                remaining: (status.battery_remaining >= 0)
+                    .then_some(status.battery_remaining as u8),
+            })
+        }
+        _ => None,
+    };
+
+    if let Some(MavMessage::GPS_RAW_INT(gps)) = find("GPS_RAW_INT") {
+        summary.gps = Some(Gps {
+            fix_type: format!("{:?}", gps.fix_type),
+            satellites: (gps.satellites_visible != u8::MAX).then_some(gps.satellites_visible),
+        });
+    }
+
+    Some(summary)
+}
+
+/// Pushes the summaries of the vehicles to the events websocket when they change
+pub fn run(interval: Duration) {
+    std::thread::spawn(move || {
+        let mut last_summaries: HashMap<u8, VehicleSummary> = HashMap::new();
+        let mut last_clients = 0;
+        loop {
+            std::thread::sleep(interval);
+
+            // New clients should receive all summaries, even when others are connected
+            let clients = websocket_manager::event_clients();
+            if clients > last_clients {
+                last_summaries.clear();
+            }
+            last_clients = clients;
+            if clients == 0 {
+                continue;
+            }
+
+            for system_id in data::vehicles() {
+                let summary = match summary(system_id) {
+                    Some(summary) => summary,
+                    None => continue,
+                };
+
+                // The reception time alone is not a change
+                let changed = last_summaries.get(&system_id).is_none_or(|last| {
+                    VehicleSummary {
+                        last_seen: summary.last_seen,
+                        ..last.clone()
+                    } != summary
+                });
+
+                if changed {
+                    websocket_manager::send_event("vehicle_summary", &summary);
+                    last_summaries.insert(system_id, summary);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write as _;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use lazy_static::lazy_static;
+use log::*;
+use mavlink::Message;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cli;
+use crate::data::{MAVLinkMessage, Reception};
+
+// Lines waiting to be sent to each stream client, newer lines are dropped for slow clients
+const STREAM_BUFFER: usize = 1000;
+
+/// Numeric fields of a received message
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sample {
+    utc: chrono::DateTime<chrono::Utc>,
+    system_id: u8,
+    component_id: u8,
+    name: String,
+    fields: Vec<(String, Value)>,
+}
+
+// Nested fields are named with their path, E.g: voltages_0, base_mode_bits
+fn flatten(prefix: &str, value: &Value, fields: &mut Vec<(String, Value)>) {
+    let name = |key: &str| match prefix {
+        "" => key.to_string(),
+        _ => format!("{prefix}_{key}"),
+    };
+
+    match value {
+        Value::Number(_) | Value::Bool(_) => fields.push((prefix.to_string(), value.clone())),
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten(&name(&index.to_string()), value, fields);
+            }
+        }
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten(&name(key), value, fields);
+            }
+        }
+        // Strings are message types, enums and texts, only numeric fields are exported
+        _ => {}
+    }
+}
+
+impl Sample {
+    fn new(
+        message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>,
+        reception: &Reception,
+    ) -> Self {
+        let mut fields = vec![];
+        flatten(
+            "",
+            &serde_json::to_value(&message.message).unwrap(),
+            &mut fields,
+        );
+
+        Self {
+            utc: reception.utc,
+            system_id: message.header.system_id,
+            component_id: message.header.component_id,
+            name: message.message.message_name().to_string(),
+            fields,
+        }
+    }
+
+    /// InfluxDB line protocol, https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+    fn line(&self) -> Option<String> {
+        if self.fields.is_empty() {
+            return None;
+        }
+
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(name, value)| match value {
+                Value::Number(number) if number.is_i64() => format!("{name}={number}i"),
+                _ => format!("{name}={value}"),
+            })
+            .collect();
+
+        Some(format!(
+            "{},system_id={},component_id={} {} {}\n",
+            self.name,
+            self.system_id,
+            self.component_id,
+            fields.join(","),
+            self.utc.timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimeSeries {
+    history: VecDeque<Sample>,
+    streams: Vec<(Regex, Sender<String>)>,
+    pusher: Option<mpsc::Sender<String>>,
+}
+
+lazy_static! {
+    static ref TIMESERIES: Mutex<TimeSeries> = Mutex::new(TimeSeries::default());
+    // Both values are validated when the command line is parsed
+    static ref HISTORY_FILTER: Option<Regex> =
+        cli::timeseries_filter().and_then(|filter| Regex::new(filter).ok());
+    static ref RETENTION: chrono::Duration =
+        chrono::Duration::from_std(cli::timeseries_retention()).unwrap_or(chrono::Duration::MAX);
+}
+
+pub fn update(message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>, reception: &Reception) {
+    let name = message.message.message_name();
+    let recorded = HISTORY_FILTER
+        .as_ref()
+        .is_some_and(|filter| filter.is_match(name));
+
+    let mut timeseries = TIMESERIES.lock().unwrap();
+    let streamed = timeseries
+        .streams
+        .iter()
+        .any(|(filter, _)| filter.is_match(name));
+
+    // Avoid the conversion of messages that nobody is interested in
+    if !recorded && !streamed {
+        return;
+    }
+
+    let sample = Sample::new(message, reception);
+    let line = match sample.line() {
+        Some(line) => line,
+        None => return,
+    };
+
+    timeseries.streams.retain_mut(|(filter, stream)| {
+        if !filter.is_match(name) {
+            return true;
+        }
+        match stream.try_send(line.clone()) {
+            Ok(()) => true,
+            Err(error) => error.is_full(),
+        }
+    });
+
+    if !recorded {
+        return;
+    }
+
+    if let Some(pusher) = &timeseries.pusher {
+        if pusher.send(line).is_err() {
+            timeseries.pusher = None;
+        }
+    }
+
+    timeseries.history.push_back(sample);
+    while let Some(oldest) = timeseries.history.front() {
+        if reception.utc - oldest.utc <= *RETENTION {
+            break;
+        }
+        timeseries.history.pop_front();
+    }
+}
+
+/// Recorded messages, used by the snapshot
+pub fn history() -> Vec<Sample> {
+    TIMESERIES.lock().unwrap().history.iter().cloned().collect()
+}
+
+/// Restores the recorded messages of a snapshot, older ones are removed with the retention
+pub fn restore(samples: Vec<Sample>) {
+    TIMESERIES.lock().unwrap().history.extend(samples);
+}
+
+/// Streams the InfluxDB lines of the messages that match the filter
+pub fn stream(filter: Regex) -> Receiver<String> {
+    let (sender, receiver) = channel(STREAM_BUFFER);
+    TIMESERIES.lock().unwrap().streams.push((filter, sender));
+    receiver
+}
+
+/// Creates a CSV with the recorded messages, each numeric field is a column
+pub fn csv(
+    filter: &Regex,
+    start: Option<chrono::DateTime<chrono::Utc>>,
+    end: Option<chrono::DateTime<chrono::Utc>>,
+    system_id: Option<u8>,
+    component_id: Option<u8>,
+) -> String {
+    let timeseries = TIMESERIES.lock().unwrap();
+    let samples: Vec<&Sample> = timeseries
+        .history
+        .iter()
+        .filter(|sample| filter.is_match(&sample.name))
+        .filter(|sample| start.is_none_or(|start| sample.utc >= start))
+        .filter(|sample| end.is_none_or(|end| sample.utc <= end))
+        .filter(|sample| system_id.is_none_or(|id| id == sample.system_id))
+        .filter(|sample| component_id.is_none_or(|id| id == sample.component_id))
+        .collect();
+
+    // Columns of all messages, in the order that they appear
+    let mut known_columns = HashSet::new();
+    let mut columns: Vec<&str> = vec![];
+    for sample in &samples {
+        for (name, _) in &sample.fields {
+            if known_columns.insert(name.as_str()) {
+                columns.push(name);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let header: Vec<&str> = ["time", "system_id", "component_id", "message"]
+        .iter()
+        .copied()
+        .chain(columns.iter().copied())
+        .collect();
+    writeln!(output, "{}", header.join(",")).unwrap();
+
+    for sample in samples {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                sample
+                    .fields
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut row = vec![
+            sample.utc.to_rfc3339(),
+            sample.system_id.to_string(),
+            sample.component_id.to_string(),
+            sample.name.clone(),
+        ];
+        row.extend(values);
+        writeln!(output, "{}", row.join(",")).unwrap();
+    }
+
+    output
+}
+
+fn post(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    token: Option<&str>,
+    body: String,
+) -> reqwest::Result<()> {
+    let mut request = client
+        .post(url)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(body);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Token {token}"));
+    }
+    request.send()?.error_for_status()?;
+    Ok(())
+}
+
+/// Pushes the recorded messages to an HTTP write endpoint, in batches of one second
+pub fn run_pusher(url: String, token: Option<String>) {
+    let (sender, receiver) = mpsc::channel::<String>();
+    TIMESERIES.lock().unwrap().pusher = Some(sender);
+
+    std::thread::spawn(move || {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("Failed to create the time series HTTP client.");
+        loop {
+            let mut body = match receiver.recv() {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+
+            std::thread::sleep(Duration::from_secs(1));
+            body.extend(receiver.try_iter());
+
+            if let Err(error) = post(&client, &url, token.as_deref(), body) {
+                warn!("Failed to push time series to {url}: {error}");
+            }
+        }
+    });
+}
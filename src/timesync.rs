@@ -0,0 +1,359 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use log::*;
+use mavlink::ardupilotmega::{MavMessage, TIMESYNC_DATA};
+use serde::Serialize;
+
+use crate::data::{self, MAVLinkMessage, Reception};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+
+// Number of samples used to estimate the clock of each component
+const SAMPLES_SIZE: usize = 60;
+// Number of the last requests whose answers are accepted
+const PENDING_SIZE: usize = 10;
+// Answers slower than this are not useful to estimate the offset
+const MAX_RTT_NS: i64 = 2_000_000_000;
+// Offset change that means that the vehicle clock was reset, E.g: after a reboot
+const RESET_THRESHOLD_NS: i64 = 1_000_000_000;
+// Timestamps above this are UNIX time instead of time since boot, in microseconds
+const UNIX_TIME_US: u64 = 1_000_000_000_000_000;
+
+/// Offset between the vehicle and service clocks measured with a TIMESYNC exchange
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    /// Service monotonic time when the vehicle answered, in the middle of the round trip
+    host_ns: i64,
+    offset_ns: i64,
+    rtt_ns: i64,
+}
+
+/// Clock estimation of a vehicle component
+#[derive(Clone, Debug, Serialize)]
+pub struct Clock {
+    pub system_id: u8,
+    pub component_id: u8,
+    /// Vehicle time minus service time in nanoseconds, the vehicle time is usually the time since boot
+    pub offset_ns: i64,
+    /// UTC time when the vehicle clock was zero, E.g: the boot time
+    pub boot_time_utc: DateTime<Utc>,
+    /// Drift of the vehicle clock in parts per million, positive if it runs faster
+    pub drift_ppm: f64,
+    /// Round-trip time of the last TIMESYNC exchange in milliseconds
+    pub rtt_ms: f64,
+    pub rtt_min_ms: f64,
+    pub rtt_mean_ms: f64,
+    /// One-way latency estimated as half of the mean round-trip time
+    pub latency_ms: f64,
+    pub samples: usize,
+    pub last_update: DateTime<Utc>,
+}
+
+// Linear fit of the vehicle clock, offset_ns = intercept + slope * host_ns
+#[derive(Clone, Copy, Debug)]
+struct Estimate {
+    intercept: f64,
+    slope: f64,
+}
+
+impl Estimate {
+    fn offset_ns(&self, host_ns: i64) -> i64 {
+        (self.intercept + self.slope * host_ns as f64) as i64
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClockState {
+    samples: VecDeque<Sample>,
+    estimate: Option<Estimate>,
+    last_update: Option<DateTime<Utc>>,
+}
+
+impl ClockState {
+    fn add(&mut self, sample: Sample, time: DateTime<Utc>) {
+        if let Some(estimate) = self.estimate {
+            if (estimate.offset_ns(sample.host_ns) - sample.offset_ns).abs() > RESET_THRESHOLD_NS {
+                self.samples.clear();
+            }
+        }
+        self.samples.push_back(sample);
+        if self.samples.len() > SAMPLES_SIZE {
+            self.samples.pop_front();
+        }
+        self.estimate = Some(self.fit());
+        self.last_update = Some(time);
+    }
+
+    // Least squares over the samples with the fastest answers, slow ones have larger errors
+    fn fit(&self) -> Estimate {
+        let rtt_min = self
+            .samples
+            .iter()
+            .map(|sample| sample.rtt_ns)
+            .min()
+            .unwrap_or(0);
+        let samples: Vec<&Sample> = self
+            .samples
+            .iter()
+            .filter(|sample| sample.rtt_ns <= 2 * rtt_min + 1_000_000)
+            .collect();
+
+        // Relative to the first sample to keep the precision
+        let (x0, y0) = (samples[0].host_ns, samples[0].offset_ns);
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|sample| ((sample.host_ns - x0) as f64, (sample.offset_ns - y0) as f64))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let covariance: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+
+        // A single sample, or samples too close in time, do not have a drift
+        let slope = if variance > 1e18 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        Estimate {
+            intercept: y0 as f64 + mean_y - slope * (x0 as f64 + mean_x),
+            slope,
+        }
+    }
+}
+
+lazy_static! {
+    static ref CLOCKS: Mutex<HashMap<(u8, u8), ClockState>> = Mutex::new(HashMap::new());
+    // Timestamps of the last requests, answers to other requests are ignored
+    static ref PENDING: Mutex<VecDeque<i64>> = Mutex::new(VecDeque::new());
+}
+
+/// Uses the TIMESYNC answers to the requests of the service to estimate the vehicle clocks
+pub fn update(message: &MAVLinkMessage<MavMessage>, reception: &Reception) {
+    let timesync = match &message.message {
+        // Requests of the vehicle have tc1 = 0
+        MavMessage::TIMESYNC(timesync) if timesync.tc1 != 0 => timesync,
+        _ => return,
+    };
+    if !PENDING.lock().unwrap().contains(&timesync.ts1) {
+        return;
+    }
+
+    let now = reception.monotonic_ns as i64;
+    let rtt_ns = now - timesync.ts1;
+    if !(0..=MAX_RTT_NS).contains(&rtt_ns) {
+        return;
+    }
+    let host_ns = timesync.ts1 + rtt_ns / 2;
+    let sample = Sample {
+        host_ns,
+        offset_ns: timesync.tc1 - host_ns,
+        rtt_ns,
+    };
+
+    CLOCKS
+        .lock()
+        .unwrap()
+        .entry((message.header.system_id, message.header.component_id))
+        .or_default()
+        .add(sample, reception.utc);
+}
+
+/// Clock estimations of all components that answered TIMESYNC
+pub fn clocks() -> Vec<Clock> {
+    let now_ns = data::monotonic_ns() as i64;
+    let now = Utc::now();
+    let mut clocks: Vec<Clock> = CLOCKS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(&(system_id, component_id), state)| {
+            let estimate = state.estimate?;
+            let last = state.samples.back()?;
+            let rtts = state
+                .samples
+                .iter()
+                .map(|sample| sample.rtt_ns as f64 / 1e6);
+            let rtt_mean_ms = rtts.clone().sum::<f64>() / state.samples.len() as f64;
+            let offset_ns = estimate.offset_ns(now_ns);
+            Some(Clock {
+                system_id,
+                component_id,
+                offset_ns,
+                boot_time_utc: now - chrono::Duration::nanoseconds(now_ns + offset_ns),
+                drift_ppm: estimate.slope * 1e6,
+                rtt_ms: last.rtt_ns as f64 / 1e6,
+                rtt_min_ms: rtts.fold(f64::INFINITY, f64::min),
+                rtt_mean_ms,
+                latency_ms: rtt_mean_ms / 2.0,
+                samples: state.samples.len(),
+                last_update: state.last_update?,
+            })
+        })
+        .collect();
+    clocks.sort_by_key(|clock| (clock.system_id, clock.component_id));
+    clocks
+}
+
+pub fn clock(system_id: u8, component_id: u8) -> Option<Clock> {
+    clocks()
+        .into_iter()
+        .find(|clock| clock.system_id == system_id && clock.component_id == component_id)
+}
+
+/// UTC time of a vehicle timestamp in nanoseconds, using the reception to convert the service clock
+pub fn vehicle_time_utc(
+    system_id: u8,
+    component_id: u8,
+    vehicle_ns: i64,
+    reception: &Reception,
+) -> Option<DateTime<Utc>> {
+    let clocks = CLOCKS.lock().unwrap();
+    let estimate = clocks.get(&(system_id, component_id))?.estimate?;
+    let reception_ns = reception.monotonic_ns as i64;
+    let host_ns = vehicle_ns - estimate.offset_ns(reception_ns);
+    Some(reception.utc - chrono::Duration::nanoseconds(reception_ns - host_ns))
+}
+
+enum Timestamp {
+    BootMs(u32),
+    Us(u64),
+}
+
+// Timestamp of the common messages that carry the vehicle time
+fn message_time(message: &MavMessage) -> Option<Timestamp> {
+    use Timestamp::{BootMs, Us};
+    Some(match message {
+        MavMessage::ATTITUDE(data) => BootMs(data.time_boot_ms),
+        MavMessage::ATTITUDE_QUATERNION(data) => BootMs(data.time_boot_ms),
+        MavMessage::ATTITUDE_TARGET(data) => BootMs(data.time_boot_ms),
+        MavMessage::GLOBAL_POSITION_INT(data) => BootMs(data.time_boot_ms),
+        MavMessage::LOCAL_POSITION_NED(data) => BootMs(data.time_boot_ms),
+        MavMessage::POSITION_TARGET_GLOBAL_INT(data) => BootMs(data.time_boot_ms),
+        MavMessage::POSITION_TARGET_LOCAL_NED(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_IMU(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_IMU2(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_IMU3(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_PRESSURE(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_PRESSURE2(data) => BootMs(data.time_boot_ms),
+        MavMessage::SCALED_PRESSURE3(data) => BootMs(data.time_boot_ms),
+        MavMessage::RC_CHANNELS(data) => BootMs(data.time_boot_ms),
+        MavMessage::RC_CHANNELS_RAW(data) => BootMs(data.time_boot_ms),
+        MavMessage::NAMED_VALUE_FLOAT(data) => BootMs(data.time_boot_ms),
+        MavMessage::NAMED_VALUE_INT(data) => BootMs(data.time_boot_ms),
+        MavMessage::SYSTEM_TIME(data) => BootMs(data.time_boot_ms),
+        MavMessage::CAMERA_IMAGE_CAPTURED(data) => BootMs(data.time_boot_ms),
+        MavMessage::GIMBAL_DEVICE_ATTITUDE_STATUS(data) => BootMs(data.time_boot_ms),
+        MavMessage::RAW_IMU(data) => Us(data.time_usec),
+        MavMessage::HIGHRES_IMU(data) => Us(data.time_usec),
+        MavMessage::GPS_RAW_INT(data) => Us(data.time_usec),
+        MavMessage::GPS2_RAW(data) => Us(data.time_usec),
+        MavMessage::VIBRATION(data) => Us(data.time_usec),
+        MavMessage::SERVO_OUTPUT_RAW(data) => Us(data.time_usec as u64),
+        _ => return None,
+    })
+}
+
+/// UTC time of the vehicle timestamp of a message, E.g: time_boot_ms of ATTITUDE.
+/// Timestamps in UNIX time are converted directly.
+pub fn message_time_utc(
+    message: &MAVLinkMessage<MavMessage>,
+    reception: &Reception,
+) -> Option<DateTime<Utc>> {
+    let vehicle_ns = match message_time(&message.message)? {
+        Timestamp::BootMs(time) => time as i64 * 1_000_000,
+        Timestamp::Us(0) => return None,
+        Timestamp::Us(time) if time > UNIX_TIME_US => {
+            return Utc
+                .timestamp_opt((time / 1_000_000) as i64, (time % 1_000_000) as u32 * 1000)
+                .single()
+        }
+        Timestamp::Us(time) => time as i64 * 1000,
+    };
+    vehicle_time_utc(
+        message.header.system_id,
+        message.header.component_id,
+        vehicle_ns,
+        reception,
+    )
+}
+
+/// Sends a TIMESYNC request to all vehicles every interval
+pub fn run(vehicle: &MAVLinkVehicleArcMutex, interval: Duration) {
+    let vehicle = vehicle.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+
+        let ts = data::monotonic_ns() as i64;
+        {
+            let mut pending = PENDING.lock().unwrap();
+            pending.push_back(ts);
+            if pending.len() > PENDING_SIZE {
+                pending.pop_front();
+            }
+        }
+
+        let message = MavMessage::TIMESYNC(TIMESYNC_DATA {
+            tc1: 0,
+            ts1: ts,
+            ..Default::default()
+        });
+        if let Err(error) = vehicle.lock().unwrap().send_default(&message) {
+            warn!("Failed to send TIMESYNC: {error}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_estimate() {
+        let mut state = ClockState::default();
+        // Vehicle clock 10 ppm faster and 5 s ahead, with slow answers that should be ignored
+        for second in 0..30i64 {
+            let host_ns = second * 1_000_000_000;
+            let rtt_ns = if second % 5 == 0 {
+                50_000_000
+            } else {
+                2_000_000
+            };
+            let offset_ns = 5_000_000_000 + host_ns / 100_000 + rtt_ns / 4;
+            state.add(
+                Sample {
+                    host_ns,
+                    offset_ns,
+                    rtt_ns,
+                },
+                Utc::now(),
+            );
+        }
+
+        let estimate = state.estimate.unwrap();
+        assert!((estimate.slope * 1e6 - 10.0).abs() < 0.1);
+        assert!((estimate.offset_ns(0) - 5_000_500_000).abs() < 10_000);
+
+        // Reboot of the vehicle
+        state.add(
+            Sample {
+                host_ns: 31_000_000_000,
+                offset_ns: -31_000_000_000,
+                rtt_ns: 2_000_000,
+            },
+            Utc::now(),
+        );
+        assert_eq!(state.samples.len(), 1);
+        assert_eq!(
+            state.estimate.unwrap().offset_ns(31_000_000_000),
+            -31_000_000_000
+        );
+    }
+}
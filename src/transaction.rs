@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::Message;
+use paperclip::actix::Apiv2Schema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cli;
+use crate::data::{self, MAVLinkMessage};
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+
+/// Number of times that a request is sent before giving up
+pub const RETRIES: usize = 3;
+/// Same as [RETRIES] for transfers of many messages, which should survive short link losses
+pub const TRANSFER_RETRIES: usize = 5;
+/// Time to wait for an answer before sending the request again
+pub const RETRY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Protocols that keep a state in the component
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Protocol {
+    Ftp,
+    Logs,
+    Mission,
+}
+
+type Locks = HashMap<(Protocol, u8, u8), Arc<Mutex<()>>>;
+
+lazy_static! {
+    static ref LOCKS: Mutex<Locks> = Mutex::new(HashMap::new());
+}
+
+/// Lock that allows a single operation of the protocol per component at a time
+pub fn component_lock(protocol: Protocol, system_id: u8, component_id: u8) -> Arc<Mutex<()>> {
+    LOCKS
+        .lock()
+        .unwrap()
+        .entry((protocol, system_id, component_id))
+        .or_default()
+        .clone()
+}
+
+/// Message that answers a transaction
+#[derive(Apiv2Schema, Clone, Debug, Deserialize)]
+pub struct Matcher {
+    /// Name of the message, E.g: AUTOPILOT_VERSION
+    pub name: String,
+    pub system_id: Option<u8>,
+    pub component_id: Option<u8>,
+    /// Fields that should be equal, nested fields use dots, E.g: {"command": "MAV_CMD_REQUEST_MESSAGE"}
+    pub fields: Option<Value>,
+}
+
+#[derive(Debug)]
+pub enum TransactionError {
+    InvalidMatcher(String),
+    Send(std::io::Error),
+    Timeout,
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidMatcher(error) => write!(f, "Invalid response matcher: {error}"),
+            Self::Send(error) => write!(f, "Failed to send message: {error}"),
+            Self::Timeout => write!(f, "Timeout while waiting for the response."),
+        }
+    }
+}
+
+// Enums are compared by name and bitflags by bits, numbers ignore their type
+fn same(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Number(actual), Value::Number(expected)) => actual.as_f64() == expected.as_f64(),
+        (Value::Object(object), Value::String(_)) => object.get("type") == Some(expected),
+        (Value::Object(object), Value::Number(_)) => {
+            object.get("bits").is_some_and(|bits| same(bits, expected))
+        }
+        _ => actual == expected,
+    }
+}
+
+impl Matcher {
+    fn validate(&self) -> Result<(), TransactionError> {
+        if MavMessage::message_id_from_name(&self.name).is_none() {
+            return Err(TransactionError::InvalidMatcher(format!(
+                "Unknown message {}",
+                self.name
+            )));
+        }
+        match &self.fields {
+            None | Some(Value::Object(_)) => Ok(()),
+            Some(_) => Err(TransactionError::InvalidMatcher(
+                "fields should be an object".to_string(),
+            )),
+        }
+    }
+
+    pub fn matches(&self, message: &MAVLinkMessage<MavMessage>) -> bool {
+        if message.message.message_name() != self.name
+            || self
+                .system_id
+                .is_some_and(|id| id != message.header.system_id)
+            || self
+                .component_id
+                .is_some_and(|id| id != message.header.component_id)
+        {
+            return false;
+        }
+
+        let fields = match &self.fields {
+            Some(Value::Object(fields)) if !fields.is_empty() => fields,
+            _ => return true,
+        };
+        let content = match serde_json::to_value(&message.message) {
+            Ok(content) => content,
+            Err(_) => return false,
+        };
+        fields.iter().all(|(path, expected)| {
+            let pointer = format!("/{}", path.replace('.', "/"));
+            content
+                .pointer(&pointer)
+                .is_some_and(|actual| same(actual, expected))
+        })
+    }
+}
+
+// Messages sent by the service are skipped, E.g: the PING of a PING transaction
+fn is_answer(matcher: &Matcher, answer: &MAVLinkMessage<MavMessage>) -> bool {
+    let (own_system_id, own_component_id) = cli::mavlink_system_and_component_id();
+    (answer.header.system_id != own_system_id || answer.header.component_id != own_component_id)
+        && matcher.matches(answer)
+}
+
+fn send(vehicle: &MAVLinkVehicleArcMutex, message: &MavMessage) -> Result<(), TransactionError> {
+    vehicle
+        .lock()
+        .unwrap()
+        .send_default(message)
+        .map(|_| ())
+        .map_err(TransactionError::Send)
+}
+
+/// Sends the message until a received message is accepted by the matcher,
+/// the timeout is split between the attempts
+pub fn exchange(
+    vehicle: &MAVLinkVehicleArcMutex,
+    message: &MavMessage,
+    matcher: &Matcher,
+    attempts: u8,
+    timeout: Duration,
+) -> Result<MAVLinkMessage<MavMessage>, TransactionError> {
+    matcher.validate()?;
+    let attempts = attempts.max(1);
+
+    let receiver = data::subscribe();
+    for _ in 0..attempts {
+        send(vehicle, message)?;
+
+        let deadline = Instant::now() + timeout / attempts as u32;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let answer = match receiver.recv_timeout(remaining) {
+                Ok(answer) => answer,
+                Err(_) => break,
+            };
+            if is_answer(matcher, &answer) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    Err(TransactionError::Timeout)
+}
+
+/// Same as [exchange] for async callers, it runs in its own thread to not hold one of
+/// the blocking pool during long timeouts
+pub async fn exchange_async(
+    vehicle: &MAVLinkVehicleArcMutex,
+    message: &MavMessage,
+    matcher: &Matcher,
+    attempts: u8,
+    timeout: Duration,
+) -> Result<MAVLinkMessage<MavMessage>, TransactionError> {
+    let (sender, receiver) = oneshot::channel();
+    let (vehicle, message, matcher) = (vehicle.clone(), message.clone(), matcher.clone());
+    std::thread::spawn(move || {
+        let _ = sender.send(exchange(&vehicle, &message, &matcher, attempts, timeout));
+    });
+    receiver.await.unwrap_or(Err(TransactionError::Timeout))
+}
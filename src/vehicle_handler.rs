@@ -50,7 +50,6 @@ impl Vehicle {
         let inner = inner.lock().unwrap();
         InnerVehicle::heartbeat_loop(&inner);
         InnerVehicle::parser_loop(&inner);
-        let _ = inner.channel.send_default(&InnerVehicle::request_stream());
     }
 }
 
@@ -130,18 +129,4 @@ impl InnerVehicle {
             })
         })
     }
-
-    pub fn request_stream() -> mavlink::ardupilotmega::MavMessage {
-        mavlink::ardupilotmega::MavMessage::common({
-            mavlink::common::MavMessage::REQUEST_DATA_STREAM(
-                mavlink::common::REQUEST_DATA_STREAM_DATA {
-                    target_system: 0,
-                    target_component: 0,
-                    req_stream_id: 0,
-                    req_message_rate: 10,
-                    start_stop: 1,
-                },
-            )
-        })
-    }
 }
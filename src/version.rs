@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::*;
+use mavlink::ardupilotmega::{
+    MavCmd, MavMessage, MavProtocolCapability, AUTOPILOT_VERSION_DATA, COMMAND_LONG_DATA,
+};
+use mavlink::Message;
+use serde::Serialize;
+
+use crate::data;
+use crate::inventory;
+use crate::mavlink_vehicle::MAVLinkVehicleArcMutex;
+use crate::transaction::{self, Matcher, TransactionError};
+
+// Time to wait for AUTOPILOT_VERSION with each request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+// Time between the requests to a component that did not answer
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Software version packed as major, minor, patch and FIRMWARE_VERSION_TYPE bytes
+#[derive(Clone, Debug, Serialize)]
+pub struct SoftwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    /// FIRMWARE_VERSION_TYPE without prefix, E.g: OFFICIAL or BETA
+    pub release: String,
+    /// Version as text, E.g: 4.5.1-official
+    pub text: String,
+}
+
+/// Decoded AUTOPILOT_VERSION of a component
+#[derive(Clone, Debug, Serialize)]
+pub struct Version {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub flight_sw_version: Option<SoftwareVersion>,
+    pub middleware_sw_version: Option<SoftwareVersion>,
+    pub os_sw_version: Option<SoftwareVersion>,
+    pub board_version: u32,
+    /// First characters of the git hash of each software, E.g: 9b5cd7ed
+    pub flight_git_hash: Option<String>,
+    pub middleware_git_hash: Option<String>,
+    pub os_git_hash: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Hardware UID as hexadecimal
+    pub uid: String,
+    /// MAV_PROTOCOL_CAPABILITY names, E.g: MAV_PROTOCOL_CAPABILITY_FTP
+    pub capabilities: Vec<String>,
+    pub capabilities_bits: u64,
+}
+
+fn software_version(packed: u32) -> Option<SoftwareVersion> {
+    if packed == 0 {
+        return None;
+    }
+    let [major, minor, patch, release] = packed.to_be_bytes();
+    let release = match release {
+        0..=63 => "DEV",
+        64..=127 => "ALPHA",
+        128..=191 => "BETA",
+        192..=254 => "RC",
+        255 => "OFFICIAL",
+    };
+    Some(SoftwareVersion {
+        major,
+        minor,
+        patch,
+        release: release.to_string(),
+        text: format!("{major}.{minor}.{patch}-{}", release.to_lowercase()),
+    })
+}
+
+// ArduPilot sends the hash as text, PX4 as the bytes of a little endian number
+fn git_hash(bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    let text: Vec<u8> = bytes
+        .iter()
+        .copied()
+        .take_while(|byte| *byte != 0)
+        .collect();
+    if !text.is_empty() && text.iter().all(u8::is_ascii_hexdigit) {
+        return Some(String::from_utf8_lossy(&text).to_string());
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    )
+}
+
+fn capability_names(capabilities: MavProtocolCapability) -> Vec<String> {
+    capabilities
+        .iter_names()
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+fn decode(system_id: u8, component_id: u8, version: &AUTOPILOT_VERSION_DATA) -> Version {
+    let capabilities_bits = version.capabilities.bits();
+    Version {
+        system_id,
+        component_id,
+        flight_sw_version: software_version(version.flight_sw_version),
+        middleware_sw_version: software_version(version.middleware_sw_version),
+        os_sw_version: software_version(version.os_sw_version),
+        board_version: version.board_version,
+        flight_git_hash: git_hash(&version.flight_custom_version),
+        middleware_git_hash: git_hash(&version.middleware_custom_version),
+        os_git_hash: git_hash(&version.os_custom_version),
+        vendor_id: version.vendor_id,
+        product_id: version.product_id,
+        uid: if version.uid2.iter().any(|byte| *byte != 0) {
+            version
+                .uid2
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        } else {
+            format!("{:016x}", version.uid)
+        },
+        capabilities: capability_names(version.capabilities),
+        capabilities_bits,
+    }
+}
+
+/// Latest AUTOPILOT_VERSION received from a component, decoded
+pub fn version(system_id: u8, component_id: u8) -> Option<Version> {
+    data::messages_with_time("AUTOPILOT_VERSION")
+        .into_iter()
+        .find_map(|(message, _, _)| match &message.message {
+            MavMessage::AUTOPILOT_VERSION(version)
+                if message.header.system_id == system_id
+                    && message.header.component_id == component_id =>
+            {
+                Some(decode(system_id, component_id, version))
+            }
+            _ => None,
+        })
+}
+
+/// Checks a MAV_PROTOCOL_CAPABILITY of a component,
+/// None if the component did not send AUTOPILOT_VERSION yet
+pub fn has_capability(
+    system_id: u8,
+    component_id: u8,
+    capability: MavProtocolCapability,
+) -> Option<bool> {
+    let bits = version(system_id, component_id)?.capabilities_bits;
+    Some(bits & capability.bits() == capability.bits())
+}
+
+/// Features are assumed to exist when the capabilities are unknown
+pub fn supports(system_id: u8, component_id: u8, capability: MavProtocolCapability) -> bool {
+    has_capability(system_id, component_id, capability).unwrap_or(true)
+}
+
+/// Requests AUTOPILOT_VERSION, falling back to MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES for older autopilots
+pub fn request(
+    vehicle: &MAVLinkVehicleArcMutex,
+    system_id: u8,
+    component_id: u8,
+) -> Result<Version, TransactionError> {
+    let matcher = Matcher {
+        name: "AUTOPILOT_VERSION".to_string(),
+        system_id: Some(system_id),
+        component_id: Some(component_id),
+        fields: None,
+    };
+    let id = MavMessage::message_id_from_name("AUTOPILOT_VERSION").unwrap_or_default();
+    // Older autopilots only know the deprecated command
+    #[allow(deprecated)]
+    let requests = [
+        (MavCmd::MAV_CMD_REQUEST_MESSAGE, id as f32),
+        (MavCmd::MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES, 1.0),
+    ];
+
+    let mut result = Err(TransactionError::Timeout);
+    for &(command, param1) in requests.iter() {
+        let request = MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+            param1,
+            command,
+            target_system: system_id,
+            target_component: component_id,
+            ..Default::default()
+        });
+        result = transaction::exchange(vehicle, &request, &matcher, 3, REQUEST_TIMEOUT);
+        if !matches!(result, Err(TransactionError::Timeout)) {
+            break;
+        }
+    }
+
+    match result?.message {
+        MavMessage::AUTOPILOT_VERSION(version) => Ok(decode(system_id, component_id, &version)),
+        _ => Err(TransactionError::Timeout),
+    }
+}
+
+/// Requests AUTOPILOT_VERSION of every autopilot that appears or comes back online,
+/// the request is repeated while it fails
+pub fn run(vehicle: &MAVLinkVehicleArcMutex, interval: Duration) {
+    let vehicle = vehicle.clone();
+    // Components whose request failed and when
+    let failed: Arc<Mutex<HashMap<(u8, u8), Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+    std::thread::spawn(move || {
+        let mut online: HashSet<(u8, u8)> = HashSet::new();
+        loop {
+            std::thread::sleep(interval);
+
+            let autopilots: HashSet<(u8, u8)> = inventory::inventory()
+                .into_iter()
+                .filter(|component| {
+                    component.online && component.autopilot != "MAV_AUTOPILOT_INVALID"
+                })
+                .map(|component| (component.system_id, component.component_id))
+                .collect();
+
+            let mut components: HashSet<(u8, u8)> =
+                autopilots.difference(&online).copied().collect();
+            failed.lock().unwrap().retain(|component, time| {
+                if !autopilots.contains(component) {
+                    return false;
+                }
+                if time.elapsed() < RETRY_INTERVAL {
+                    return true;
+                }
+                components.insert(*component);
+                false
+            });
+
+            for (system_id, component_id) in components {
+                let vehicle = vehicle.clone();
+                let failed = failed.clone();
+                std::thread::spawn(move || match request(&vehicle, system_id, component_id) {
+                    Ok(version) => info!(
+                        "Vehicle {system_id} component {component_id} firmware: {}",
+                        version
+                            .flight_sw_version
+                            .map_or_else(|| "unknown".to_string(), |version| version.text)
+                    ),
+                    Err(error) => {
+                        warn!(
+                                "Failed to request AUTOPILOT_VERSION of vehicle {system_id} component {component_id}: {error}"
+                            );
+                        failed
+                            .lock()
+                            .unwrap()
+                            .insert((system_id, component_id), Instant::now());
+                    }
+                });
+            }
+            online = autopilots;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn software_versions() {
+        assert!(software_version(0).is_none());
+
+        let version = software_version(0x0405_01FF).unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (4, 5, 1));
+        assert_eq!(version.release, "OFFICIAL");
+        assert_eq!(version.text, "4.5.1-official");
+
+        let release = |byte: u32| software_version(0x0100_0000 | byte).unwrap().release;
+        assert_eq!(release(0), "DEV");
+        assert_eq!(release(64), "ALPHA");
+        assert_eq!(release(191), "BETA");
+        assert_eq!(release(254), "RC");
+    }
+
+    #[test]
+    fn git_hashes() {
+        assert_eq!(git_hash(&[0; 8]), None);
+        assert_eq!(git_hash(b"9b5cd7ed").as_deref(), Some("9b5cd7ed"));
+        assert_eq!(git_hash(b"9b5cd7\0\0").as_deref(), Some("9b5cd7"));
+        assert_eq!(
+            git_hash(&[0xed, 0xd7, 0x5c, 0x9b, 0x00, 0x00, 0x00, 0x00]).as_deref(),
+            Some("000000009b5cd7ed")
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use serde_json::{json, Value};
+
+use crate::definitions::{self, EnumDefinition};
+use crate::modes::Firmware;
+
+// Flags can be serialized as {"bits": 81}, "FLAG_A | FLAG_B" or plain numbers
+fn flags_bits(value: &Value, enumeration: &EnumDefinition) -> Option<u64> {
+    match value {
+        Value::Object(object) => object.get("bits")?.as_u64(),
+        Value::Number(number) => number.as_u64(),
+        Value::String(names) => Some(
+            names
+                .split('|')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .filter_map(|name| match name.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                    None => enumeration.entry(name).map(|entry| entry.value),
+                })
+                .fold(0, |bits, value| bits | value),
+        ),
+        _ => None,
+    }
+}
+
+fn decode_flags(value: &Value, enumeration: &EnumDefinition) -> Option<Value> {
+    let bits = flags_bits(value, enumeration)?;
+    let flags: Vec<&str> = enumeration
+        .entries
+        .iter()
+        .filter(|entry| entry.value != 0 && bits & entry.value == entry.value)
+        .map(|entry| entry.name.as_str())
+        .collect();
+
+    Some(json!({
+        "bits": bits,
+        "flags": flags,
+    }))
+}
+
+fn decode_enum(value: &Value, enumeration: &EnumDefinition) -> Option<Value> {
+    // Enums are serialized as {"type": "NAME"}, but some fields are only numbers
+    let entry = match value {
+        Value::Object(object) => enumeration.entry(object.get("type")?.as_str()?)?,
+        Value::Number(number) => enumeration.entry_by_value(number.as_u64()?)?,
+        _ => return None,
+    };
+
+    Some(json!({
+        "type": entry.name,
+        "value": entry.value,
+        "description": entry.description,
+    }))
+}
+
+// The meaning of custom_mode depends on the autopilot and vehicle type of the same heartbeat
+fn decode_custom_mode(fields: &mut serde_json::Map<String, Value>) -> Option<()> {
+    let name = |field: &str| fields.get(field)?.get("type")?.as_str();
+    let firmware = Firmware::from_names(name("autopilot")?, name("mavtype")?)?;
+    let custom_mode = fields.get("custom_mode")?.as_u64()?;
+    let mode_name = firmware.mode_name(custom_mode as u32)?;
+
+    fields.insert(
+        "custom_mode".to_string(),
+        json!({
+            "value": custom_mode,
+            "name": mode_name,
+        }),
+    );
+    Some(())
+}
+
+/// Replaces enums and bitflags of the message with their names, values and descriptions
+pub fn decode(value: &mut Value) {
+    let message = match value.get_mut("message") {
+        Some(message) => message,
+        None => return,
+    };
+
+    let message_name = match message.get("type").and_then(Value::as_str) {
+        Some(name) => name.to_string(),
+        None => return,
+    };
+
+    let fields = match message.as_object_mut() {
+        Some(fields) => fields,
+        None => return,
+    };
+
+    for (field_name, field_value) in fields.iter_mut() {
+        let field = match definitions::field(&message_name, field_name) {
+            Some(field) => field,
+            None => continue,
+        };
+
+        let enumeration = match field
+            .enumeration
+            .as_deref()
+            .and_then(definitions::enumeration)
+        {
+            Some(enumeration) => enumeration,
+            None => continue,
+        };
+
+        let decoded = if field.bitmask || enumeration.bitmask {
+            decode_flags(field_value, enumeration)
+        } else {
+            decode_enum(field_value, enumeration)
+        };
+
+        if let Some(decoded) = decoded {
+            *field_value = decoded;
+        }
+    }
+
+    if message_name == "HEARTBEAT" {
+        decode_custom_mode(fields);
+    }
+}
+
+struct Conversion {
+    units: &'static str,
+    si_scale: f64,
+    si_offset: f64,
+    si_units: &'static str,
+    display_scale: f64,
+    display_units: &'static str,
+}
+
+const fn scale(
+    units: &'static str,
+    si_scale: f64,
+    si_units: &'static str,
+    display_scale: f64,
+    display_units: &'static str,
+) -> Conversion {
+    Conversion {
+        units,
+        si_scale,
+        si_offset: 0.0,
+        si_units,
+        display_scale,
+        display_units,
+    }
+}
+
+const DEG_TO_RAD: f64 = std::f64::consts::PI / 180.0;
+const RAD_TO_DEG: f64 = 180.0 / std::f64::consts::PI;
+const KELVIN_OFFSET: f64 = 273.15;
+
+// Units used by the MAVLink definitions, https://mavlink.io/en/guide/xml_schema.html#units
+static CONVERSIONS: &[Conversion] = &[
+    scale("degE7", DEG_TO_RAD * 1e-7, "rad", 1e-7, "deg"),
+    scale("degE5", DEG_TO_RAD * 1e-5, "rad", 1e-5, "deg"),
+    scale("cdeg", DEG_TO_RAD * 1e-2, "rad", 1e-2, "deg"),
+    scale("deg", DEG_TO_RAD, "rad", 1.0, "deg"),
+    scale("rad", 1.0, "rad", RAD_TO_DEG, "deg"),
+    scale("mrad", 1e-3, "rad", RAD_TO_DEG * 1e-3, "deg"),
+    scale("cdeg/s", DEG_TO_RAD * 1e-2, "rad/s", 1e-2, "deg/s"),
+    scale("deg/s", DEG_TO_RAD, "rad/s", 1.0, "deg/s"),
+    scale("rad/s", 1.0, "rad/s", RAD_TO_DEG, "deg/s"),
+    scale("mrad/s", 1e-3, "rad/s", RAD_TO_DEG * 1e-3, "deg/s"),
+    scale("rpm", std::f64::consts::PI / 30.0, "rad/s", 1.0, "rpm"),
+    scale("mm", 1e-3, "m", 1e-3, "m"),
+    scale("cm", 1e-2, "m", 1e-2, "m"),
+    scale("dm", 1e-1, "m", 1e-1, "m"),
+    scale("m", 1.0, "m", 1.0, "m"),
+    scale("mm/s", 1e-3, "m/s", 1e-3, "m/s"),
+    scale("cm/s", 1e-2, "m/s", 1e-2, "m/s"),
+    scale("dm/s", 1e-1, "m/s", 1e-1, "m/s"),
+    scale("m/s", 1.0, "m/s", 1.0, "m/s"),
+    scale("m/s/s", 1.0, "m/s/s", 1.0, "m/s/s"),
+    scale("mG", 9.80665e-3, "m/s/s", 1e-3, "g"),
+    scale("mV", 1e-3, "V", 1e-3, "V"),
+    scale("cV", 1e-2, "V", 1e-2, "V"),
+    scale("V", 1.0, "V", 1.0, "V"),
+    scale("mA", 1e-3, "A", 1e-3, "A"),
+    scale("cA", 1e-2, "A", 1e-2, "A"),
+    scale("A", 1.0, "A", 1.0, "A"),
+    scale("mAh", 3.6, "C", 1.0, "mAh"),
+    scale("hJ", 1e2, "J", 1e2, "J"),
+    scale("W", 1.0, "W", 1.0, "W"),
+    scale("us", 1e-6, "s", 1e-6, "s"),
+    scale("ms", 1e-3, "s", 1e-3, "s"),
+    scale("cs", 1e-2, "s", 1e-2, "s"),
+    scale("ds", 1e-1, "s", 1e-1, "s"),
+    scale("s", 1.0, "s", 1.0, "s"),
+    scale("Hz", 1.0, "Hz", 1.0, "Hz"),
+    scale("hPa", 1e2, "Pa", 1.0, "hPa"),
+    scale("kPa", 1e3, "Pa", 1e1, "hPa"),
+    scale("Pa", 1.0, "Pa", 1e-2, "hPa"),
+    scale("gauss", 1e-4, "T", 1.0, "gauss"),
+    scale("mgauss", 1e-7, "T", 1e-3, "gauss"),
+    scale("%", 1e-2, "1", 1.0, "%"),
+    scale("d%", 1e-3, "1", 1e-1, "%"),
+    scale("c%", 1e-4, "1", 1e-2, "%"),
+    Conversion {
+        units: "cdegC",
+        si_scale: 1e-2,
+        si_offset: KELVIN_OFFSET,
+        si_units: "K",
+        display_scale: 1e-2,
+        display_units: "degC",
+    },
+    Conversion {
+        units: "degC",
+        si_scale: 1.0,
+        si_offset: KELVIN_OFFSET,
+        si_units: "K",
+        display_scale: 1.0,
+        display_units: "degC",
+    },
+];
+
+// Applies the function over numbers and arrays of numbers
+fn convert(value: &Value, function: &dyn Fn(f64) -> f64) -> Option<Value> {
+    match value {
+        Value::Number(number) => Some(json!(function(number.as_f64()?))),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| convert(value, function))
+            .collect::<Option<Vec<Value>>>()
+            .map(Value::Array),
+        _ => None,
+    }
+}
+
+fn units_of_field(value: &Value, units: &str) -> Option<Value> {
+    let conversion = match CONVERSIONS
+        .iter()
+        .find(|conversion| conversion.units == units)
+    {
+        Some(conversion) => conversion,
+        None => {
+            // Keep the original units even when there is no known conversion
+            convert(value, &|value| value)?;
+            return Some(json!({ "units": units }));
+        }
+    };
+
+    let si = convert(value, &|value| {
+        value * conversion.si_scale + conversion.si_offset
+    })?;
+    let display = convert(value, &|value| value * conversion.display_scale)?;
+
+    Some(json!({
+        "units": units,
+        "si": {
+            "value": si,
+            "units": conversion.si_units,
+        },
+        "display": {
+            "value": display,
+            "units": conversion.display_units,
+        },
+    }))
+}
+
+/// Adds a "units" object next to the message, with SI and display values of each field that has units
+pub fn units(value: &mut Value) {
+    let message = match value.get("message") {
+        Some(message) => message,
+        None => return,
+    };
+
+    let message_name = match message.get("type").and_then(Value::as_str) {
+        Some(name) => name,
+        None => return,
+    };
+
+    let fields = match message.as_object() {
+        Some(fields) => fields,
+        None => return,
+    };
+
+    let units: serde_json::Map<String, Value> = fields
+        .iter()
+        .filter_map(|(field_name, field_value)| {
+            let units = definitions::field(message_name, field_name)?
+                .units
+                .as_deref()?;
+            Some((field_name.clone(), units_of_field(field_value, units)?))
+        })
+        .collect();
+
+    value["units"] = Value::Object(units);
+}
+
+/// Optional representations that can be requested by the clients
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Options {
+    pub decode: bool,
+    pub units: bool,
+}
+
+impl Options {
+    pub fn is_empty(&self) -> bool {
+        !self.decode && !self.units
+    }
+
+    pub fn apply(&self, value: &mut Value) {
+        // Units are calculated first, while the fields still have their raw values
+        if self.units {
+            units(value);
+        }
+        if self.decode {
+            decode(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mavlink::ardupilotmega::{
+        MavAutopilot, MavMessage, MavModeFlag, MavState, MavType, HEARTBEAT_DATA,
+    };
+
+    fn assert_close(value: &Value, expected: f64) {
+        assert!(
+            (value.as_f64().unwrap() - expected).abs() < 1e-9,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn units_conversion() {
+        let latitude = units_of_field(&json!(-225000000), "degE7").unwrap();
+        assert_close(&latitude["display"]["value"], -22.5);
+        assert_eq!(latitude["display"]["units"], "deg");
+        assert_eq!(latitude["si"]["units"], "rad");
+
+        let temperature = units_of_field(&json!(2500), "cdegC").unwrap();
+        assert_close(&temperature["si"]["value"], 298.15);
+        assert_close(&temperature["display"]["value"], 25.0);
+
+        let voltages = units_of_field(&json!([12000, 3700]), "mV").unwrap();
+        assert_close(&voltages["si"]["value"][0], 12.0);
+        assert_close(&voltages["si"]["value"][1], 3.7);
+
+        let unknown = units_of_field(&json!(3), "furlong").unwrap();
+        assert_eq!(unknown, json!({ "units": "furlong" }));
+
+        assert!(units_of_field(&json!("text"), "m").is_none());
+    }
+
+    #[test]
+    fn decode_heartbeat() {
+        let heartbeat = MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+            custom_mode: 4,
+            mavtype: MavType::MAV_TYPE_QUADROTOR,
+            autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+            base_mode: MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED
+                | MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED,
+            system_status: MavState::MAV_STATE_ACTIVE,
+            mavlink_version: 3,
+        });
+        let mut value = json!({ "message": heartbeat });
+        decode(&mut value);
+
+        let message = &value["message"];
+        assert_eq!(message["mavtype"]["type"], "MAV_TYPE_QUADROTOR");
+        assert_eq!(message["mavtype"]["value"], 2);
+        assert_eq!(message["system_status"]["type"], "MAV_STATE_ACTIVE");
+        assert_eq!(message["base_mode"]["bits"], 129);
+        let flags = message["base_mode"]["flags"].as_array().unwrap();
+        assert_eq!(flags.len(), 2);
+        assert!(flags.contains(&json!("MAV_MODE_FLAG_SAFETY_ARMED")));
+        assert!(flags.contains(&json!("MAV_MODE_FLAG_CUSTOM_MODE_ENABLED")));
+        assert_eq!(message["custom_mode"]["value"], 4);
+        assert_eq!(message["custom_mode"]["name"], "GUIDED");
+    }
+
+    #[test]
+    fn decode_flag_names() {
+        let enumeration = definitions::enumeration("MAV_MODE_FLAG").unwrap();
+        let bits = flags_bits(&json!("MAV_MODE_FLAG_SAFETY_ARMED | 0x1"), enumeration);
+        assert_eq!(bits, Some(129));
+        assert_eq!(flags_bits(&json!(64), enumeration), Some(64));
+        assert!(decode_enum(&json!(9999), enumeration).is_none());
+    }
+}
@@ -5,8 +5,12 @@ use lazy_static::lazy_static;
 use mavlink::Message as MavMessage;
 use regex::Regex;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
+use crate::data::Reception;
+use crate::views;
 use crate::MAVLinkMessage;
 
 pub struct StringMessage(String);
@@ -24,6 +28,9 @@ pub struct WebsocketError {
 pub struct WebsocketActorContent {
     pub actor: Addr<WebsocketActor>,
     pub re: Option<Regex>,
+    pub views: views::Options,
+    /// Number of messages waiting to be sent by the actor
+    pub pending: Arc<AtomicUsize>,
 }
 
 #[derive(Derivative, Default)]
@@ -42,11 +49,28 @@ impl WebsocketManager {
         }
 
         let string = serde_json::to_string_pretty(value).unwrap();
+        // Other views are only created if a client asks for it
+        let mut view_strings: HashMap<views::Options, String> = HashMap::new();
         for client in &self.clients {
-            let is_match = client.re.as_ref().map_or(false, |regx| regx.is_match(name));
-            if is_match {
-                client.actor.do_send(StringMessage(string.clone()));
+            let is_match = client.re.as_ref().is_some_and(|regx| regx.is_match(name));
+            if !is_match {
+                continue;
             }
+
+            let content = if client.views.is_empty() {
+                string.clone()
+            } else {
+                view_strings
+                    .entry(client.views)
+                    .or_insert_with(|| {
+                        let mut value = value.clone();
+                        client.views.apply(&mut value);
+                        serde_json::to_string_pretty(&value).unwrap()
+                    })
+                    .clone()
+            };
+            client.pending.fetch_add(1, Ordering::Relaxed);
+            client.actor.do_send(StringMessage(content));
         }
     }
 }
@@ -54,29 +78,86 @@ impl WebsocketManager {
 lazy_static! {
     static ref MANAGER: Arc<Mutex<WebsocketManager>> =
         Arc::new(Mutex::new(WebsocketManager::default()));
+    static ref EVENTS: Arc<Mutex<WebsocketManager>> =
+        Arc::new(Mutex::new(WebsocketManager::default()));
+    static ref GIMBALS: Arc<Mutex<WebsocketManager>> =
+        Arc::new(Mutex::new(WebsocketManager::default()));
 }
 
 pub fn manager() -> Arc<Mutex<WebsocketManager>> {
     MANAGER.clone()
 }
 
-pub fn send(message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>) {
+/// Manager of the gimbal websocket, its callback handles the gimbal controls
+pub fn gimbal_manager() -> Arc<Mutex<WebsocketManager>> {
+    GIMBALS.clone()
+}
+
+pub fn send(message: &MAVLinkMessage<mavlink::ardupilotmega::MavMessage>, reception: &Reception) {
     let name = message.message.message_name();
-    let value = serde_json::to_value(message).unwrap();
+    let mut value = serde_json::to_value(message).unwrap();
+    value["reception"] = serde_json::to_value(reception).unwrap();
     MANAGER.lock().unwrap().send(&value, name);
 }
 
+/// Sends an event of the service to the clients of the events websocket that match the topic
+pub fn send_event<T: Serialize>(topic: &str, data: &T) {
+    let value = serde_json::json!({ "topic": topic, "data": data });
+    EVENTS.lock().unwrap().send(&value, topic);
+}
+
+/// Sends a gimbal attitude to the clients of the gimbal websocket
+pub fn send_gimbal_attitude<T: Serialize>(attitude: &T) {
+    let value = serde_json::to_value(attitude).unwrap();
+    GIMBALS.lock().unwrap().send(&value, "gimbal_attitude");
+}
+
+/// Managers of the MAVLink, events and gimbal websockets, with the kind of their clients
+pub fn managers() -> [(&'static str, Arc<Mutex<WebsocketManager>>); 3] {
+    [
+        ("mavlink", MANAGER.clone()),
+        ("events", EVENTS.clone()),
+        ("gimbal", GIMBALS.clone()),
+    ]
+}
+
+pub fn event_clients() -> usize {
+    EVENTS.lock().unwrap().clients.len()
+}
+
 #[derive(Debug)]
 pub struct WebsocketActor {
     server: Arc<Mutex<WebsocketManager>>,
     pub filter: String,
+    pub views: views::Options,
+    pending: Arc<AtomicUsize>,
 }
 
 impl WebsocketActor {
-    pub fn new(message_filter: String) -> Self {
+    pub fn new(message_filter: String, views: views::Options) -> Self {
         Self {
             server: MANAGER.clone(),
             filter: message_filter,
+            views,
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn events(topic_filter: String) -> Self {
+        Self {
+            server: EVENTS.clone(),
+            filter: topic_filter,
+            views: views::Options::default(),
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn gimbals() -> Self {
+        Self {
+            server: GIMBALS.clone(),
+            filter: ".*".to_string(),
+            views: views::Options::default(),
+            pending: Arc::new(AtomicUsize::new(0)),
         }
     }
 }
@@ -85,6 +166,7 @@ impl Handler<StringMessage> for WebsocketActor {
     type Result = ();
 
     fn handle(&mut self, message: StringMessage, context: &mut Self::Context) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
         context.text(message.0);
     }
 }
@@ -103,6 +185,8 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
             .push(WebsocketActorContent {
                 actor: ctx.address(),
                 re: Regex::new(&self.filter).ok(),
+                views: self.views,
+                pending: self.pending.clone(),
             });
     }
 
@@ -35,6 +35,11 @@ def set_arm(arm: int):
     response = requests.post(f"{API}/mavlink", json=arm_message)
     return response.status_code == requests.codes.ok
 
+def send_command(command: str, **params) -> dict:
+    response = requests.post(f"{API}/vehicles/1/command", json={"command": command, **params})
+    assert(response.status_code == requests.codes.ok), f"Command failed: {response.text}"
+    return response.json()
+
 async def start_client(url: str, amount: int) -> None:
     ws = await aiohttp.ClientSession().ws_connect(url, autoclose=False, autoping=False)
 
@@ -87,9 +92,17 @@ assert(set_arm(1)), "Fail to send ARM command"
 time.sleep(1)
 assert((vehicle_mode() & 128) != 0), "Failed to ARM vehicle."
 
+print("Test command..")
+assert(send_command("MAV_CMD_COMPONENT_ARM_DISARM", param1=0)["result"] == "MAV_RESULT_ACCEPTED"), "DISARM command was not accepted."
+time.sleep(1)
+assert((vehicle_mode() & 128) == 0), "Vehicle appears to be ARMED."
+assert(send_command("MAV_CMD_COMPONENT_ARM_DISARM", param1=1)["result"] == "MAV_RESULT_ACCEPTED"), "ARM command was not accepted."
+time.sleep(1)
+assert((vehicle_mode() & 128) != 0), "Failed to ARM vehicle."
+
 print("Test pretty..")
 response = requests.get(f"{API}/mavlink/vehicles/1/components/1/messages/HEARTBEAT")
-assert(response.text.count('\n') == 26), "Pretty heartbeat does not look correct."
+assert(response.text.count('\n') == 36), "Pretty heartbeat does not look correct."
 
 async def test_websocket_fetch_filter():
     print("Test websocket..")